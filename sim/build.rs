@@ -0,0 +1,37 @@
+use std::f64::consts::PI;
+use std::fmt::Write;
+
+// generates the lookup tables used by the fixed-point math procedures
+fn main() {
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    let mut tables = String::new();
+
+    // quarter sine wave in Q1.15, index i is the angle i / 256 * pi / 2
+    let sin = (0..=256).map(|i| {
+        let x = (i as f64 / 256.0 * PI / 2.0).sin();
+        (x * 32768.0).round().min(32767.0) as u16
+    });
+
+    // atan(i / 256) for i in 0..=256, as a binary angle (0x10000 = full turn)
+    let atan = (0..=256).map(|i| {
+        let x = (i as f64 / 256.0).atan();
+        (x / PI * 32768.0).round() as u16
+    });
+
+    emit(&mut tables, "SIN_TABLE", sin);
+    emit(&mut tables, "ATAN_TABLE", atan);
+
+    std::fs::write(format!("{out_dir}/tables.rs"), tables).unwrap();
+    println!("cargo:rerun-if-changed=build.rs");
+}
+
+fn emit(out: &mut String, name: &str, values: impl Iterator<Item = u16>) {
+    let values: Vec<String> = values.map(|v| format!("{v:#06x}")).collect();
+    writeln!(out, "pub const {name}: [u16; {}] = [", values.len()).unwrap();
+
+    for line in values.chunks(8) {
+        writeln!(out, "    {},", line.join(", ")).unwrap();
+    }
+
+    writeln!(out, "];").unwrap();
+}
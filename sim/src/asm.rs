@@ -1,10 +1,40 @@
-use crate::isa::{AluOp, Cond, ControlOp, Inst, Reg, STACK_POINTER_TOP};
+use crate::isa::{
+    AluOp, Cond, ControlOp, Inst, Reg, INTERRUPT_ADDR, IO_PAGE, IO_PAGE_END, MMIO_START, MULDIV_A,
+    MULDIV_B, MULDIV_BASE, MULDIV_CTRL, MULDIV_DIV, MULDIV_HI, MULDIV_LO, MULDIV_MUL,
+    MULDIV_STATUS, STACK_END, STACK_POINTER_TOP,
+};
+use crate::START_PC;
 use std::collections::HashMap;
 
+// static variables without a fixed address are allocated in the zero page,
+// which can be addressed with an offset from Z
+const ZERO_PAGE_END: u16 = 128;
+
+struct StaticVar {
+    name: String,
+    words: u16,
+    addr: Option<u16>,
+}
+
 pub struct Assembler {
     output: Vec<u16>,
     labels: HashMap<String, usize>,
     unresolved_labels: Vec<(String, usize)>,
+    static_vars: Vec<StaticVar>,
+    // (variable, offset, instruction address) of loads and stores to variables
+    var_refs: Vec<(String, u8, usize)>,
+    // (label, instruction address) of every jump and lea
+    label_refs: Vec<(String, usize)>,
+    // start and end of the interrupt handler
+    interrupt_handler: Option<(usize, Option<usize>)>,
+    // multiplications and divisions use the coprocessor, see `use_muldiv`
+    muldiv: bool,
+}
+
+impl Default for Assembler {
+    fn default() -> Self {
+        Assembler::new()
+    }
 }
 
 impl Assembler {
@@ -13,35 +43,302 @@ impl Assembler {
             output: Vec::new(),
             labels: HashMap::new(),
             unresolved_labels: Vec::new(),
+            static_vars: Vec::new(),
+            var_refs: Vec::new(),
+            label_refs: Vec::new(),
+            interrupt_handler: None,
+            muldiv: cfg!(feature = "muldiv"),
         }
     }
 
-    fn get_relative_offset(label_addr: usize, inst_addr: usize) -> i8 {
+    // whether `muli`, `mul` and `divu` use the multiply/divide coprocessor
+    // (see muldiv.rs) instead of shifts, adds and subtractions.
+    // Off by default unless the simulator is built with the muldiv feature:
+    // only the simulator has the coprocessor, its registers are plain RAM on the FPGA.
+    pub fn use_muldiv(&mut self, enabled: bool) -> &mut Self {
+        self.muldiv = enabled;
+        self
+    }
+
+    // jumps load the offset with `set`, so it has to fit in 11 bits
+    fn get_relative_offset(label_addr: usize, inst_addr: usize) -> i16 {
         let offset = label_addr as i32 - inst_addr as i32 - 1;
 
         assert!(
-            offset >= -128 && offset <= 127,
-            "get_relative_offset: label is too far away (max 127 instructions)"
+            (-0x7ff..=0x7ff).contains(&offset),
+            "get_relative_offset: label is too far away (max 2047 instructions)"
         );
 
-        offset as i8
+        offset as i16
     }
 
     fn push_inst(&mut self, inst: Inst) {
         self.output.push(inst.into());
     }
 
+    // number of instructions emitted so far
+    pub fn len(&self) -> usize {
+        self.output.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.output.is_empty()
+    }
+
+    // offset of a label from the start of the program
+    pub fn label_address(&self, label: &str) -> Option<usize> {
+        self.labels.get(label).copied()
+    }
+
     pub fn init_sp(&mut self) -> &mut Self {
         self.setw(Reg::SP, STACK_POINTER_TOP, Reg::TMP)
     }
 
+    // declares a static variable of `words` words, allocated in the zero page
+    // when the program is assembled. Declaring a variable again with the same
+    // size refers to the same variable, so that procedures sharing state can be
+    // defined independently.
+    pub fn static_var(&mut self, name: &str, words: u16) -> &mut Self {
+        self.declare_var(name, words, None)
+    }
+
+    // declares a static variable at a fixed address, for buffers too large for
+    // the zero page. Its range is still checked against the other variables.
+    pub fn static_var_at(&mut self, name: &str, addr: u16, words: u16) -> &mut Self {
+        self.declare_var(name, words, Some(addr))
+    }
+
+    fn declare_var(&mut self, name: &str, words: u16, addr: Option<u16>) -> &mut Self {
+        assert!(words > 0, "static_var: {name} is empty");
+
+        if let Some(var) = self.static_vars.iter().find(|var| var.name == name) {
+            assert!(
+                var.words == words && var.addr == addr,
+                "static_var: {name} is already declared with a different layout"
+            );
+        } else {
+            self.static_vars.push(StaticVar {
+                name: name.to_string(),
+                words,
+                addr,
+            });
+        }
+
+        self
+    }
+
+    // assigns an address to every static variable and checks that they don't
+    // overlap with each other, the stack, the I/O page, the program loaded at
+    // `start`, the interrupt vector or the MMIO ports
+    fn static_layout(&self, start: u16) -> Vec<(&str, u16, u16)> {
+        let program = start as u32..start as u32 + self.output.len() as u32;
+        let mut layout: Vec<(&str, u16, u16)> = self
+            .static_vars
+            .iter()
+            .filter_map(|var| Some((var.name.as_str(), var.addr?, var.words)))
+            .collect();
+
+        for &(name, addr, words) in &layout {
+            let end = addr as u32 + words as u32;
+
+            assert!(
+                end <= STACK_POINTER_TOP as u32 || addr >= STACK_END,
+                "static_var: {name} overlaps with the stack"
+            );
+            assert!(
+                end <= IO_PAGE as u32 || addr >= IO_PAGE_END,
+                "static_var: {name} overlaps with the I/O page"
+            );
+            assert!(
+                end <= program.start || addr as u32 >= program.end,
+                "static_var: {name} overlaps with the program"
+            );
+            assert!(
+                end <= MMIO_START as u32,
+                "static_var: {name} overlaps with the MMIO ports"
+            );
+            assert!(
+                end <= INTERRUPT_ADDR as u32,
+                "static_var: {name} overlaps with the interrupt vector"
+            );
+        }
+
+        for (i, &(name, addr, words)) in layout.iter().enumerate() {
+            for &(other, other_addr, other_words) in &layout[..i] {
+                assert!(
+                    addr + words <= other_addr || other_addr + other_words <= addr,
+                    "static_var: {name} overlaps with {other}"
+                );
+            }
+        }
+
+        // first fit in the zero page, around the variables with a fixed address
+        let mut next = 0;
+
+        for var in self.static_vars.iter().filter(|var| var.addr.is_none()) {
+            let mut addr = next;
+
+            while let Some(&(_, other_addr, other_words)) = layout
+                .iter()
+                .find(|&&(_, a, w)| addr < a + w && a < addr + var.words)
+            {
+                addr = other_addr + other_words;
+            }
+
+            assert!(
+                addr + var.words <= ZERO_PAGE_END,
+                "static_var: no room left in the zero page for {}",
+                var.name
+            );
+
+            layout.push((&var.name, addr, var.words));
+            next = addr + var.words;
+        }
+
+        layout
+    }
+
+    // the address of a variable of the program loaded at START_PC
+    pub fn var_address(&self, name: &str) -> Option<u16> {
+        self.static_layout(START_PC)
+            .into_iter()
+            .find(|&(var, _, _)| var == name)
+            .map(|(_, addr, _)| addr)
+    }
+
+    // labels (relative to `start`, the address the program is loaded at)
+    // and static variables, sorted by address
+    pub fn symbols(&self, start: u16) -> Vec<(u16, String)> {
+        let mut symbols: Vec<(u16, String)> = self
+            .labels
+            .iter()
+            .map(|(label, &addr)| (start + addr as u16, label.clone()))
+            .chain(
+                self.static_layout(start)
+                    .into_iter()
+                    .map(|(name, addr, _)| (addr, name.to_string())),
+            )
+            .collect();
+
+        symbols.sort();
+        symbols
+    }
+
+    // starts the interrupt handler, which must end with `restore`.
+    // The CPU backs up all the registers and flags before jumping to the
+    // handler, so it is free to clobber them.
+    pub fn interrupt_handler(&mut self, label: &str) -> &mut Self {
+        assert!(
+            self.interrupt_handler.is_none(),
+            "interrupt_handler: there is a single interrupt vector"
+        );
+
+        self.interrupt_handler = Some((self.output.len(), None));
+        self.label(label)
+    }
+
+    pub fn end_interrupt_handler(&mut self) -> &mut Self {
+        let end = self.output.len();
+
+        match &mut self.interrupt_handler {
+            Some((start, handler_end @ None)) => {
+                let last = (end > *start).then(|| Inst::from(self.output[end - 1]));
+                assert!(
+                    matches!(
+                        last,
+                        Some(Inst::Ctl {
+                            op: ControlOp::Restore
+                        })
+                    ),
+                    "end_interrupt_handler: the handler must end with restore"
+                );
+
+                *handler_end = Some(end);
+            }
+            _ => panic!("end_interrupt_handler: no interrupt handler started"),
+        }
+
+        self
+    }
+
+    // assembles the program for the address it is loaded at, padding it up to
+    // the interrupt vector if there is an interrupt handler. Handlers fitting
+    // below the MMIO ports which don't jump outside of themselves are copied
+    // to the vector, otherwise the vector jumps to the handler.
+    pub fn assemble_at(&self, start: u16) -> Vec<u16> {
+        let mut out = self.link(start);
+
+        let Some((handler_start, handler_end)) = self.interrupt_handler else {
+            return out;
+        };
+
+        let handler_end = handler_end.expect("assemble_at: the interrupt handler is not ended");
+        let vector = INTERRUPT_ADDR.checked_sub(start).map(usize::from);
+        let vector = vector
+            .filter(|&vector| out.len() <= vector)
+            .expect("assemble_at: the program overlaps with the interrupt vector");
+
+        let handler = handler_start..handler_end;
+        let self_contained = self
+            .label_refs
+            .iter()
+            .filter(|(_, inst_addr)| handler.contains(inst_addr))
+            .all(|(label, _)| handler.contains(&self.labels[label]));
+        let vector_len = (MMIO_START - INTERRUPT_ADDR) as usize;
+
+        out.resize(vector, 0);
+
+        if self_contained && handler.len() <= vector_len {
+            out.extend_from_within(handler);
+        } else {
+            let handler_addr = start + handler_start as u16;
+            let mut trampoline = Assembler::new();
+
+            // the offset is relative to the add instruction, after the setw
+            // which is at most 5 instructions long
+            let offset = handler_addr.wrapping_sub(INTERRUPT_ADDR + 5);
+            trampoline.setw(Reg::R1, offset, Reg::TMP);
+
+            while trampoline.len() < 5 {
+                trampoline.nop();
+            }
+
+            trampoline.add(Reg::PC, Reg::PC, Reg::R1);
+            out.extend(trampoline.assemble());
+        }
+
+        out
+    }
+
+    // assembles the program for START_PC, see `assemble_at` for the interrupt vector
     pub fn assemble(&self) -> Vec<u16> {
+        self.link(START_PC)
+    }
+
+    // resolves the labels and the static variables of the program loaded at `start`
+    fn link(&self, start: u16) -> Vec<u16> {
         let mut out = self.output.clone();
+        let layout = self.static_layout(start);
+
+        for (name, offset, inst_addr) in &self.var_refs {
+            let &(_, addr, words) = layout
+                .iter()
+                .find(|&&(var, _, _)| var == name)
+                .unwrap_or_else(|| panic!("undeclared static variable: {name}"));
+
+            assert!(
+                *offset < words as u8,
+                "{name}: offset {offset} is out of bounds"
+            );
+            let addr = addr + *offset as u16;
+            assert!(addr < ZERO_PAGE_END, "{name} is not in the zero page");
+            out[*inst_addr] |= addr;
+        }
 
         for (label, inst_addr) in &self.unresolved_labels {
             if let Some(&label_addr) = self.labels.get(label) {
                 let relative_offset = Self::get_relative_offset(label_addr, *inst_addr);
-                out[*inst_addr] |= (relative_offset as u16) & 0x7f;
+                out[*inst_addr] |= relative_offset.unsigned_abs() & 0x7ff;
             } else {
                 panic!("unresolved label: {label}");
             }
@@ -214,6 +511,19 @@ impl Assembler {
             return self.mov(dst, Reg::Z);
         }
 
+        // the coprocessor takes about as many cycles as 5 shifts and adds
+        if self.muldiv && n <= 0x3ff && n.count_ones() > 5 {
+            assert!(src != tmp, "muli: src == tmp");
+
+            return self
+                .setw(dst, MULDIV_BASE, tmp)
+                .store(src, dst, MULDIV_A as u8)
+                .set(tmp, n)
+                .store(tmp, dst, MULDIV_B as u8)
+                .run_muldiv(dst, MULDIV_MUL, tmp)
+                .load(dst, dst, MULDIV_LO as u8);
+        }
+
         if n.is_power_of_two() {
             let log2 = (n as f32).log2() as u16;
             self.set(tmp, log2);
@@ -237,6 +547,91 @@ impl Assembler {
         self
     }
 
+    // starts an operation on the coprocessor whose operands have been stored,
+    // and waits for it. `base` holds MULDIV_BASE, `tmp` is clobbered.
+    fn run_muldiv(&mut self, base: Reg, op: u16, tmp: Reg) -> &mut Self {
+        self.set(tmp, op).store(tmp, base, MULDIV_CTRL as u8);
+
+        let wait_addr = self.output.len();
+        self.load(tmp, base, MULDIV_STATUS as u8).update_flags(tmp);
+
+        // like jmp_if_rel, but the offset goes in tmp too
+        let offset = Self::get_relative_offset(wait_addr, self.output.len());
+        self.set(tmp, offset.unsigned_abs())
+            .sub_if(Reg::PC, Reg::PC, tmp, Cond::IfNotZero)
+    }
+
+    // dst = a * b (low word), clobbers b
+    pub fn mul(&mut self, dst: Reg, a: Reg, b: Reg) -> &mut Self {
+        assert!(dst != a && dst != b && a != b, "mul: registers must differ");
+        assert!(![dst, a, b].contains(&Reg::TMP), "mul: TMP is clobbered");
+
+        if self.muldiv {
+            return self
+                .setw(dst, MULDIV_BASE, Reg::TMP)
+                .store(a, dst, MULDIV_A as u8)
+                .store(b, dst, MULDIV_B as u8)
+                .run_muldiv(dst, MULDIV_MUL, Reg::TMP)
+                .load(dst, dst, MULDIV_LO as u8);
+        }
+
+        // shift-and-add, starting from the msb of b
+        self.set(dst, 0);
+
+        for _ in 0..16 {
+            self.add(dst, dst, dst)
+                .add(b, b, b) // carry = next bit of b
+                .add_if(dst, dst, a, Cond::IfCarry);
+        }
+
+        self
+    }
+
+    // dst -> a / b, a -> a % b (unsigned)
+    // division by zero yields a quotient of 0xffff and leaves a untouched
+    pub fn divu(&mut self, dst: Reg, a: Reg, b: Reg) -> &mut Self {
+        assert!(
+            dst != a && dst != b && a != b,
+            "divu: registers must differ"
+        );
+        assert!(![dst, a, b].contains(&Reg::TMP), "divu: TMP is clobbered");
+
+        if self.muldiv {
+            return self
+                .setw(dst, MULDIV_BASE, Reg::TMP)
+                .store(a, dst, MULDIV_A as u8)
+                .store(b, dst, MULDIV_B as u8)
+                .run_muldiv(dst, MULDIV_DIV, Reg::TMP)
+                .load(a, dst, MULDIV_HI as u8)
+                .load(dst, dst, MULDIV_LO as u8);
+        }
+
+        // shift-and-subtract like `divu16` (see arith.rs), the dividend is
+        // shifted out of dst as the quotient is shifted in
+        self.mov(dst, a).set(a, 0);
+
+        for _ in 0..16 {
+            self.add(dst, dst, dst).adc(a, a, a);
+            self.restoring_div_step(dst, a, b);
+        }
+
+        self
+    }
+
+    // one step of a restoring division: the remainder has just been shifted left
+    // and its 17th bit is in the carry flag. Subtracts the divisor from the
+    // remainder if it fits and shifts the quotient bit into the lsb of `quot`,
+    // which must be 0.
+    pub(crate) fn restoring_div_step(&mut self, quot: Reg, rem: Reg, divisor: Reg) -> &mut Self {
+        self.adc(Reg::TMP, Reg::Z, Reg::Z) // TMP = bit 16 of the remainder
+            // a 17-bit remainder is always larger than the divisor,
+            // and the wrapped difference is the correct new remainder
+            .sub_if(rem, rem, divisor, Cond::IfNotZero)
+            .cmp(rem, divisor)
+            .sub_if(rem, rem, divisor, Cond::IfCarry) // keeps the carry set
+            .adc(quot, quot, Reg::TMP) // TMP and carry are never both set
+    }
+
     pub fn muli(&mut self, dst: Reg, src: Reg, n: u16) -> &mut Self {
         self.muli2(dst, src, n, Reg::TMP)
     }
@@ -317,8 +712,8 @@ impl Assembler {
         self.alu(dst, src, Reg::Z, AluOp::Dec)
     }
 
-    fn jmp_if_rel(&mut self, relative_offset: i8, cond: Cond) -> &mut Self {
-        self.set(Reg::TMP, relative_offset.abs() as u16);
+    fn jmp_if_rel(&mut self, relative_offset: i16, cond: Cond) -> &mut Self {
+        self.set(Reg::TMP, relative_offset.unsigned_abs());
 
         if relative_offset < 0 {
             self.sub_if(Reg::PC, Reg::PC, Reg::TMP, cond)
@@ -329,6 +724,7 @@ impl Assembler {
 
     pub fn jmp_if(&mut self, label: &str, cond: Cond) -> &mut Self {
         let inst_addr = self.output.len();
+        self.label_refs.push((label.to_string(), inst_addr));
 
         if let Some(&label_addr) = self.labels.get(label) {
             let relative_offset = Self::get_relative_offset(label_addr, inst_addr);
@@ -339,6 +735,30 @@ impl Assembler {
         }
     }
 
+    // dst = address of label
+    pub fn lea(&mut self, dst: Reg, label: &str) -> &mut Self {
+        assert!(dst != Reg::PC, "lea: dst == pc");
+        let inst_addr = self.output.len();
+        self.label_refs.push((label.to_string(), inst_addr));
+
+        let relative_offset = match self.labels.get(label) {
+            Some(&label_addr) => Self::get_relative_offset(label_addr, inst_addr),
+            None => {
+                self.unresolved_labels.push((label.to_string(), inst_addr));
+                0 // placeholder
+            }
+        };
+
+        // same encoding as jumps: the offset is relative to the add instruction
+        self.set(dst, relative_offset.unsigned_abs());
+
+        if relative_offset < 0 {
+            self.sub(dst, Reg::PC, dst)
+        } else {
+            self.add(dst, Reg::PC, dst)
+        }
+    }
+
     pub fn jmp(&mut self, label: &str) -> &mut Self {
         self.jmp_if(label, Cond::Always)
     }
@@ -402,6 +822,27 @@ impl Assembler {
         self
     }
 
+    // `index` + the address of a static variable in the zero page + `offset`
+    pub fn load_var_at(&mut self, dst: Reg, index: Reg, var: &str, offset: u8) -> &mut Self {
+        self.var_refs
+            .push((var.to_string(), offset, self.output.len()));
+        self.load(dst, index, 0)
+    }
+
+    pub fn store_var_at(&mut self, src: Reg, index: Reg, var: &str, offset: u8) -> &mut Self {
+        self.var_refs
+            .push((var.to_string(), offset, self.output.len()));
+        self.store(src, index, 0)
+    }
+
+    pub fn load_var(&mut self, dst: Reg, var: &str) -> &mut Self {
+        self.load_var_at(dst, Reg::Z, var, 0)
+    }
+
+    pub fn store_var(&mut self, src: Reg, var: &str) -> &mut Self {
+        self.store_var_at(src, Reg::Z, var, 0)
+    }
+
     pub fn push(&mut self, val: Reg) -> &mut Self {
         self.store(val, Reg::SP, 0);
         self.inc(Reg::SP)
@@ -425,6 +866,12 @@ impl Assembler {
         self.jmp(procedure_label)
     }
 
+    // raw data words, which must not be reached by the program counter
+    pub fn data(&mut self, words: &[u16]) -> &mut Self {
+        self.output.extend_from_slice(words);
+        self
+    }
+
     pub fn label(&mut self, label: &str) -> &mut Self {
         assert!(
             !self.labels.contains_key(label),
@@ -435,3 +882,131 @@ impl Assembler {
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::CPU;
+    use crate::START_PC;
+
+    #[test]
+    fn test_static_var_layout() {
+        let mut asm = Assembler::new();
+
+        asm.static_var("a", 2)
+            .static_var_at("buf", 3, 4)
+            .static_var("b", 2)
+            .static_var("a", 2)
+            .static_var_at("screen", 0x6000, 0x100)
+            .set(Reg::R1, 7)
+            .store_var(Reg::R1, "b")
+            .store_var_at(Reg::R1, Reg::Z, "a", 1)
+            .load_var(Reg::R2, "b")
+            .halt();
+
+        assert_eq!(asm.var_address("a"), Some(0));
+        assert_eq!(asm.var_address("buf"), Some(3));
+        assert_eq!(asm.var_address("b"), Some(7));
+        assert_eq!(asm.var_address("screen"), Some(0x6000));
+        assert_eq!(asm.var_address("c"), None);
+
+        let mut cpu = CPU::from(&asm.assemble(), START_PC);
+        cpu.run();
+
+        assert_eq!(&cpu.ram[..8], &[0, 7, 0, 0, 0, 0, 0, 7]);
+        assert_eq!(cpu.regs[Reg::R2 as usize], 7);
+    }
+
+    #[test]
+    fn test_symbols() {
+        let mut asm = Assembler::new();
+        asm.static_var("n", 1).nop().label("start").halt();
+
+        assert_eq!(
+            asm.symbols(0x8000),
+            [(0, "n".to_string()), (0x8001, "start".to_string())]
+        );
+    }
+
+    fn assemble_vars(vars: &[(&str, Option<u16>, u16)]) {
+        let mut asm = Assembler::new();
+
+        for &(name, addr, words) in vars {
+            match addr {
+                Some(addr) => asm.static_var_at(name, addr, words),
+                None => asm.static_var(name, words),
+            };
+        }
+
+        asm.assemble();
+    }
+
+    #[test]
+    #[should_panic(expected = "b overlaps with a")]
+    fn test_static_var_overlap() {
+        assemble_vars(&[("a", Some(0x1000), 16), ("b", Some(0x100f), 1)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "overlaps with the stack")]
+    fn test_static_var_stack_overlap() {
+        assemble_vars(&[("a", Some(STACK_POINTER_TOP - 1), 2)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "overlaps with the I/O page")]
+    fn test_static_var_io_page_overlap() {
+        assemble_vars(&[("a", Some(IO_PAGE + 0x10), 1)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "buf overlaps with the program")]
+    fn test_static_var_program_overlap() {
+        let mut asm = Assembler::new();
+        asm.static_var_at("buf", START_PC + 1, 4).nop().nop().halt();
+        asm.assemble();
+    }
+
+    #[test]
+    #[should_panic(expected = "buf overlaps with the program")]
+    fn test_static_var_program_moved() {
+        let mut asm = Assembler::new();
+        asm.static_var_at("buf", 0x1000, 4).halt();
+
+        // fine at START_PC, not once loaded over the buffer
+        asm.assemble();
+        asm.assemble_at(0x1002);
+    }
+
+    #[test]
+    #[should_panic(expected = "overlaps with the interrupt vector")]
+    fn test_static_var_vector_overlap() {
+        assemble_vars(&[("a", Some(INTERRUPT_ADDR + 4), 2)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "overlaps with the MMIO ports")]
+    fn test_static_var_mmio_overlap() {
+        assemble_vars(&[("a", Some(0xfff0), 15)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "no room left in the zero page for b")]
+    fn test_zero_page_full() {
+        assemble_vars(&[("a", None, 100), ("b", None, 30)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "already declared")]
+    fn test_static_var_redeclared() {
+        assemble_vars(&[("a", None, 1), ("a", None, 2)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "not in the zero page")]
+    fn test_load_var_outside_zero_page() {
+        let mut asm = Assembler::new();
+        asm.static_var_at("a", 0x1000, 1).load_var(Reg::R1, "a");
+        asm.assemble();
+    }
+}
@@ -0,0 +1,206 @@
+use crate::bus::{Device, Machine};
+use crate::isa::{CLOCK_HZ, IO_PAGE};
+use std::io;
+
+pub const BEEPER_BASE: u16 = IO_PAGE + 0x50;
+pub const BEEPER_END: u16 = BEEPER_BASE + 2;
+
+// registers, relative to BEEPER_BASE
+pub const BEEPER_CTRL: u16 = 0;
+// the period of the tone in units of DIVIDER_CYCLES, 0 is silent
+pub const BEEPER_DIVIDER: u16 = 1;
+// the part of the period the output is high, out of 256
+pub const BEEPER_DUTY: u16 = 2;
+
+// control bits
+pub const BEEPER_ENABLE: u16 = 1 << 0;
+
+// from 6 Hz (divider 0xffff) to 390 kHz (divider 1)
+pub const DIVIDER_CYCLES: u64 = 256;
+pub const SAMPLE_RATE: u64 = 44_100;
+
+// 8-bit unsigned samples
+const SILENCE: u8 = 0x80;
+const AMPLITUDE: u8 = 0x40;
+
+// the divider of a tone, rounded to the nearest
+pub fn divider(frequency_hz: u64) -> u16 {
+    ((CLOCK_HZ + frequency_hz * DIVIDER_CYCLES / 2) / (frequency_hz * DIVIDER_CYCLES)) as u16
+}
+
+// A square-wave tone generator. Its output is sampled at SAMPLE_RATE against
+// the cycles of the CPU, and recorded for `to_wav`.
+pub struct Beeper {
+    ctrl: u16,
+    divider: u16,
+    duty: u16,
+    // the cycle the current tone started at
+    phase_start: u64,
+    cycles: u64,
+    samples: Vec<u8>,
+}
+
+impl Beeper {
+    pub fn new() -> Self {
+        Beeper {
+            ctrl: 0,
+            divider: 0,
+            duty: 128,
+            phase_start: 0,
+            cycles: 0,
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn samples(&self) -> &[u8] {
+        &self.samples
+    }
+
+    fn output(&self, cycle: u64) -> u8 {
+        if self.ctrl & BEEPER_ENABLE == 0 || self.divider == 0 {
+            return SILENCE;
+        }
+
+        let period = self.divider as u64 * DIVIDER_CYCLES;
+        let high = period * self.duty as u64 / 256;
+
+        if (cycle - self.phase_start) % period < high {
+            SILENCE + AMPLITUDE
+        } else {
+            SILENCE - AMPLITUDE
+        }
+    }
+
+    // a mono, 8-bit PCM WAV file of everything played
+    pub fn to_wav(&self) -> Vec<u8> {
+        let len = self.samples.len() as u32;
+        let mut wav = Vec::with_capacity(44 + self.samples.len());
+
+        wav.extend(b"RIFF");
+        wav.extend((36 + len).to_le_bytes());
+        wav.extend(b"WAVEfmt ");
+        wav.extend(16u32.to_le_bytes());
+        wav.extend(1u16.to_le_bytes()); // PCM
+        wav.extend(1u16.to_le_bytes()); // channels
+        wav.extend((SAMPLE_RATE as u32).to_le_bytes());
+        wav.extend((SAMPLE_RATE as u32).to_le_bytes()); // bytes per second
+        wav.extend(1u16.to_le_bytes()); // bytes per frame
+        wav.extend(8u16.to_le_bytes()); // bits per sample
+        wav.extend(b"data");
+        wav.extend(len.to_le_bytes());
+        wav.extend(&self.samples);
+
+        wav
+    }
+
+    pub fn save_wav(&self, path: &str) -> io::Result<()> {
+        std::fs::write(path, self.to_wav())
+    }
+}
+
+impl Default for Beeper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Device for Beeper {
+    fn read(&mut self, offset: u16) -> u16 {
+        match offset {
+            BEEPER_CTRL => self.ctrl,
+            BEEPER_DIVIDER => self.divider,
+            BEEPER_DUTY => self.duty,
+            _ => 0,
+        }
+    }
+
+    // changing the tone restarts its period
+    fn write(&mut self, offset: u16, val: u16) {
+        match offset {
+            BEEPER_CTRL => self.ctrl = val & BEEPER_ENABLE,
+            BEEPER_DIVIDER => self.divider = val,
+            BEEPER_DUTY => self.duty = val.min(256),
+            _ => {}
+        }
+
+        self.phase_start = self.cycles;
+    }
+
+    fn tick(&mut self, cycles: u64, _machine: &mut Machine) {
+        let end = self.cycles + cycles;
+
+        // the sample n is taken at cycle n * CLOCK_HZ / SAMPLE_RATE
+        loop {
+            let n = self.samples.len() as u64;
+            let cycle = (n * CLOCK_HZ).div_ceil(SAMPLE_RATE);
+
+            if cycle >= end {
+                break;
+            }
+
+            self.samples.push(self.output(cycle));
+        }
+
+        self.cycles = end;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::tick;
+
+    const HIGH: u8 = SILENCE + AMPLITUDE;
+    const LOW: u8 = SILENCE - AMPLITUDE;
+
+    #[test]
+    fn test_divider() {
+        assert_eq!(divider(440), 888);
+        assert_eq!(divider(1000), 391);
+    }
+
+    #[test]
+    fn test_square_wave() {
+        let mut beeper = Beeper::new();
+        tick(&mut beeper, CLOCK_HZ / 100);
+        assert_eq!(beeper.samples().len(), 441);
+        assert!(beeper.samples().iter().all(|&s| s == SILENCE));
+
+        // 11160 Hz: about 4 samples per period
+        let mut beeper = Beeper::new();
+        beeper.write(BEEPER_DIVIDER, 35);
+        beeper.write(BEEPER_CTRL, BEEPER_ENABLE);
+        tick(&mut beeper, 8 * CLOCK_HZ / SAMPLE_RATE);
+
+        assert_eq!(
+            beeper.samples(),
+            [HIGH, HIGH, LOW, LOW, HIGH, HIGH, LOW, LOW]
+        );
+    }
+
+    #[test]
+    fn test_duty() {
+        let mut beeper = Beeper::new();
+        beeper.write(BEEPER_DIVIDER, divider(440));
+        beeper.write(BEEPER_DUTY, 64);
+        beeper.write(BEEPER_CTRL, BEEPER_ENABLE);
+        tick(&mut beeper, CLOCK_HZ / 10);
+
+        // a quarter of the samples are high
+        let high = beeper.samples().iter().filter(|&&s| s == HIGH).count();
+        assert!((1080..1125).contains(&high), "{high}");
+    }
+
+    #[test]
+    fn test_wav() {
+        let mut beeper = Beeper::new();
+        tick(&mut beeper, CLOCK_HZ / 100);
+
+        let wav = beeper.to_wav();
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(wav.len(), 44 + 441);
+        assert_eq!(u32::from_le_bytes(wav[40..44].try_into().unwrap()), 441);
+    }
+}
@@ -0,0 +1,437 @@
+use std::io::{self, Read, Write};
+
+use crate::isa::Reg;
+use crate::ppu::Ppu;
+use crate::sim::CPU;
+use crate::upload::Receiver;
+use crate::START_PC;
+
+// selected by sw[0] and btn_l in Top.veryl
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    // the CPU runs, the LEDs show the PC and the display {R1, R2}
+    Execute,
+    // RAM is written from the UART, the LEDs and the display show the program address
+    Program,
+    // the CPU is paused, the LEDs show the switches and the display the RAM at that address
+    Inspect,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    // btn_l, toggles the inspect mode on every press
+    Left,
+    // btn_r, resets the board while held
+    Right,
+}
+
+// the RGB LED (led16)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StatusLed {
+    pub red: bool,
+    pub green: bool,
+    pub blue: bool,
+}
+
+// The Nexys A7 running Top.veryl around a simulated CPU:
+// the inputs are driven with the methods below, the outputs are computed
+// from the state of the board whenever they are read.
+pub struct Board {
+    pub cpu: CPU,
+    switches: u16,
+    btn_l: bool,
+    btn_r: bool,
+    inspect: bool,
+    prog_addr: u16,
+    // the first byte of the word being received in program mode
+    prog_high_byte: Option<u8>,
+    // replaces the byte stream of the program mode, see `Board::framed`
+    receiver: Option<Receiver>,
+}
+
+impl Board {
+    pub fn new(cpu: CPU) -> Self {
+        Board {
+            cpu,
+            switches: 0,
+            btn_l: false,
+            btn_r: false,
+            inspect: false,
+            prog_addr: 0,
+            prog_high_byte: None,
+            receiver: None,
+        }
+    }
+
+    // A board whose program mode takes the framed protocol of upload.rs
+    // instead of the byte stream of Top.veryl, which has no framed receiver:
+    // this only exists in the simulator, the program address stays at 0.
+    pub fn framed(cpu: CPU) -> Self {
+        Board {
+            receiver: Some(Receiver::new()),
+            ..Board::new(cpu)
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        if self.inspect {
+            Mode::Inspect
+        } else if self.switches & 1 != 0 {
+            Mode::Program
+        } else {
+            Mode::Execute
+        }
+    }
+
+    pub fn set_switch(&mut self, index: usize, on: bool) {
+        assert!(index < 16, "set_switch: there are 16 switches");
+
+        if on {
+            self.switches |= 1 << index;
+        } else {
+            self.switches &= !(1 << index);
+        }
+    }
+
+    pub fn set_switches(&mut self, switches: u16) {
+        self.switches = switches;
+    }
+
+    pub fn switches(&self) -> u16 {
+        self.switches
+    }
+
+    pub fn press(&mut self, button: Button) {
+        match button {
+            // PosEdgeDetector: holding the button toggles the mode once
+            Button::Left if !self.btn_l => {
+                self.btn_l = true;
+                self.inspect = !self.inspect;
+            }
+            Button::Left => {}
+            Button::Right => {
+                self.btn_r = true;
+                self.reset();
+            }
+        }
+    }
+
+    pub fn release(&mut self, button: Button) {
+        match button {
+            Button::Left => self.btn_l = false,
+            Button::Right => self.btn_r = false,
+        }
+    }
+
+    // press and release
+    pub fn click(&mut self, button: Button) {
+        self.press(button);
+        self.release(button);
+    }
+
+    // the RAM and the name table survive a reset, the name table address does not
+    fn reset(&mut self) {
+        self.cpu.reset(START_PC);
+        self.inspect = false;
+        self.prog_addr = 0;
+        self.prog_high_byte = None;
+
+        if let Some(receiver) = &mut self.receiver {
+            *receiver = Receiver::new();
+        }
+
+        if let Some(ppu) = self.cpu.bus.device_mut::<Ppu>("ppu") {
+            ppu.tile_index = 0;
+        }
+    }
+
+    // A byte received by the UART: in program mode, every other byte completes
+    // a word which is written at the program address, until it reaches 0xffff.
+    // Bytes received in other modes are dropped.
+    // Returns the bytes sent back, only the framed receiver replies.
+    pub fn receive(&mut self, byte: u8) -> Vec<u8> {
+        if self.mode() != Mode::Program || self.prog_addr == 0xffff {
+            return Vec::new();
+        }
+
+        if let Some(receiver) = &mut self.receiver {
+            return receiver.receive(byte, &mut self.cpu.ram[..]);
+        }
+
+        match self.prog_high_byte.take() {
+            None => self.prog_high_byte = Some(byte),
+            Some(high) => {
+                self.cpu.ram[self.prog_addr as usize] = u16::from_be_bytes([high, byte]);
+                self.prog_addr += 1;
+            }
+        }
+
+        Vec::new()
+    }
+
+    // receives bytes until the end of `port` or until the program address
+    // reaches 0xffff, sending the replies back, returns the number of bytes read.
+    // The end of a pty is reached once its other end is closed.
+    pub fn receive_from<P: Read + Write>(&mut self, port: &mut P) -> io::Result<usize> {
+        let mut buffer = [0; 256];
+        let mut count = 0;
+
+        while self.prog_addr != 0xffff {
+            let len = match port.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(len) => len,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) if err.raw_os_error() == Some(libc::EIO) => break,
+                Err(err) => return Err(err),
+            };
+
+            for &byte in &buffer[..len] {
+                let reply = self.receive(byte);
+                port.write_all(&reply)?;
+            }
+
+            count += len;
+        }
+
+        Ok(count)
+    }
+
+    pub fn prog_addr(&self) -> u16 {
+        self.prog_addr
+    }
+
+    // the CPU clock is gated outside of the execute mode and during a reset
+    pub fn step(&mut self) {
+        if self.mode() == Mode::Execute && !self.btn_r {
+            self.cpu.step();
+        }
+    }
+
+    // steps until the CPU halts, returns false if it did not within `fuel` steps
+    pub fn run(&mut self, fuel: usize) -> bool {
+        for _ in 0..fuel {
+            if self.cpu.halted {
+                return true;
+            }
+
+            self.step();
+        }
+
+        self.cpu.halted
+    }
+
+    pub fn leds(&self) -> u16 {
+        match self.mode() {
+            Mode::Execute => self.cpu.regs[Reg::PC as usize],
+            Mode::Program => self.prog_addr,
+            Mode::Inspect => self.switches,
+        }
+    }
+
+    // w_status is wired as {blue, green, red}: the inspect mode sets the
+    // bit Top.veryl labels as white, which only lights the red LED
+    pub fn status_led(&self) -> StatusLed {
+        match self.mode() {
+            Mode::Execute => StatusLed::default(),
+            Mode::Program => StatusLed {
+                green: self.prog_addr == 0xffff,
+                blue: self.prog_addr != 0xffff,
+                ..StatusLed::default()
+            },
+            Mode::Inspect => StatusLed {
+                red: true,
+                ..StatusLed::default()
+            },
+        }
+    }
+
+    // the 32-bit value shown on the seven-segment display
+    pub fn display(&self) -> u32 {
+        match self.mode() {
+            Mode::Execute => {
+                (self.cpu.regs[Reg::R1 as usize] as u32) << 16
+                    | self.cpu.regs[Reg::R2 as usize] as u32
+            }
+            Mode::Program => self.prog_addr as u32,
+            Mode::Inspect => self.cpu.ram[self.switches as usize] as u32,
+        }
+    }
+
+    // segments of every digit (active low, bit 0 is segment a),
+    // digit 0 is the rightmost one, as driven by SevenSegment.veryl
+    pub fn seven_segment(&self) -> [u8; 8] {
+        let value = self.display();
+        std::array::from_fn(|digit| SEGMENTS[(value >> (4 * digit)) as usize & 0xf])
+    }
+}
+
+const SEGMENTS: [u8; 16] = [
+    0b1000000, 0b1111001, 0b0100100, 0b0110000, 0b0011001, 0b0010010, 0b0000010, 0b1111000,
+    0b0000000, 0b0010000, 0b0001000, 0b0000011, 0b1000110, 0b0100001, 0b0000110, 0b0001110,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::Assembler;
+    use crate::upload::{frame, upload_stream, ACK, WRITE};
+    use Reg::*;
+
+    fn board() -> Board {
+        let mut asm = Assembler::new();
+        asm.setw(R1, 0x1234, TMP)
+            .setw(R2, 0xabcd, TMP)
+            .set(R3, 0x42)
+            .store(R3, Z, 0x10)
+            .halt();
+
+        Board::new(CPU::from(&asm.assemble(), START_PC))
+    }
+
+    #[test]
+    fn test_modes() {
+        let mut board = board();
+        assert_eq!(board.mode(), Mode::Execute);
+
+        board.set_switch(0, true);
+        assert_eq!(board.mode(), Mode::Program);
+        assert_eq!(
+            board.status_led(),
+            StatusLed {
+                blue: true,
+                ..StatusLed::default()
+            }
+        );
+
+        // the inspect mode takes precedence over sw[0]
+        board.press(Button::Left);
+        board.press(Button::Left);
+        assert_eq!(board.mode(), Mode::Inspect);
+        board.release(Button::Left);
+        board.click(Button::Left);
+        assert_eq!(board.mode(), Mode::Program);
+
+        board.click(Button::Left);
+        board.click(Button::Right);
+        assert_eq!(board.mode(), Mode::Program);
+        board.set_switches(0);
+        assert_eq!(board.mode(), Mode::Execute);
+    }
+
+    #[test]
+    fn test_execute() {
+        let mut board = board();
+        assert_eq!(board.leds(), START_PC);
+
+        assert!(board.run(100));
+        assert_eq!(board.leds(), board.cpu.regs[PC as usize]);
+        assert_eq!(board.display(), 0x1234_abcd);
+        assert_eq!(board.status_led(), StatusLed::default());
+
+        // 0x1234_abcd, rightmost digit first
+        let [d, c, b, a, four, three, two, one] = board.seven_segment();
+        assert_eq!(
+            [one, two, three, four],
+            [0b1111001, 0b0100100, 0b0110000, 0b0011001]
+        );
+        assert_eq!([a, b, c, d], [0b0001000, 0b0000011, 0b1000110, 0b0100001]);
+    }
+
+    #[test]
+    fn test_paused_cpu() {
+        let mut board = board();
+        board.step();
+
+        board.set_switch(0, true);
+        assert!(!board.run(100), "the CPU is not clocked in program mode");
+
+        board.set_switch(0, false);
+        board.press(Button::Right);
+        assert_eq!(board.leds(), START_PC);
+        board.step();
+        assert_eq!(board.leds(), START_PC, "the CPU is held in reset");
+
+        board.release(Button::Right);
+        board.click(Button::Left);
+        assert!(!board.run(100), "the CPU is not clocked in inspect mode");
+
+        board.click(Button::Left);
+        assert!(board.run(100));
+    }
+
+    #[test]
+    fn test_inspect() {
+        let mut board = board();
+        board.run(100);
+        board.click(Button::Left);
+        board.set_switches(0x10);
+
+        assert_eq!(board.leds(), 0x10);
+        assert_eq!(board.display(), 0x42);
+        assert_eq!(
+            board.seven_segment()[..3],
+            [0b0100100, 0b0011001, 0b1000000]
+        );
+        assert_eq!(
+            board.status_led(),
+            StatusLed {
+                red: true,
+                ..StatusLed::default()
+            }
+        );
+    }
+
+    fn program_mode() -> Board {
+        let mut board = Board::new(CPU::from(&[], START_PC));
+        board.set_switch(0, true);
+        board
+    }
+
+    #[test]
+    fn test_byte_order() {
+        let mut board = program_mode();
+
+        for byte in upload_stream(&[0x1234, 0xabcd]) {
+            board.receive(byte);
+        }
+
+        assert_eq!(&board.cpu.ram[..3], &[0x1234, 0xabcd, 0]);
+        assert_eq!(board.leds(), 2);
+        assert_eq!(board.display(), 2);
+    }
+
+    #[test]
+    fn test_receive_outside_program_mode() {
+        let mut board = program_mode();
+        board.receive(0x12);
+
+        // the pending byte is kept while the CPU runs, like r_prog_inst
+        board.set_switch(0, false);
+        board.receive(0xff);
+        board.set_switch(0, true);
+        board.receive(0x34);
+        assert_eq!(board.cpu.ram[0], 0x1234);
+
+        board.click(Button::Right);
+        assert_eq!(board.prog_addr(), 0);
+    }
+
+    #[test]
+    fn test_framed_program_mode() {
+        let mut board = Board::framed(CPU::from(&[], START_PC));
+        let receive = |board: &mut Board| -> Vec<u8> {
+            frame(WRITE, 0x10, 1, &[7])
+                .into_iter()
+                .flat_map(|byte| board.receive(byte))
+                .collect()
+        };
+
+        assert!(receive(&mut board).is_empty());
+        assert_eq!(board.cpu.ram[0x10], 0);
+
+        board.set_switch(0, true);
+        assert_eq!(receive(&mut board), [ACK, WRITE, 0, 0x10]);
+        assert_eq!(board.cpu.ram[0x10], 7);
+        assert_eq!(board.prog_addr(), 0);
+    }
+}
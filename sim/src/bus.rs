@@ -0,0 +1,333 @@
+use crate::isa::MMIO_START;
+use crate::ppu::Ppu;
+use std::any::Any;
+use std::ops::RangeInclusive;
+
+// A peripheral mapped in the address space of the CPU.
+// Offsets are relative to the start of the range the device is mapped at.
+pub trait Device: Any {
+    fn read(&mut self, offset: u16) -> u16;
+    fn write(&mut self, offset: u16, val: u16);
+
+    // called after every instruction with the number of cycles it took
+    fn tick(&mut self, _cycles: u64, _machine: &mut Machine) {}
+}
+
+// what devices can do to the rest of the machine while they are ticked
+pub struct Machine<'a> {
+    pub ram: &'a mut [u16; 0x10000],
+    pub(crate) nmi: bool,
+    pub(crate) exit_code: Option<u16>,
+    pub(crate) writes: Vec<(u16, u16)>,
+    pub(crate) stall: u64,
+}
+
+impl<'a> Machine<'a> {
+    pub fn new(ram: &'a mut [u16; 0x10000]) -> Self {
+        Machine {
+            ram,
+            nmi: false,
+            exit_code: None,
+            writes: Vec::new(),
+            stall: 0,
+        }
+    }
+
+    pub fn raise_nmi(&mut self) {
+        self.nmi = true;
+    }
+
+    // halts the CPU, see `CPU::run`
+    pub fn exit(&mut self, code: u16) {
+        self.exit_code = Some(code);
+    }
+
+    // a store to the address space of the CPU, like the CPU's own stores it goes
+    // to the device mapped at `addr` if any. Stores are done in order once every
+    // device has been ticked.
+    pub fn write(&mut self, addr: u16, val: u16) {
+        self.writes.push((addr, val));
+    }
+
+    // stops the CPU for `cycles` cycles, during which devices keep being ticked
+    pub fn stall(&mut self, cycles: u64) {
+        self.stall += cycles;
+    }
+}
+
+// A free-running 64-bit cycle counter, read by devices through four
+// registers, high word first: reading the high word latches the whole counter
+// so that the words read next belong to the same value.
+#[derive(Default)]
+pub struct CycleCounter {
+    cycles: u64,
+    latched: u64,
+}
+
+impl CycleCounter {
+    pub fn tick(&mut self, cycles: u64) {
+        self.cycles += cycles;
+    }
+
+    // `word` 0 is the high word
+    pub fn read(&mut self, word: u16) -> u16 {
+        if word == 0 {
+            self.latched = self.cycles;
+        }
+
+        (self.latched >> (16 * (3 - word))) as u16
+    }
+}
+
+struct Mapping {
+    name: String,
+    range: RangeInclusive<u16>,
+    device: Box<dyn Device>,
+}
+
+// Dispatches loads and stores to the devices mapped at their address,
+// addresses without a device go to the CPU's RAM.
+#[derive(Default)]
+pub struct Bus {
+    mappings: Vec<Mapping>,
+}
+
+impl Bus {
+    pub fn new() -> Self {
+        Bus::default()
+    }
+
+    // the memory map of Top.veryl
+    pub fn board() -> Self {
+        let mut bus = Bus::new();
+        bus.map("ppu", MMIO_START..=0xffff, Ppu::new());
+        bus
+    }
+
+    pub fn map(
+        &mut self,
+        name: &str,
+        range: RangeInclusive<u16>,
+        device: impl Device,
+    ) -> &mut Self {
+        assert!(!range.is_empty(), "map: {name} has an empty range");
+
+        for mapping in &self.mappings {
+            assert!(
+                range.end() < mapping.range.start() || mapping.range.end() < range.start(),
+                "map: {name} overlaps with {}",
+                mapping.name
+            );
+            assert!(mapping.name != name, "map: {name} is already mapped");
+        }
+
+        self.mappings.push(Mapping {
+            name: name.to_string(),
+            range,
+            device: Box::new(device),
+        });
+
+        self
+    }
+
+    pub fn unmap(&mut self, name: &str) -> Option<Box<dyn Device>> {
+        let index = self.mappings.iter().position(|m| m.name == name)?;
+        Some(self.mappings.remove(index).device)
+    }
+
+    // (name, range) of every device
+    pub fn memory_map(&self) -> Vec<(&str, RangeInclusive<u16>)> {
+        let mut map: Vec<_> = self
+            .mappings
+            .iter()
+            .map(|m| (m.name.as_str(), m.range.clone()))
+            .collect();
+
+        map.sort_by_key(|(_, range)| *range.start());
+        map
+    }
+
+    pub fn device<T: Device>(&self, name: &str) -> Option<&T> {
+        let mapping = self.mappings.iter().find(|m| m.name == name)?;
+        (mapping.device.as_ref() as &dyn Any).downcast_ref()
+    }
+
+    pub fn device_mut<T: Device>(&mut self, name: &str) -> Option<&mut T> {
+        let mapping = self.mappings.iter_mut().find(|m| m.name == name)?;
+        (mapping.device.as_mut() as &mut dyn Any).downcast_mut()
+    }
+
+    fn find(&mut self, addr: u16) -> Option<(&mut dyn Device, u16)> {
+        self.mappings
+            .iter_mut()
+            .find(|m| m.range.contains(&addr))
+            .map(|m| (m.device.as_mut(), addr - m.range.start()))
+    }
+
+    // None if no device is mapped at `addr`
+    pub fn read(&mut self, addr: u16) -> Option<u16> {
+        self.find(addr).map(|(device, offset)| device.read(offset))
+    }
+
+    // false if no device is mapped at `addr`
+    pub fn write(&mut self, addr: u16, val: u16) -> bool {
+        self.find(addr)
+            .map(|(device, offset)| device.write(offset, val))
+            .is_some()
+    }
+
+    pub fn tick(&mut self, cycles: u64, machine: &mut Machine) {
+        for mapping in &mut self.mappings {
+            mapping.device.tick(cycles, machine);
+        }
+    }
+}
+
+// plain memory, for additional banks or to shadow a region of the RAM
+pub struct Ram {
+    pub words: Vec<u16>,
+}
+
+impl Ram {
+    pub fn new(len: usize) -> Self {
+        Ram {
+            words: vec![0; len],
+        }
+    }
+}
+
+impl Device for Ram {
+    fn read(&mut self, offset: u16) -> u16 {
+        self.words[offset as usize]
+    }
+
+    fn write(&mut self, offset: u16, val: u16) {
+        self.words[offset as usize] = val;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::Assembler;
+    use crate::isa::Reg;
+    use crate::sim::CPU;
+    use crate::START_PC;
+    use Reg::*;
+
+    // counts the cycles it is ticked for, and raises an NMI every `period` cycles
+    struct Counter {
+        cycles: u64,
+        period: u64,
+    }
+
+    impl Device for Counter {
+        fn read(&mut self, offset: u16) -> u16 {
+            (self.cycles >> (16 * offset)) as u16
+        }
+
+        fn write(&mut self, _offset: u16, _val: u16) {
+            self.cycles = 0;
+        }
+
+        fn tick(&mut self, cycles: u64, machine: &mut Machine) {
+            let before = self.cycles / self.period;
+            self.cycles += cycles;
+
+            if self.cycles / self.period != before {
+                machine.raise_nmi();
+            }
+        }
+    }
+
+    #[test]
+    fn test_memory_map() {
+        let mut bus = Bus::board();
+        bus.map("bank", 0x9000..=0x90ff, Ram::new(0x100)).map(
+            "counter",
+            0x100..=0x101,
+            Counter {
+                cycles: 0,
+                period: 1,
+            },
+        );
+
+        assert_eq!(
+            bus.memory_map(),
+            [
+                ("counter", 0x100..=0x101),
+                ("bank", 0x9000..=0x90ff),
+                ("ppu", 0xfffe..=0xffff)
+            ]
+        );
+
+        assert!(bus.write(0x9010, 7));
+        assert_eq!(bus.device::<Ram>("bank").unwrap().words[0x10], 7);
+        assert_eq!(bus.read(0x9010), Some(7));
+        assert_eq!(bus.read(0x9100), None);
+        assert!(!bus.write(0x8fff, 1));
+        assert!(bus.device::<Ram>("counter").is_none());
+
+        assert!(bus.unmap("bank").is_some());
+        assert_eq!(bus.read(0x9010), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "bank overlaps with ppu")]
+    fn test_overlapping_devices() {
+        Bus::board().map("bank", 0xff00..=0xfffe, Ram::new(0xff));
+    }
+
+    #[test]
+    fn test_cpu_dispatch() {
+        let mut asm = Assembler::new();
+        asm.setw(R1, 0x9000, TMP)
+            .set(R2, 42)
+            .store(R2, R1, 1)
+            .load(R3, R1, 1)
+            .set(R4, 0x100)
+            .load(R4, R4, 0)
+            .halt();
+
+        let mut cpu = CPU::from(&asm.assemble(), START_PC);
+        cpu.bus.map("bank", 0x9000..=0x90ff, Ram::new(0x100));
+        cpu.bus.map(
+            "counter",
+            0x100..=0x101,
+            Counter {
+                cycles: 0,
+                period: 1000,
+            },
+        );
+        cpu.run();
+
+        assert_eq!(cpu.regs[R3 as usize], 42);
+        assert_eq!(cpu.ram[0x9001], 0, "the store went to the device");
+        // cycles of the instructions before the load: setw (set, set, shl, or),
+        // set, store, load, set
+        assert_eq!(cpu.regs[R4 as usize], 3 + 3 + 4 + 4 + 3 + 5 + 5 + 3);
+        assert!(!cpu.nmi_pending());
+    }
+
+    #[test]
+    fn test_device_nmi() {
+        let mut asm = Assembler::new();
+        asm.label("loop").jmp("loop");
+
+        let mut cpu = CPU::from(&asm.assemble(), START_PC);
+        cpu.bus.map(
+            "counter",
+            0x100..=0x101,
+            Counter {
+                cycles: 0,
+                period: 10,
+            },
+        );
+
+        cpu.step(); // set: 3 cycles
+        cpu.step(); // add: 4 cycles
+        assert!(!cpu.nmi_pending());
+        cpu.step();
+        assert!(cpu.nmi_pending());
+    }
+}
@@ -0,0 +1,215 @@
+use crate::bus::{Device, Machine};
+use crate::isa::IO_PAGE;
+
+pub const DMA_BASE: u16 = IO_PAGE + 0x60;
+pub const DMA_END: u16 = DMA_BASE + DMA_STATUS;
+
+// registers, relative to DMA_BASE
+// the first word read, or the value written by DMA_FILL
+pub const DMA_SRC: u16 = 0;
+pub const DMA_DST: u16 = 1;
+// the number of words to transfer
+pub const DMA_LEN: u16 = 2;
+pub const DMA_MODE: u16 = 3;
+pub const DMA_CTRL: u16 = 4;
+// writing 1 to DMA_DONE clears it
+pub const DMA_STATUS: u16 = 5;
+
+// modes
+// copies DMA_LEN words from DMA_SRC to DMA_DST
+pub const DMA_COPY: u16 = 0;
+// writes DMA_LEN words from DMA_SRC to the port at DMA_DST, like the PPU data port
+pub const DMA_TO_PORT: u16 = 1;
+// writes the value in DMA_SRC to DMA_LEN words from DMA_DST
+pub const DMA_FILL: u16 = 2;
+// writes the value in DMA_SRC DMA_LEN times to the port at DMA_DST
+pub const DMA_FILL_PORT: u16 = 3;
+
+// control bits
+// starts a transfer, ignored while busy
+pub const DMA_START: u16 = 1 << 0;
+// raise an NMI once the transfer is done
+pub const DMA_NMI: u16 = 1 << 1;
+
+// status bits
+pub const DMA_BUSY: u16 = 1 << 0;
+pub const DMA_DONE: u16 = 1 << 1;
+
+// bus cycles taken per word, a read and a write, or just a write for the fills
+pub const READ_CYCLES: u64 = 1;
+pub const WRITE_CYCLES: u64 = 1;
+
+// A DMA controller. Transfers are done in bursts, the CPU is stalled for the
+// bus cycles they take. The source is read from RAM before anything is written,
+// so overlapping copies behave like memmove; the destination can be a device.
+#[derive(Default)]
+pub struct Dma {
+    src: u16,
+    dst: u16,
+    len: u16,
+    mode: u16,
+    ctrl: u16,
+    status: u16,
+    // cycles stolen by the transfers so far
+    stolen_cycles: u64,
+}
+
+impl Dma {
+    pub fn new() -> Self {
+        Dma::default()
+    }
+
+    pub fn stolen_cycles(&self) -> u64 {
+        self.stolen_cycles
+    }
+
+    fn transfer(&mut self, machine: &mut Machine) {
+        let words: Vec<u16> = (0..self.len)
+            .map(|i| match self.mode {
+                DMA_COPY | DMA_TO_PORT => machine.ram[self.src.wrapping_add(i) as usize],
+                _ => self.src,
+            })
+            .collect();
+
+        for (i, word) in words.into_iter().enumerate() {
+            let dst = match self.mode {
+                DMA_COPY | DMA_FILL => self.dst.wrapping_add(i as u16),
+                _ => self.dst,
+            };
+
+            machine.write(dst, word);
+        }
+
+        let per_word = match self.mode {
+            DMA_COPY | DMA_TO_PORT => READ_CYCLES + WRITE_CYCLES,
+            _ => WRITE_CYCLES,
+        };
+        let cycles = self.len as u64 * per_word;
+
+        machine.stall(cycles);
+        self.stolen_cycles += cycles;
+        self.status = DMA_DONE;
+
+        if self.ctrl & DMA_NMI != 0 {
+            machine.raise_nmi();
+        }
+    }
+}
+
+impl Device for Dma {
+    fn read(&mut self, offset: u16) -> u16 {
+        match offset {
+            DMA_SRC => self.src,
+            DMA_DST => self.dst,
+            DMA_LEN => self.len,
+            DMA_MODE => self.mode,
+            DMA_CTRL => self.ctrl,
+            DMA_STATUS => self.status,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset: u16, val: u16) {
+        if self.status & DMA_BUSY != 0 {
+            return;
+        }
+
+        match offset {
+            DMA_SRC => self.src = val,
+            DMA_DST => self.dst = val,
+            DMA_LEN => self.len = val,
+            DMA_MODE => self.mode = val.min(DMA_FILL_PORT),
+            DMA_CTRL => {
+                self.ctrl = val & DMA_NMI;
+
+                if val & DMA_START != 0 {
+                    self.status = DMA_BUSY;
+                }
+            }
+            DMA_STATUS => self.status &= !(val & DMA_DONE),
+            _ => {}
+        }
+    }
+
+    // the transfer starts right after the store which started it
+    fn tick(&mut self, _cycles: u64, machine: &mut Machine) {
+        if self.status & DMA_BUSY != 0 {
+            self.transfer(machine);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::tick_with_ram;
+
+    // returns the machine's stores, stall and NMI
+    fn tick(dma: &mut Dma, ram: &mut [u16; 0x10000]) -> (Vec<(u16, u16)>, u64, bool) {
+        let ticked = tick_with_ram(dma, 4, ram);
+        (ticked.writes, ticked.stall, ticked.nmi)
+    }
+
+    fn start(dma: &mut Dma, mode: u16, src: u16, dst: u16, len: u16, ctrl: u16) {
+        dma.write(DMA_SRC, src);
+        dma.write(DMA_DST, dst);
+        dma.write(DMA_LEN, len);
+        dma.write(DMA_MODE, mode);
+        dma.write(DMA_CTRL, ctrl | DMA_START);
+    }
+
+    #[test]
+    fn test_copy() {
+        let mut ram = Box::new([0; 0x10000]);
+        ram[0x100..0x104].copy_from_slice(&[1, 2, 3, 4]);
+
+        let mut dma = Dma::new();
+        start(&mut dma, DMA_COPY, 0x100, 0x102, 4, 0);
+        assert_eq!(dma.read(DMA_STATUS), DMA_BUSY);
+
+        // like memmove
+        let (writes, stall, nmi) = tick(&mut dma, &mut ram);
+        assert_eq!(writes, [(0x102, 1), (0x103, 2), (0x104, 3), (0x105, 4)]);
+        assert_eq!((stall, nmi), (8, false));
+        assert_eq!(dma.read(DMA_STATUS), DMA_DONE);
+
+        dma.write(DMA_STATUS, DMA_DONE);
+        assert_eq!(dma.read(DMA_STATUS), 0);
+        assert_eq!(tick(&mut dma, &mut ram).0, []);
+    }
+
+    #[test]
+    fn test_ports_and_fill() {
+        let mut ram = Box::new([0; 0x10000]);
+        ram[0x10..0x12].copy_from_slice(&[b'h' as u16, b'i' as u16]);
+
+        let mut dma = Dma::new();
+        start(&mut dma, DMA_TO_PORT, 0x10, 0xffff, 2, DMA_NMI);
+        assert_eq!(
+            tick(&mut dma, &mut ram),
+            (vec![(0xffff, b'h' as u16), (0xffff, b'i' as u16)], 4, true)
+        );
+
+        start(&mut dma, DMA_FILL, 7, 0x200, 3, 0);
+        assert_eq!(
+            tick(&mut dma, &mut ram),
+            (vec![(0x200, 7), (0x201, 7), (0x202, 7)], 3, false)
+        );
+
+        start(&mut dma, DMA_FILL_PORT, b' ' as u16, 0xffff, 2, 0);
+        assert_eq!(
+            tick(&mut dma, &mut ram),
+            (vec![(0xffff, b' ' as u16), (0xffff, b' ' as u16)], 2, false)
+        );
+        assert_eq!(dma.stolen_cycles(), 9);
+    }
+
+    #[test]
+    fn test_busy() {
+        let mut dma = Dma::new();
+        start(&mut dma, DMA_FILL, 1, 0x200, 3, 0);
+        dma.write(DMA_LEN, 100);
+
+        assert_eq!(dma.read(DMA_LEN), 3);
+    }
+}
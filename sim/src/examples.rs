@@ -0,0 +1,482 @@
+use crate::asm::Assembler;
+use crate::isa::Reg;
+use crate::procedures::uart::def_uart;
+use crate::procedures::{def_division, def_is_power_of_two, def_itoa, def_print};
+
+pub fn add() -> Vec<u16> {
+    use Reg::*;
+
+    Assembler::new()
+        .set(R1, 0x23)
+        .set(R2, 0x17)
+        .add(R1, R1, R2)
+        .halt()
+        .assemble()
+}
+
+pub fn sub() -> Vec<u16> {
+    use Reg::*;
+
+    Assembler::new()
+        .set(R1, 0x23)
+        .set(R2, 0x17)
+        .sub(R1, R1, R2)
+        .halt()
+        .assemble()
+}
+
+pub fn muli() -> Vec<u16> {
+    use Reg::*;
+
+    Assembler::new()
+        .set(R2, 0x23)
+        .muli(R1, R2, 0x17)
+        .halt()
+        .assemble()
+}
+
+pub fn xor() -> Vec<u16> {
+    use Reg::*;
+
+    Assembler::new()
+        .set(R2, 0x23)
+        .set(R3, 0x17)
+        .xor(R1, R2, R3)
+        .halt()
+        .assemble()
+}
+
+pub fn dec() -> Vec<u16> {
+    use Reg::*;
+
+    Assembler::new().set(R1, 0x23).dec(R1).halt().assemble()
+}
+
+pub fn count() -> Vec<u16> {
+    use Reg::*;
+
+    Assembler::new()
+        .set(R1, 0xa)
+        .set(R2, 0)
+        .label("loop")
+        .dec(R1)
+        .jmpnz("loop")
+        .halt()
+        .assemble()
+}
+
+pub fn div() -> Vec<u16> {
+    use Reg::*;
+
+    let mut asm = Assembler::new();
+
+    asm.init_sp().set(R2, 1621).set(R3, 17).call("div").halt();
+
+    def_division(&mut asm, "div", R1, R2, R3);
+
+    asm.assemble()
+}
+
+pub fn add32() -> Vec<u16> {
+    use Reg::*;
+
+    Assembler::new()
+        .setw(R1, 0x1234, TMP)
+        .setw(R2, 0xbaba, TMP)
+        .setw(R3, 0x4321, TMP)
+        .setw(R4, 0x5678, TMP)
+        .add32(R1, R2, R3, R4)
+        .halt()
+        .assemble()
+}
+
+pub fn euler1() -> Vec<u16> {
+    use Reg::*;
+
+    // static variables
+    let n = "n";
+    let sum_hi = "sum_hi";
+    let sum_lo = "sum_lo";
+
+    let mut asm = Assembler::new();
+
+    asm.static_var(n, 1)
+        .static_var(sum_hi, 1)
+        .static_var(sum_lo, 1)
+        .init_sp()
+        .store_var(Z, sum_lo)
+        .store_var(Z, sum_hi)
+        .setw(TMP, 1000, R1)
+        .dec(TMP)
+        .store_var(TMP, n)
+        .label("loop")
+        .load_var(R1, n)
+        .set(R3, 3)
+        .call("div")
+        .update_flags(R1)
+        .jmpz("is_divisible")
+        .load_var(R1, n)
+        .set(R3, 5)
+        .call("div")
+        .update_flags(R1)
+        .jmpz("is_divisible")
+        .label("loop_back")
+        .load_var(R1, n)
+        .dec(R1)
+        .store_var(R1, n)
+        .jmpnz("loop")
+        .jmp("end")
+        .label("is_divisible")
+        .load_var(R1, n)
+        .load_var(R2, sum_hi)
+        .load_var(R3, sum_lo)
+        .add32(R2, R3, Z, R1)
+        .store_var(R2, sum_hi)
+        .store_var(R3, sum_lo)
+        .jmp("loop_back")
+        .label("end")
+        .load_var(R1, sum_hi)
+        .load_var(R2, sum_lo)
+        .halt();
+
+    def_division(&mut asm, "div", R2, R1, R3);
+
+    asm.assemble()
+}
+
+pub fn lab() -> Assembler {
+    use Reg::*;
+
+    let mut asm = Assembler::new();
+    asm.static_var_at("str", 0x20, 6);
+    let str_ptr = asm.var_address("str").unwrap();
+
+    asm.init_sp()
+        .setw(R1, 0xbaba, TMP)
+        .set(R2, str_ptr)
+        .call("itoa")
+        .set(R1, str_ptr)
+        .set(R2, 0)
+        .call("print")
+        .halt();
+
+    def_itoa(&mut asm);
+    def_print(&mut asm);
+
+    asm
+}
+
+pub fn call() -> Vec<u16> {
+    use Reg::*;
+
+    Assembler::new()
+        .jmp("start")
+        .label("yo")
+        .set(R1, 0x23)
+        .ret()
+        .label("start")
+        .set(R1, 7)
+        .call("yo")
+        .inc(R1)
+        .halt()
+        .assemble()
+}
+
+pub fn mem() -> Vec<u16> {
+    use Reg::*;
+
+    Assembler::new()
+        .set(R1, 0x23)
+        .store(R1, Z, 3)
+        .set(R1, 0)
+        .load(R1, Z, 3)
+        .halt()
+        .assemble()
+}
+
+pub fn stack() -> Vec<u16> {
+    use Reg::*;
+
+    Assembler::new()
+        .init_sp()
+        .set(R1, 0x23)
+        .push(R1)
+        .set(R1, 0x11)
+        .pop(R1)
+        .halt()
+        .assemble()
+}
+
+pub fn power_of_two() -> Vec<u16> {
+    use Reg::*;
+
+    let mut asm = Assembler::new();
+
+    asm.init_sp()
+        .setw(R1, 0x80, TMP)
+        .call("is_power_of_two")
+        .halt();
+
+    def_is_power_of_two(&mut asm, "is_power_of_two", R1);
+
+    asm.assemble()
+}
+
+pub fn yo_fpga() -> Vec<u16> {
+    use Reg::*;
+
+    let mut asm = Assembler::new();
+
+    let message = "In computing, the reset vector is the default location a central processing unit will go to find the first instruction it will execute after a reset. The reset vector is a pointer or address, where the CPU should always begin as soon as it is able to execute instructions. The address is in a section of non-volatile memory initialized to contain instructions to start the operation of the CPU, as the first step in the process of booting the system containing the CPU.";
+
+    asm.init_sp()
+        .setw(R2, 0xfffe, TMP)
+        .store(Z, R2, 0)
+        .setw(R2, 0xffff, TMP)
+        .setw(R3, 1 << 15, TMP);
+
+    for byte in message.bytes() {
+        asm.set(R1, byte as u16).store(R1, R2, 0);
+    }
+
+    asm.halt().assemble()
+}
+
+pub fn itoa() -> Assembler {
+    use Reg::*;
+
+    let mut asm = Assembler::new();
+    asm.static_var_at("str", 0x20, 6);
+    let str_ptr = asm.var_address("str").unwrap();
+
+    asm.init_sp()
+        .setw(R1, 0xbaba, TMP) // num
+        .set(R2, str_ptr)
+        .call("itoa")
+        .halt();
+
+    def_itoa(&mut asm);
+
+    asm
+}
+
+// echoes every line it receives over the UART in upper case, until an empty line
+pub fn uart_echo() -> Vec<u16> {
+    use Reg::*;
+
+    let mut asm = Assembler::new();
+
+    asm.init_sp()
+        .set(R4, 0) // length of the current line
+        .label("echo_loop")
+        .push(R4)
+        .call("uart_getc")
+        .pop(R4)
+        .set(TMP, b'\n' as u16)
+        .cmp(R1, TMP)
+        .jump_if_eq("echo_newline")
+        .inc(R4)
+        // lower case letters are in 'a'..='z'
+        .set(TMP, b'a' as u16)
+        .cmp(R1, TMP)
+        .jmpnc("echo_putc")
+        .set(TMP, b'z' as u16 + 1)
+        .cmp(R1, TMP)
+        .jmpc("echo_putc")
+        .set(TMP, 0x20)
+        .sub(R1, R1, TMP)
+        .jmp("echo_putc")
+        .label("echo_newline")
+        .update_flags(R4)
+        .jmpz("echo_end")
+        .set(R4, 0)
+        .label("echo_putc")
+        .push(R4)
+        .call("uart_putc")
+        .pop(R4)
+        .jmp("echo_loop")
+        .label("echo_end")
+        .call("uart_flush")
+        .halt();
+
+    def_uart(&mut asm);
+
+    asm.assemble()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::CPU;
+    use crate::testing;
+    use crate::uart::{Uart, UartConfig, UART_BASE, UART_END};
+    use crate::START_PC;
+
+    #[test]
+    fn test_add() {
+        let mut cpu = CPU::from(&add(), START_PC);
+
+        cpu.run();
+
+        assert_eq!(cpu.regs[Reg::R1 as usize], 0x23 + 0x17);
+    }
+
+    #[test]
+    fn test_sub() {
+        let mut cpu = CPU::from(&sub(), START_PC);
+
+        cpu.run();
+
+        assert_eq!(cpu.regs[Reg::R1 as usize], 0x23 - 0x17);
+    }
+
+    #[test]
+    fn test_muli() {
+        let mut cpu = CPU::from(&muli(), START_PC);
+
+        cpu.run();
+
+        assert_eq!(cpu.regs[Reg::R1 as usize], 0x23 * 0x17);
+    }
+
+    #[test]
+    fn test_xor() {
+        let mut cpu = CPU::from(&xor(), START_PC);
+
+        cpu.run();
+
+        assert_eq!(cpu.regs[Reg::R1 as usize], 0x23 ^ 0x17);
+    }
+
+    #[test]
+    fn test_dec() {
+        let mut cpu = CPU::from(&dec(), START_PC);
+
+        cpu.run();
+
+        assert_eq!(cpu.regs[Reg::R1 as usize], 0x23 - 1);
+    }
+
+    #[test]
+    fn test_count() {
+        let mut cpu = CPU::from(&count(), START_PC);
+
+        cpu.run();
+
+        assert_eq!(cpu.regs[Reg::R1 as usize], 0);
+    }
+
+    #[test]
+    fn test_div() {
+        let mut cpu = CPU::from(&div(), START_PC);
+
+        cpu.run();
+
+        assert_eq!(cpu.regs[Reg::R1 as usize], 1621 / 17);
+    }
+
+    #[test]
+    fn test_add32() {
+        let mut cpu = CPU::from(&add32(), START_PC);
+
+        cpu.run();
+
+        let sum: u32 = 0x1234_baba + 0x4321_5678;
+        assert_eq!(cpu.regs[Reg::R1 as usize], (sum >> 16) as u16);
+        assert_eq!(cpu.regs[Reg::R2 as usize], (sum & 0xffff) as u16);
+    }
+
+    #[test]
+    fn test_call() {
+        let mut cpu = CPU::from(&call(), START_PC);
+
+        cpu.run();
+
+        assert_eq!(cpu.regs[Reg::R1 as usize], 0x23 + 1);
+    }
+
+    #[test]
+    fn test_mem() {
+        let mut cpu = CPU::from(&mem(), START_PC);
+
+        cpu.run();
+
+        assert_eq!(cpu.ram[3], 0x23);
+    }
+
+    #[test]
+    fn test_euler1() {
+        let mut cpu = CPU::from(&euler1(), START_PC);
+
+        cpu.run();
+
+        assert_eq!(cpu.regs[Reg::R1 as usize], 0x0003);
+        assert_eq!(cpu.regs[Reg::R2 as usize], 0x8ed0);
+    }
+
+    #[test]
+    fn test_stack() {
+        let mut cpu = CPU::from(&stack(), START_PC);
+
+        cpu.run();
+
+        assert_eq!(cpu.regs[Reg::R1 as usize], 0x23);
+    }
+
+    #[test]
+    fn test_power_of_two() {
+        let mut cpu = CPU::from(&power_of_two(), START_PC);
+
+        cpu.run();
+
+        assert_eq!(cpu.regs[Reg::R1 as usize], 1);
+    }
+
+    #[test]
+    fn test_itoa() {
+        let asm = itoa();
+        let mut cpu = CPU::from(&asm.assemble(), START_PC);
+
+        cpu.run();
+
+        let mut str = String::new();
+        let str_ptr = asm.var_address("str").unwrap() as usize;
+
+        for i in str_ptr..str_ptr + 6 {
+            str.push(char::from(cpu.ram[i] as u8));
+        }
+
+        assert_eq!(str, "47802\0");
+    }
+
+    #[test]
+    fn test_lab() {
+        let mut cpu = CPU::from(&lab().assemble(), START_PC);
+
+        cpu.run();
+
+        testing::assert_row_eq(&cpu, 0, "47802");
+    }
+
+    #[test]
+    fn test_yo_fpga() {
+        let mut cpu = CPU::from(&yo_fpga(), START_PC);
+
+        cpu.run();
+
+        testing::assert_screen_snapshot(&cpu, "yo_fpga");
+    }
+
+    #[test]
+    fn test_uart_echo() {
+        let mut cpu = CPU::from(&uart_echo(), START_PC);
+        let mut uart = Uart::new(UartConfig::default());
+        uart.send(b"hello, uart!\nbye\n\nignored\n");
+        cpu.bus.map("uart", UART_BASE..=UART_END, uart);
+
+        assert!(cpu.run_with_fuel(100_000, false).is_some());
+
+        let uart = cpu.bus.device::<Uart>("uart").unwrap();
+        assert_eq!(uart.sent(), b"HELLO, UART!\nBYE\n");
+    }
+}
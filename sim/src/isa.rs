@@ -11,6 +11,42 @@ pub enum Reg {
 }
 
 pub const STACK_POINTER_TOP: u16 = 0x7f00;
+// the stack grows upwards until the program, which is loaded at 0x8000
+pub const STACK_END: u16 = 0x8000;
+// the peripherals of the simulator (see bus.rs) are mapped in the page
+// below the stack, which programs can not use for static variables
+pub const IO_PAGE: u16 = 0x7e00;
+pub const IO_PAGE_END: u16 = STACK_POINTER_TOP;
+// PPU name table address and data ports (see Top.veryl)
+pub const MMIO_START: u16 = 0xfffe;
+// the simulator jumps there on an NMI (INTERRUPT_ADDR in CPU.veryl)
+pub const INTERRUPT_ADDR: u16 = 0xfff0;
+// the CPU runs at the 100 MHz clock of the board
+pub const CLOCK_HZ: u64 = 100_000_000;
+
+// the multiply/divide coprocessor of the simulator (see muldiv.rs),
+// which `Assembler::use_muldiv` programs
+pub const MULDIV_BASE: u16 = IO_PAGE + 0x70;
+pub const MULDIV_END: u16 = MULDIV_BASE + MULDIV_HI;
+
+// registers, relative to MULDIV_BASE
+pub const MULDIV_A: u16 = 0;
+pub const MULDIV_B: u16 = 1;
+// writing an operation starts it, ignored while busy
+pub const MULDIV_CTRL: u16 = 2;
+pub const MULDIV_STATUS: u16 = 3;
+// the low word of the product, or the quotient
+pub const MULDIV_LO: u16 = 4;
+// the high word of the product, or the remainder
+pub const MULDIV_HI: u16 = 5;
+
+// operations, both unsigned. Like `divu16` (see arith.rs), a division by zero
+// yields a quotient of 0xffff and leaves the dividend as the remainder.
+pub const MULDIV_MUL: u16 = 1;
+pub const MULDIV_DIV: u16 = 2;
+
+// status bits, the results are those of the previous operation while busy
+pub const MULDIV_BUSY: u16 = 1 << 0;
 
 impl std::fmt::Display for Reg {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -80,6 +116,8 @@ impl std::fmt::Display for Cond {
     }
 }
 
+// the digits are grouped as <condition: 3> <operation: 2>
+#[allow(clippy::unusual_byte_groupings)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AluOp {
     Add = 0b000_00,
@@ -259,6 +297,18 @@ pub enum Inst {
     },
 }
 
+impl Inst {
+    // number of clock cycles spent in the CPU.veryl state machine:
+    // fetch, decode, [execute], [memory access], writeback
+    pub fn cycles(&self) -> u64 {
+        match self {
+            Inst::Set { .. } => 3,
+            Inst::Ctl { .. } | Inst::Alu { .. } => 4,
+            Inst::Mem { .. } => 5,
+        }
+    }
+}
+
 fn bit(val: u16, pos: u16) -> bool {
     (val & (1 << pos)) != 0
 }
@@ -290,9 +340,9 @@ impl From<u16> for Inst {
     }
 }
 
-impl Into<u16> for Inst {
-    fn into(self) -> u16 {
-        match self {
+impl From<Inst> for u16 {
+    fn from(inst: Inst) -> Self {
+        match inst {
             Inst::Ctl { op } => {
                 let mut inst = 0b00 << 14;
                 inst |= match op {
@@ -0,0 +1,613 @@
+use crate::bus::{Device, Machine};
+use crate::isa::IO_PAGE;
+use std::collections::VecDeque;
+use std::io::{self, Read};
+use std::sync::mpsc;
+
+pub const KEYBOARD_BASE: u16 = IO_PAGE + 0x30;
+pub const KEYBOARD_END: u16 = KEYBOARD_BASE + 3;
+
+// registers, relative to KEYBOARD_BASE
+pub const KEYBOARD_STATUS: u16 = 0;
+// reading pops a key event (0 if there is none):
+// the scancode in the high byte, with bit 7 set on release, and the ASCII code in the low byte
+pub const KEYBOARD_DATA: u16 = 1;
+pub const KEYBOARD_CTRL: u16 = 2;
+pub const KEYBOARD_COUNT: u16 = 3;
+
+// status bits
+pub const KEYBOARD_READY: u16 = 1 << 0;
+// a key event was dropped because the FIFO was full, cleared by reading the status
+pub const KEYBOARD_OVERRUN: u16 = 1 << 1;
+
+// control bits
+// raise an NMI when a key is pressed
+pub const KEYBOARD_NMI: u16 = 1 << 0;
+
+pub const FIFO_DEPTH: usize = 16;
+// cycles between two key events of typed text, 0.1 ms
+pub const TYPING_INTERVAL: u64 = 10_000;
+
+// A key of a US keyboard, identified by its scancode (set 1).
+// `ascii` is 0 for keys without a character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Key {
+    pub scancode: u8,
+    pub ascii: u8,
+}
+
+// (scancode, unshifted, shifted)
+const KEYMAP: &[(u8, u8, u8)] = &[
+    (0x01, 0x1b, 0x1b),
+    (0x02, b'1', b'!'),
+    (0x03, b'2', b'@'),
+    (0x04, b'3', b'#'),
+    (0x05, b'4', b'$'),
+    (0x06, b'5', b'%'),
+    (0x07, b'6', b'^'),
+    (0x08, b'7', b'&'),
+    (0x09, b'8', b'*'),
+    (0x0a, b'9', b'('),
+    (0x0b, b'0', b')'),
+    (0x0c, b'-', b'_'),
+    (0x0d, b'=', b'+'),
+    (0x0e, 0x08, 0x08),
+    (0x0f, b'\t', b'\t'),
+    (0x10, b'q', b'Q'),
+    (0x11, b'w', b'W'),
+    (0x12, b'e', b'E'),
+    (0x13, b'r', b'R'),
+    (0x14, b't', b'T'),
+    (0x15, b'y', b'Y'),
+    (0x16, b'u', b'U'),
+    (0x17, b'i', b'I'),
+    (0x18, b'o', b'O'),
+    (0x19, b'p', b'P'),
+    (0x1a, b'[', b'{'),
+    (0x1b, b']', b'}'),
+    (0x1c, b'\n', b'\n'),
+    (0x1e, b'a', b'A'),
+    (0x1f, b's', b'S'),
+    (0x20, b'd', b'D'),
+    (0x21, b'f', b'F'),
+    (0x22, b'g', b'G'),
+    (0x23, b'h', b'H'),
+    (0x24, b'j', b'J'),
+    (0x25, b'k', b'K'),
+    (0x26, b'l', b'L'),
+    (0x27, b';', b':'),
+    (0x28, b'\'', b'"'),
+    (0x29, b'`', b'~'),
+    (0x2b, b'\\', b'|'),
+    (0x2c, b'z', b'Z'),
+    (0x2d, b'x', b'X'),
+    (0x2e, b'c', b'C'),
+    (0x2f, b'v', b'V'),
+    (0x30, b'b', b'B'),
+    (0x31, b'n', b'N'),
+    (0x32, b'm', b'M'),
+    (0x33, b',', b'<'),
+    (0x34, b'.', b'>'),
+    (0x35, b'/', b'?'),
+    (0x39, b' ', b' '),
+];
+
+// keys without a character, or whose character is not printable
+const NAMED_KEYS: &[(&str, u8)] = &[
+    ("esc", 0x01),
+    ("backspace", 0x0e),
+    ("tab", 0x0f),
+    ("enter", 0x1c),
+    ("ctrl", 0x1d),
+    ("shift", 0x2a),
+    ("space", 0x39),
+    ("up", 0x48),
+    ("left", 0x4b),
+    ("right", 0x4d),
+    ("down", 0x50),
+];
+
+impl Key {
+    pub const SHIFT: Key = Key {
+        scancode: 0x2a,
+        ascii: 0,
+    };
+
+    // the key typing `c` and whether it needs shift
+    pub fn from_char(c: char) -> Option<(Key, bool)> {
+        // terminals in raw mode send Enter as CR and Backspace as DEL
+        let c = match u8::try_from(c).ok()? {
+            b'\r' => b'\n',
+            0x7f => 0x08,
+            c => c,
+        };
+
+        KEYMAP.iter().find_map(|&(scancode, unshifted, shifted)| {
+            let shift = c == shifted && c != unshifted;
+            (c == unshifted || shift).then_some((Key { scancode, ascii: c }, shift))
+        })
+    }
+
+    // a key from its name (see NAMED_KEYS, case-insensitive) or its character
+    pub fn from_name(name: &str) -> Option<Key> {
+        if let Some(&(_, scancode)) = NAMED_KEYS
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        {
+            let ascii = KEYMAP
+                .iter()
+                .find(|&&(code, _, _)| code == scancode)
+                .map_or(0, |&(_, ascii, _)| ascii);
+
+            return Some(Key { scancode, ascii });
+        }
+
+        let mut chars = name.chars();
+
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Key::from_char(c).map(|(key, _)| key),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub key: Key,
+    pub pressed: bool,
+}
+
+impl KeyEvent {
+    // the value read from KEYBOARD_DATA
+    pub fn word(&self) -> u16 {
+        let release = if self.pressed { 0 } else { 0x80 };
+        ((self.key.scancode | release) as u16) << 8 | self.key.ascii as u16
+    }
+}
+
+// the key events typing `text`, with shift held for shifted characters
+pub fn text_events(text: &str) -> Result<Vec<KeyEvent>, String> {
+    let mut events = Vec::new();
+
+    for c in text.chars() {
+        let (key, shift) = Key::from_char(c).ok_or(format!("no key types {c:?}"))?;
+
+        if shift {
+            events.push(KeyEvent {
+                key: Key::SHIFT,
+                pressed: true,
+            });
+        }
+
+        events.push(KeyEvent { key, pressed: true });
+        events.push(KeyEvent {
+            key,
+            pressed: false,
+        });
+
+        if shift {
+            events.push(KeyEvent {
+                key: Key::SHIFT,
+                pressed: false,
+            });
+        }
+    }
+
+    Ok(events)
+}
+
+// Parses a script of key events, one per line:
+//   <cycle> press <key>
+//   <cycle> release <key>
+//   <cycle> type <text>
+// where keys are named as in `Key::from_name`, and typed text is spaced by
+// TYPING_INTERVAL cycles. Empty lines and lines starting with # are ignored.
+pub fn parse_script(script: &str) -> Result<Vec<(u64, KeyEvent)>, String> {
+    let mut events = Vec::new();
+
+    for (i, line) in script.lines().enumerate() {
+        let error = |msg: &str| format!("line {}: {msg}", i + 1);
+        let trimmed = line.trim_start();
+
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        let mut parts = trimmed.splitn(3, ' ');
+        let (cycle, command, arg) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(cycle), Some(command), Some(arg)) => (cycle, command, arg),
+            _ => return Err(error("expected <cycle> <command> <argument>")),
+        };
+
+        let cycle: u64 = cycle.parse().map_err(|_| error("invalid cycle"))?;
+
+        match command {
+            "press" | "release" => {
+                let key = Key::from_name(arg.trim()).ok_or(error("unknown key"))?;
+                let pressed = command == "press";
+                events.push((cycle, KeyEvent { key, pressed }));
+            }
+            "type" => {
+                let typed = text_events(arg).map_err(|err| error(&err))?;
+
+                for (j, event) in typed.into_iter().enumerate() {
+                    events.push((cycle + j as u64 * TYPING_INTERVAL, event));
+                }
+            }
+            _ => return Err(error(&format!("unknown command {command}"))),
+        }
+    }
+
+    Ok(events)
+}
+
+const CTRL_C: u8 = 0x03;
+
+// Puts the terminal on stdin in raw mode, so that keys are read as they are
+// typed and not echoed, and restores its settings when dropped.
+struct RawStdin(libc::termios);
+
+impl RawStdin {
+    fn new() -> io::Result<Self> {
+        // SAFETY: the termios struct is initialized by tcgetattr before being used
+        unsafe {
+            let mut termios = std::mem::zeroed::<libc::termios>();
+
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let original = termios;
+            libc::cfmakeraw(&mut termios);
+            // keep translating "\n" to "\r\n" in what is printed
+            termios.c_oflag |= libc::OPOST;
+
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(RawStdin(original))
+        }
+    }
+
+    fn restore(termios: &libc::termios) {
+        // SAFETY: `termios` was filled by tcgetattr
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, termios);
+        }
+    }
+}
+
+impl Drop for RawStdin {
+    fn drop(&mut self) {
+        RawStdin::restore(&self.0);
+    }
+}
+
+// A keyboard of the simulator. Key events are scheduled at a cycle (see
+// `schedule`), and are pushed to the FIFO read by the program once the CPU
+// has run that many cycles.
+pub struct Keyboard {
+    ctrl: u16,
+    fifo: VecDeque<KeyEvent>,
+    overrun: bool,
+    // sorted by cycle
+    scheduled: VecDeque<(u64, KeyEvent)>,
+    cycles: u64,
+    // the cycle after the last event of the text typed so far
+    typed_until: u64,
+    input: Option<mpsc::Receiver<u8>>,
+    // the terminal on stdin while reading `input` from it
+    raw_stdin: Option<RawStdin>,
+}
+
+impl Keyboard {
+    pub fn new() -> Self {
+        Keyboard {
+            ctrl: 0,
+            fifo: VecDeque::new(),
+            overrun: false,
+            scheduled: VecDeque::new(),
+            cycles: 0,
+            typed_until: 0,
+            input: None,
+            raw_stdin: None,
+        }
+    }
+
+    // keys typed on the terminal, one press and release per byte read from stdin.
+    // The terminal is in raw mode until the keyboard is dropped, and Ctrl-C
+    // exits the simulator instead of being passed to the program.
+    pub fn stdin() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        // stdin may not be a terminal
+        let raw_stdin = RawStdin::new().ok();
+        let original = raw_stdin.as_ref().map(|raw| raw.0);
+
+        std::thread::spawn(move || {
+            let mut stdin = io::stdin();
+            let mut buffer = [0; 64];
+
+            while let Ok(len @ 1..) = stdin.read(&mut buffer) {
+                if let (Some(original), true) = (&original, buffer[..len].contains(&CTRL_C)) {
+                    RawStdin::restore(original);
+                    std::process::exit(130);
+                }
+
+                if buffer[..len].iter().any(|&byte| sender.send(byte).is_err()) {
+                    break;
+                }
+            }
+        });
+
+        let mut keyboard = Keyboard::new();
+        keyboard.input = Some(receiver);
+        keyboard.raw_stdin = raw_stdin;
+        keyboard
+    }
+
+    pub fn schedule(&mut self, cycle: u64, event: KeyEvent) {
+        let index = self.scheduled.partition_point(|&(at, _)| at <= cycle);
+        self.scheduled.insert(index, (cycle, event));
+    }
+
+    pub fn schedule_all(&mut self, events: impl IntoIterator<Item = (u64, KeyEvent)>) {
+        for (cycle, event) in events {
+            self.schedule(cycle, event);
+        }
+    }
+
+    pub fn press(&mut self, key: Key) {
+        self.schedule(self.cycles, KeyEvent { key, pressed: true });
+    }
+
+    pub fn release(&mut self, key: Key) {
+        self.schedule(
+            self.cycles,
+            KeyEvent {
+                key,
+                pressed: false,
+            },
+        );
+    }
+
+    // types `text` from now on, or once the text typed before is done,
+    // see `text_events`
+    pub fn type_text(&mut self, text: &str) -> Result<(), String> {
+        let start = self.cycles.max(self.typed_until);
+        let events = text_events(text)?;
+        self.typed_until = start + events.len() as u64 * TYPING_INTERVAL;
+
+        for (i, event) in events.into_iter().enumerate() {
+            self.schedule(start + i as u64 * TYPING_INTERVAL, event);
+        }
+
+        Ok(())
+    }
+
+    // events which have not been delivered to the FIFO yet
+    pub fn scheduled(&self) -> usize {
+        self.scheduled.len()
+    }
+
+    fn type_input(&mut self) {
+        let Some(input) = &self.input else {
+            return;
+        };
+
+        let typed: String = input.try_iter().map(char::from).collect();
+
+        // characters without a key, like control codes, are dropped
+        for c in typed.chars() {
+            let _ = self.type_text(&c.to_string());
+        }
+    }
+
+    fn status(&mut self) -> u16 {
+        let mut status = 0;
+
+        if !self.fifo.is_empty() {
+            status |= KEYBOARD_READY;
+        }
+
+        if std::mem::take(&mut self.overrun) {
+            status |= KEYBOARD_OVERRUN;
+        }
+
+        status
+    }
+}
+
+impl Default for Keyboard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Device for Keyboard {
+    fn read(&mut self, offset: u16) -> u16 {
+        match offset {
+            KEYBOARD_STATUS => self.status(),
+            KEYBOARD_DATA => self.fifo.pop_front().map_or(0, |event| event.word()),
+            KEYBOARD_CTRL => self.ctrl,
+            KEYBOARD_COUNT => self.fifo.len() as u16,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset: u16, val: u16) {
+        if offset == KEYBOARD_CTRL {
+            self.ctrl = val & KEYBOARD_NMI;
+        }
+    }
+
+    fn tick(&mut self, cycles: u64, machine: &mut Machine) {
+        self.cycles += cycles;
+        self.type_input();
+
+        while let Some(&(cycle, event)) = self.scheduled.front() {
+            if cycle > self.cycles {
+                break;
+            }
+
+            self.scheduled.pop_front();
+
+            if self.fifo.len() == FIFO_DEPTH {
+                self.overrun = true;
+                continue;
+            }
+
+            self.fifo.push_back(event);
+
+            if event.pressed && self.ctrl & KEYBOARD_NMI != 0 {
+                machine.raise_nmi();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::tick;
+
+    fn drain(keyboard: &mut Keyboard) -> Vec<u16> {
+        std::iter::from_fn(|| match keyboard.read(KEYBOARD_DATA) {
+            0 => None,
+            word => Some(word),
+        })
+        .collect()
+    }
+
+    #[test]
+    fn test_keys() {
+        assert_eq!(
+            Key::from_char('A'),
+            Some((
+                Key {
+                    scancode: 0x1e,
+                    ascii: b'A'
+                },
+                true
+            ))
+        );
+        assert_eq!(Key::from_char('\u{e9}'), None);
+        assert_eq!(Key::from_char('\r'), Key::from_char('\n'));
+        assert_eq!(
+            Key::from_char('\x7f').unwrap().0,
+            Key::from_name("backspace").unwrap()
+        );
+        assert_eq!(
+            Key::from_name("Enter"),
+            Some(Key {
+                scancode: 0x1c,
+                ascii: b'\n'
+            })
+        );
+        assert_eq!(Key::from_name("up").unwrap().ascii, 0);
+        assert_eq!(Key::from_name("q").unwrap().scancode, 0x10);
+        assert_eq!(Key::from_name("qq"), None);
+    }
+
+    #[test]
+    fn test_type_text() {
+        let mut keyboard = Keyboard::new();
+        keyboard.type_text("a!").unwrap();
+
+        tick(&mut keyboard, TYPING_INTERVAL);
+        assert_eq!(drain(&mut keyboard), [0x1e61, 0x9e61]);
+
+        tick(&mut keyboard, 4 * TYPING_INTERVAL);
+        assert_eq!(drain(&mut keyboard), [0x2a00, 0x0221, 0x8221, 0xaa00]);
+        assert_eq!(keyboard.scheduled(), 0);
+    }
+
+    #[test]
+    fn test_type_input() {
+        let (sender, receiver) = mpsc::channel();
+        let mut keyboard = Keyboard::new();
+        keyboard.input = Some(receiver);
+
+        // bytes read from a terminal in raw mode, in two reads
+        sender.send(b'a').unwrap();
+        tick(&mut keyboard, 1);
+        for byte in *b"b\r" {
+            sender.send(byte).unwrap();
+        }
+        tick(&mut keyboard, TYPING_INTERVAL);
+
+        // the keys of every byte follow the ones of the previous byte
+        assert_eq!(drain(&mut keyboard), [0x1e61, 0x9e61]);
+        assert_eq!(keyboard.scheduled(), 4);
+
+        tick(&mut keyboard, 4 * TYPING_INTERVAL);
+        assert_eq!(drain(&mut keyboard), [0x3062, 0xb062, 0x1c0a, 0x9c0a]);
+    }
+
+    #[test]
+    fn test_nmi_on_press() {
+        let mut keyboard = Keyboard::new();
+        keyboard.write(KEYBOARD_CTRL, KEYBOARD_NMI);
+        let key = Key::from_name("esc").unwrap();
+
+        keyboard.press(key);
+        assert!(tick(&mut keyboard, 1).nmi);
+        keyboard.release(key);
+        assert!(!tick(&mut keyboard, 1).nmi);
+
+        assert_eq!(keyboard.read(KEYBOARD_COUNT), 2);
+        assert_eq!(keyboard.read(KEYBOARD_STATUS), KEYBOARD_READY);
+    }
+
+    #[test]
+    fn test_overrun() {
+        let mut keyboard = Keyboard::new();
+        let key = Key::from_name("x").unwrap();
+
+        for _ in 0..=FIFO_DEPTH {
+            keyboard.press(key);
+        }
+
+        tick(&mut keyboard, 1);
+        assert_eq!(keyboard.read(KEYBOARD_COUNT), FIFO_DEPTH as u16);
+        assert_eq!(
+            keyboard.read(KEYBOARD_STATUS),
+            KEYBOARD_READY | KEYBOARD_OVERRUN
+        );
+        assert_eq!(keyboard.read(KEYBOARD_STATUS), KEYBOARD_READY);
+    }
+
+    #[test]
+    fn test_script() {
+        let events = parse_script(
+            "# scripted keys\n\
+             100 press shift\n\
+             \n\
+             50 type hi\n\
+             200 release Shift\n",
+        )
+        .unwrap();
+
+        assert_eq!(events.len(), 6);
+        assert_eq!(events[1].0, 50);
+        assert_eq!(events[4].0, 50 + 3 * TYPING_INTERVAL);
+
+        let mut keyboard = Keyboard::new();
+        keyboard.schedule_all(events);
+        tick(&mut keyboard, 150);
+        assert_eq!(drain(&mut keyboard), [0x2300 | b'h' as u16, 0x2a00]);
+
+        assert_eq!(
+            parse_script("1 press shift\n10 press hyper"),
+            Err("line 2: unknown key".to_string())
+        );
+        assert_eq!(
+            parse_script("x type a"),
+            Err("line 1: invalid cycle".to_string())
+        );
+        assert_eq!(
+            parse_script("1 hold a"),
+            Err("line 1: unknown command hold".to_string())
+        );
+    }
+}
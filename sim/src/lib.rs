@@ -0,0 +1,21 @@
+pub mod asm;
+pub mod beeper;
+pub mod board;
+pub mod bus;
+pub mod dma;
+pub mod examples;
+pub mod isa;
+pub mod keyboard;
+pub mod muldiv;
+pub mod ppu;
+pub mod procedures;
+pub mod semihosting;
+pub mod sim;
+pub mod storage;
+#[cfg(test)]
+mod testing;
+pub mod timer;
+pub mod uart;
+pub mod upload;
+
+pub const START_PC: u16 = 0x8000;
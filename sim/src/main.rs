@@ -1,395 +1,22 @@
-use std::{fmt::format, io::{Read, Write}};
-
-use asm::Assembler;
-use isa::Reg;
-use procedures::{def_division, def_is_power_of_two, def_itoa, def_print};
-use serde::Serialize;
-use serde_json::json;
-use sim::CPU;
-
-mod asm;
-mod isa;
-mod procedures;
-mod sim;
-
-const START_PC: u16 = 0x8000;
-
-fn add() -> Vec<u16> {
-    use Reg::*;
-
-    Assembler::new()
-        .set(R1, 0x23)
-        .set(R2, 0x17)
-        .add(R1, R1, R2)
-        .halt()
-        .assemble()
-}
-
-fn sub() -> Vec<u16> {
-    use Reg::*;
-
-    Assembler::new()
-        .set(R1, 0x23)
-        .set(R2, 0x17)
-        .sub(R1, R1, R2)
-        .halt()
-        .assemble()
-}
-
-fn muli() -> Vec<u16> {
-    use Reg::*;
-
-    Assembler::new()
-        .set(R2, 0x23)
-        .muli(R1, R2, 0x17)
-        .halt()
-        .assemble()
-}
-
-fn xor() -> Vec<u16> {
-    use Reg::*;
-
-    Assembler::new()
-        .set(R2, 0x23)
-        .set(R3, 0x17)
-        .xor(R1, R2, R3)
-        .halt()
-        .assemble()
-}
-
-fn dec() -> Vec<u16> {
-    use Reg::*;
-
-    Assembler::new().set(R1, 0x23).dec(R1).halt().assemble()
-}
-
-fn count() -> Vec<u16> {
-    use Reg::*;
-
-    Assembler::new()
-        .set(R1, 0xa)
-        .set(R2, 0)
-        .label("loop")
-        .dec(R1)
-        .jmpnz("loop")
-        .halt()
-        .assemble()
-}
-
-fn div() -> Vec<u16> {
-    use Reg::*;
-
-    let mut asm = Assembler::new();
-
-    asm.init_sp().set(R2, 1621).set(R3, 17).call("div").halt();
-
-    def_division(&mut asm, "div", R1, R2, R3);
-
-    asm.assemble()
-}
-
-fn add32() -> Vec<u16> {
-    use Reg::*;
-
-    Assembler::new()
-        .setw(R1, 0x1234, TMP)
-        .setw(R2, 0xbaba, TMP)
-        .setw(R3, 0x4321, TMP)
-        .setw(R4, 0x5678, TMP)
-        .add32(R1, R2, R3, R4)
-        .halt()
-        .assemble()
-}
-
-fn euler1() -> Vec<u16> {
-    use Reg::*;
-
-    // ram addresses
-    let n = 0;
-    let sum_hi = 1;
-    let sum_lo = 2;
-
-    let mut asm = Assembler::new();
-
-    asm.init_sp()
-        .store(Z, Z, sum_lo)
-        .store(Z, Z, sum_hi)
-        .setw(TMP, 1000, R1)
-        .dec(TMP)
-        .store(TMP, Z, n)
-        .label("loop")
-        .load(R1, Z, n)
-        .set(R3, 3)
-        .call("div")
-        .update_flags(R1)
-        .jmpz("is_divisible")
-        .load(R1, Z, n)
-        .set(R3, 5)
-        .call("div")
-        .update_flags(R1)
-        .jmpz("is_divisible")
-        .label("loop_back")
-        .load(R1, Z, n)
-        .dec(R1)
-        .store(R1, Z, n)
-        .jmpnz("loop")
-        .jmp("end")
-        .label("is_divisible")
-        .load(R1, Z, n)
-        .load(R2, Z, sum_hi)
-        .load(R3, Z, sum_lo)
-        .add32(R2, R3, Z, R1)
-        .store(R2, Z, sum_hi)
-        .store(R3, Z, sum_lo)
-        .jmp("loop_back")
-        .label("end")
-        .load(R1, Z, sum_hi)
-        .load(R2, Z, sum_lo)
-        .halt();
-
-    def_division(&mut asm, "div", R2, R1, R3);
-
-    asm.assemble()
-}
-
-fn lab() -> Vec<u16> {
-    use Reg::*;
-
-    let mut asm = Assembler::new();
-
-    asm.init_sp()
-        .setw(R1, 0xbaba, TMP)
-        .set(R2, 0x20)
-        .call("itoa")
-        .set(R1, 0x20)
-        .set(R2, 0)
-        .call("print")
-        .halt();
-
-    def_itoa(&mut asm);
-    def_print(&mut asm);
-
-    asm.assemble()
-}
-
-fn call() -> Vec<u16> {
-    use Reg::*;
-
-    Assembler::new()
-        .jmp("start")
-        .label("yo")
-        .set(R1, 0x23)
-        .ret()
-        .label("start")
-        .set(R1, 7)
-        .call("yo")
-        .inc(R1)
-        .halt()
-        .assemble()
-}
-
-fn mem() -> Vec<u16> {
-    use Reg::*;
-
-    Assembler::new()
-        .set(R1, 0x23)
-        .store(R1, Z, 3)
-        .set(R1, 0)
-        .load(R1, Z, 3)
-        .halt()
-        .assemble()
-}
-
-fn stack() -> Vec<u16> {
-    use Reg::*;
-
-    Assembler::new()
-        .init_sp()
-        .set(R1, 0x23)
-        .push(R1)
-        .set(R1, 0x11)
-        .pop(R1)
-        .halt()
-        .assemble()
-}
-
-fn power_of_two() -> Vec<u16> {
-    use Reg::*;
-
-    let mut asm = Assembler::new();
-
-    asm.init_sp()
-        .setw(R1, 0x80, TMP)
-        .call("is_power_of_two")
-        .halt();
-
-    def_is_power_of_two(&mut asm, "is_power_of_two", R1);
-
-    asm.assemble()
-}
-
-fn yo_fpga() -> Vec<u16> {
-    use Reg::*;
-
-    let mut asm = Assembler::new();
-
-    let message = "In computing, the reset vector is the default location a central processing unit will go to find the first instruction it will execute after a reset. The reset vector is a pointer or address, where the CPU should always begin as soon as it is able to execute instructions. The address is in a section of non-volatile memory initialized to contain instructions to start the operation of the CPU, as the first step in the process of booting the system containing the CPU.";
-
-    asm.init_sp()
-        .setw(R2, 0xfffe, TMP)
-        .store(Z, R2, 0)
-        .setw(R2, 0xffff, TMP)
-        .setw(R3, 1 << 15, TMP);
-
-    for byte in message.bytes() {
-        asm.set(R1, byte as u16).store(R1, R2, 0);
-    }
-
-    asm.halt().assemble()
-}
-
-fn itoa() -> Vec<u16> {
-    use Reg::*;
-
-    let mut asm = Assembler::new();
-
-    asm.init_sp()
-        .setw(R1, 0xbaba, TMP) // num
-        .set(R2, 0x20) // str_ptr
-        .call("itoa")
-        .halt();
-
-    def_itoa(&mut asm);
-
-    asm.assemble()
-}
-
-#[test]
-fn test_add() {
-    let mut cpu = CPU::from(&add(), START_PC);
-
-    cpu.run();
-
-    assert_eq!(cpu.regs[Reg::R1 as usize], 0x23 + 0x17);
-}
-
-#[test]
-fn test_sub() {
-    let mut cpu = CPU::from(&sub(), START_PC);
-
-    cpu.run();
-
-    assert_eq!(cpu.regs[Reg::R1 as usize], 0x23 - 0x17);
-}
-
-#[test]
-fn test_muli() {
-    let mut cpu = CPU::from(&muli(), START_PC);
-
-    cpu.run();
-
-    assert_eq!(cpu.regs[Reg::R1 as usize], 0x23 * 0x17);
-}
-
-#[test]
-fn test_xor() {
-    let mut cpu = CPU::from(&xor(), START_PC);
-
-    cpu.run();
-
-    assert_eq!(cpu.regs[Reg::R1 as usize], 0x23 ^ 0x17);
-}
-
-#[test]
-fn test_dec() {
-    let mut cpu = CPU::from(&dec(), START_PC);
-
-    cpu.run();
-
-    assert_eq!(cpu.regs[Reg::R1 as usize], 0x23 - 1);
-}
-
-#[test]
-fn test_count() {
-    let mut cpu = CPU::from(&count(), START_PC);
-
-    cpu.run();
-
-    assert_eq!(cpu.regs[Reg::R1 as usize], 0);
-}
-
-#[test]
-fn test_div() {
-    let mut cpu = CPU::from(&div(), START_PC);
-
-    cpu.run();
-
-    assert_eq!(cpu.regs[Reg::R1 as usize], 1621 / 17);
-}
-
-#[test]
-fn test_add32() {
-    let mut cpu = CPU::from(&add32(), START_PC);
-
-    cpu.run();
-
-    let sum: u32 = 0x1234_baba + 0x4321_5678;
-    assert_eq!(cpu.regs[Reg::R1 as usize], (sum >> 16) as u16);
-    assert_eq!(cpu.regs[Reg::R2 as usize], (sum & 0xffff) as u16);
-}
-
-#[test]
-fn test_mem() {
-    let mut cpu = CPU::from(&mem(), START_PC);
-
-    cpu.run();
-
-    assert_eq!(cpu.ram[3], 0x23);
-}
-
-#[test]
-fn test_euler1() {
-    let mut cpu = CPU::from(&euler1(), START_PC);
-
-    cpu.run();
-
-    assert_eq!(cpu.regs[Reg::R1 as usize], 0x0003);
-    assert_eq!(cpu.regs[Reg::R2 as usize], 0x8ed0);
-}
-
-#[test]
-fn test_stack() {
-    let mut cpu = CPU::from(&stack(), START_PC);
-
-    cpu.run();
-
-    assert_eq!(cpu.regs[Reg::R1 as usize], 0x23);
-}
-
-#[test]
-fn test_power_of_two() {
-    let mut cpu = CPU::from(&power_of_two(), START_PC);
-
-    cpu.run();
-
-    assert_eq!(cpu.regs[Reg::R1 as usize], 1);
-}
-
-#[test]
-fn test_itoa() {
-    let mut cpu = CPU::from(&itoa(), START_PC);
-
-    cpu.run();
-
-    let mut str = String::new();
-
-    for i in 0x20..0x26 {
-        str.push(char::from(cpu.ram[i] as u8));
-    }
-
-    assert_eq!(str, "47802\0");
-}
-
+use std::io::{Read, Write};
+
+use cpu16::asm::Assembler;
+use cpu16::beeper::{Beeper, BEEPER_BASE, BEEPER_END};
+use cpu16::board::{Board, Button};
+use cpu16::dma::{Dma, DMA_BASE, DMA_END};
+use cpu16::examples::{itoa, lab};
+use cpu16::isa::{MULDIV_BASE, MULDIV_END};
+use cpu16::keyboard::{self, Keyboard, KEYBOARD_BASE, KEYBOARD_END};
+use cpu16::muldiv::MulDiv;
+use cpu16::ppu::Ppu;
+use cpu16::semihosting::{Semihosting, SEMIHOSTING_BASE, SEMIHOSTING_END};
+use cpu16::sim::CPU;
+use cpu16::storage::{self, Storage, STORAGE_BASE, STORAGE_END};
+use cpu16::timer::{Timer, TIMER_BASE, TIMER_END};
+use cpu16::uart::{self, Uart, UartConfig, UART_BASE, UART_END};
+use cpu16::{isa, upload, START_PC};
+
+// cargo run -- rom <program>, prints the program as the ROM module of the design
 fn dump_instructions(prog: &[u16]) {
     println!("module ROM (");
     println!("    i_addr: input logic<16>,");
@@ -415,15 +42,49 @@ fn dump_bin(prog: &[u16], bin_paht: &str) {
     // save the raw binary to a file
     let mut rom_file = std::fs::File::create(bin_paht).expect("failed to create bin file");
     rom_file
-        .write_all(&bin.iter().flat_map(|&inst| inst.to_le_bytes()).collect::<Vec<_>>())
+        .write_all(
+            &bin.iter()
+                .flat_map(|&inst| inst.to_le_bytes())
+                .collect::<Vec<_>>(),
+        )
         .expect("failed to write to bin file");
 }
 
+// the symbols of a program loaded at START_PC, one "address name" per line
+fn dump_symbols(asm: &Assembler, sym_path: &str) {
+    let symbols: String = asm
+        .symbols(START_PC)
+        .iter()
+        .map(|(addr, name)| format!("{addr:04x} {name}\n"))
+        .collect();
+
+    std::fs::write(sym_path, symbols).expect("failed to write sym file");
+}
+
+// the example programs the CLI can build (see examples.rs)
+fn program(name: &str) -> Assembler {
+    match name {
+        "lab" => lab(),
+        "itoa" => itoa(),
+        _ => panic!("unknown program: {name}"),
+    }
+}
+
+// cargo run -- build <program> <bin file>, also writes the symbols next to it
+fn build(program_name: &str, bin_path: &str) {
+    let asm = program(program_name);
+
+    dump_bin(&asm.assemble(), bin_path);
+
+    let sym_path = std::path::Path::new(bin_path).with_extension("sym");
+    dump_symbols(&asm, sym_path.to_str().unwrap());
+}
+
+// cargo run -- trace <program> <file>, writes the state after every step as JSON lines
 fn trace(prog: &[u16], trace_path: &str) {
-    let cpu = CPU::from(&prog, START_PC);
+    let cpu = CPU::from(prog, START_PC);
 
-    let mut output_file =
-        std::fs::File::create(trace_path).expect("failed to create output file");
+    let mut output_file = std::fs::File::create(trace_path).expect("failed to create output file");
 
     for state in cpu {
         let state_json = serde_json::to_string(&state).expect("failed to serialize state");
@@ -437,14 +98,14 @@ fn trace(prog: &[u16], trace_path: &str) {
     }
 }
 
+// cargo run -- disasm <program> <file>
 fn disassemble(prog: &[u16], disasm_path: &str) {
     let disasm = prog
         .iter()
         .map(|&inst| isa::Inst::from(inst))
         .collect::<Vec<_>>();
 
-    let mut output_file =
-        std::fs::File::create(disasm_path).expect("failed to create output file");
+    let mut output_file = std::fs::File::create(disasm_path).expect("failed to create output file");
 
     for (i, inst) in disasm.iter().enumerate() {
         let inst_str = format!("{:04x}: {}", i, inst);
@@ -464,7 +125,7 @@ fn read_bin_file(bin_path: &str) -> std::io::Result<[u16; 65536]> {
     let mut file = std::fs::File::open(bin_path).expect("failed to open bin file");
     let mut temp_buffer = [0u8; 131072]; // 128kB = 131072 bytes
     file.read_exact(&mut temp_buffer)?;
-    
+
     // Convert the u8 buffer to u16 array
     for (i, chunk) in temp_buffer.chunks_exact(2).enumerate() {
         bin[i] = u16::from_le_bytes([chunk[0], chunk[1]]);
@@ -473,11 +134,274 @@ fn read_bin_file(bin_path: &str) -> std::io::Result<[u16; 65536]> {
     Ok(bin)
 }
 
+// cargo run -- upload [--framed] <bin file> [serial port]
+// Top.veryl only receives the plain byte stream, the framed protocol is only
+// understood by the simulated board (see `board --pty --framed`), on a pty.
+fn upload_bin(bin_path: &str, port_path: &str, framed: bool) {
+    assert!(
+        !framed || port_path.starts_with("/dev/pts/"),
+        "upload --framed: {port_path} is not a pty, Top.veryl has no framed receiver"
+    );
+
+    // binaries are stored low byte first, the uploader sends the high byte first
+    let bin = read_bin_file(bin_path).expect("failed to read bin file");
+    let mut port = upload::open_serial(port_path, upload::BAUD_RATE)
+        .unwrap_or_else(|err| panic!("failed to open {port_path}: {err}"));
+
+    let result = if framed {
+        upload::upload_framed(&mut port, 0, &bin, |sent| {
+            print!("\rtransferred {sent}/{} words", bin.len());
+            std::io::stdout().flush().unwrap();
+        })
+    } else {
+        upload::upload(&mut port, &bin, |sent| {
+            print!("\rtransferred {sent}/{} bytes", 2 * bin.len());
+            std::io::stdout().flush().unwrap();
+        })
+    };
+
+    println!();
+    result.expect("failed to upload");
+}
+
+// cargo run -- run <bin file> [--pty] [--keys <script>] [--disk <image>] [--wav <file>]
+//     [--warn-code-writes]
+// runs a program with its UART connected to the terminal or to a pseudo-terminal,
+// and exits with the exit code of the program if it reported one (see semihosting.rs).
+// Key events come from a script (see keyboard.rs), or from the terminal if the UART is on a pty.
+// The image backs the block storage (see storage.rs), and the beeper is recorded to the WAV file.
+fn run_bin(bin_path: &str, options: &[&str]) {
+    let (mut pty, mut keys, mut disk, mut wav) = (false, None, None, None);
+    let mut warn_on_code_writes = false;
+    let mut options = options.iter();
+
+    while let Some(&option) = options.next() {
+        match option {
+            "--pty" => pty = true,
+            "--keys" => keys = Some(*options.next().expect("--keys expects a script")),
+            "--disk" => disk = Some(*options.next().expect("--disk expects an image")),
+            "--wav" => wav = Some(*options.next().expect("--wav expects a file")),
+            "--warn-code-writes" => warn_on_code_writes = true,
+            _ => panic!("unknown option {option}"),
+        }
+    }
+
+    let bin = read_bin_file(bin_path).expect("failed to read bin file");
+    let mut cpu = CPU::new(bin, START_PC);
+    cpu.warn_on_code_writes = warn_on_code_writes;
+
+    let uart = if pty {
+        let (uart, path) = Uart::pty(UartConfig::default()).expect("failed to open a pty");
+        println!("UART connected to {path}");
+        uart
+    } else {
+        Uart::stdio(UartConfig::default())
+    };
+
+    let keyboard = match keys {
+        Some(path) => {
+            let script = std::fs::read_to_string(path).expect("failed to read the key script");
+            let mut keyboard = Keyboard::new();
+            keyboard.schedule_all(
+                keyboard::parse_script(&script).unwrap_or_else(|err| panic!("{path}: {err}")),
+            );
+            keyboard
+        }
+        None if pty => Keyboard::stdin(),
+        None => Keyboard::new(),
+    };
+
+    cpu.bus.map("uart", UART_BASE..=UART_END, uart).map(
+        "semihosting",
+        SEMIHOSTING_BASE..=SEMIHOSTING_END,
+        Semihosting::new(),
+    );
+    cpu.bus.map("timer", TIMER_BASE..=TIMER_END, Timer::new());
+    cpu.bus
+        .map("keyboard", KEYBOARD_BASE..=KEYBOARD_END, keyboard);
+
+    if let Some(path) = disk {
+        let storage = Storage::open(path).unwrap_or_else(|err| panic!("{path}: {err}"));
+        cpu.bus.map("storage", STORAGE_BASE..=STORAGE_END, storage);
+    }
+
+    cpu.bus
+        .map("beeper", BEEPER_BASE..=BEEPER_END, Beeper::new())
+        .map("dma", DMA_BASE..=DMA_END, Dma::new());
+
+    if cfg!(feature = "muldiv") {
+        cpu.bus
+            .map("muldiv", MULDIV_BASE..=MULDIV_END, MulDiv::default());
+    }
+
+    let exit_code = cpu.run();
+    cpu.bus.device_mut::<Uart>("uart").unwrap().flush();
+
+    for warning in &cpu.warnings {
+        eprintln!("warning: {warning}");
+    }
+
+    if let Some(path) = wav {
+        let beeper = cpu.bus.device::<Beeper>("beeper").unwrap();
+        beeper.save_wav(path).expect("failed to save the WAV file");
+    }
+
+    // exit skips destructors, the keyboard restores the terminal when dropped
+    drop(cpu);
+
+    if let Some(code) = exit_code {
+        std::process::exit(code as i32);
+    }
+}
+
+// cargo run -- board [<bin file>] [--pty [--framed]] [--switches <hex>] [--fuel <steps>]
+// runs a program on the board of Top.veryl (see board.rs) until it halts,
+// and prints what the LEDs and the seven-segment display show.
+// With --pty the board starts in program mode and the program is received from
+// a pseudo-terminal, which `upload` can be pointed at, over the bin file if any.
+// With --framed it is received with the framed protocol of `upload --framed`.
+fn run_board(options: &[&str]) {
+    let (mut bin_path, mut pty, mut framed) = (None, false, false);
+    let (mut switches, mut fuel) = (0, 1_000_000);
+    let mut options = options.iter();
+
+    while let Some(&option) = options.next() {
+        match option {
+            "--pty" => pty = true,
+            "--framed" => framed = true,
+            "--switches" => {
+                let arg = options.next().expect("--switches expects a value");
+                switches = u16::from_str_radix(arg.trim_start_matches("0x"), 16)
+                    .unwrap_or_else(|_| panic!("invalid switches {arg}"));
+            }
+            "--fuel" => {
+                let arg = options.next().expect("--fuel expects a number of steps");
+                fuel = arg
+                    .parse()
+                    .unwrap_or_else(|_| panic!("invalid number {arg}"));
+            }
+            _ if !option.starts_with("--") && bin_path.is_none() => bin_path = Some(option),
+            _ => panic!("unknown option {option}"),
+        }
+    }
+
+    let bin = match bin_path {
+        Some(path) => read_bin_file(path).expect("failed to read bin file"),
+        None if pty => [0; 65536],
+        None => panic!("board expects a bin file or --pty"),
+    };
+    assert!(!framed || pty, "--framed expects --pty");
+    let cpu = CPU::new(bin, START_PC);
+    let mut board = if framed {
+        Board::framed(cpu)
+    } else {
+        Board::new(cpu)
+    };
+
+    if pty {
+        receive_program(&mut board);
+        // back to the execute mode and out of reset, like after prog.py
+        board.click(Button::Right);
+    }
+
+    board.set_switches(switches);
+
+    if !board.run(fuel) {
+        println!("not halted after {fuel} steps");
+    }
+
+    print_board(&board);
+}
+
+// receives a program in program mode until the uploader closes the pty
+fn receive_program(board: &mut Board) {
+    let (mut master, slave, path) = uart::open_pty().expect("failed to open a pty");
+    println!("program mode, upload to {path}");
+    board.set_switch(0, true);
+
+    // reads from the master fail once the slave has no open end, the one
+    // opened here is only kept until the uploader opens its own
+    let mut first = [0];
+    master
+        .read_exact(&mut first)
+        .expect("failed to receive the program");
+    drop(slave);
+    master
+        .write_all(&board.receive(first[0]))
+        .expect("failed to reply");
+
+    let received = 1 + board
+        .receive_from(&mut master)
+        .expect("failed to receive the program");
+    println!("received {received} bytes");
+}
+
+fn print_board(board: &Board) {
+    let status = board.status_led();
+
+    println!("mode    {:?}", board.mode());
+    println!("leds    {:016b}", board.leds());
+    println!("display {:08x}", board.display());
+    println!(
+        "status  red {} green {} blue {}",
+        status.red as u8, status.green as u8, status.blue as u8
+    );
+}
+
+// cargo run -- image create <image> <sectors>
+// cargo run -- image write <image> <sector> <file>
+// cargo run -- image dump <image> <sector>
+// creates and inspects block storage images (see storage.rs)
+fn image(command: &str, path: &str, arg: &str, file: Option<&str>) -> std::io::Result<()> {
+    let num: u32 = arg
+        .parse()
+        .unwrap_or_else(|_| panic!("invalid number {arg}"));
+
+    match (command, file) {
+        ("create", None) => storage::create_image(path, num),
+        ("write", Some(file)) => storage::write_image(path, num, &std::fs::read(file)?),
+        ("dump", None) => {
+            print!("{}", storage::dump_sector(path, num)?);
+            Ok(())
+        }
+        _ => panic!("unknown image command {command}"),
+    }
+}
+
 fn main() {
-    // let prog = lab();
-    // dump_bin(&prog, "lab.bin");
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["upload", "--framed", bin_path] => {
+            return upload_bin(bin_path, upload::DEFAULT_PORT, true)
+        }
+        ["upload", "--framed", bin_path, port_path] => {
+            return upload_bin(bin_path, port_path, true)
+        }
+        ["image", command, path, arg] => return image(command, path, arg, None).unwrap(),
+        ["image", command, path, arg, file] => {
+            return image(command, path, arg, Some(file)).unwrap()
+        }
+        ["build", name, bin_path] => return build(name, bin_path),
+        ["trace", name, path] => return trace(&program(name).assemble(), path),
+        ["disasm", name, path] => return disassemble(&program(name).assemble(), path),
+        ["rom", name] => return dump_instructions(&program(name).assemble()),
+        ["run", bin_path, ref options @ ..] => return run_bin(bin_path, options),
+        ["board", ref options @ ..] => return run_board(options),
+        ["upload", bin_path] => return upload_bin(bin_path, upload::DEFAULT_PORT, false),
+        ["upload", bin_path, port_path] => return upload_bin(bin_path, port_path, false),
+        _ => {}
+    }
+
     let prog = read_bin_file("../lang/out.bin").expect("failed to read bin file");
 
     let mut cpu = CPU::new(prog, START_PC);
     cpu.run_with_fuel(1000, true);
+
+    // cargo run -- screen.png (or .ppm)
+    if let Some(path) = args.first() {
+        let ppu = cpu.bus.device::<Ppu>("ppu").unwrap();
+        ppu.screenshot(path).expect("failed to save the screenshot");
+        print!("{}", ppu.text_dump());
+    }
 }
@@ -0,0 +1,269 @@
+use crate::bus::{Device, Machine};
+use crate::isa::{
+    MULDIV_A, MULDIV_B, MULDIV_BUSY, MULDIV_CTRL, MULDIV_DIV, MULDIV_HI, MULDIV_LO, MULDIV_MUL,
+    MULDIV_STATUS,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MulDivConfig {
+    pub mul_cycles: u64,
+    pub div_cycles: u64,
+}
+
+impl Default for MulDivConfig {
+    // a pipelined DSP multiplier, and a divider computing a bit per cycle
+    fn default() -> Self {
+        MulDivConfig {
+            mul_cycles: 3,
+            div_cycles: 17,
+        }
+    }
+}
+
+// A multiply/divide coprocessor, see `Assembler::use_muldiv` for the
+// pseudo-instructions using it.
+pub struct MulDiv {
+    config: MulDivConfig,
+    a: u16,
+    b: u16,
+    lo: u16,
+    hi: u16,
+    // the results of the operation in progress and the cycles it has left
+    pending: Option<(u16, u16, u64)>,
+}
+
+impl MulDiv {
+    pub fn new(config: MulDivConfig) -> Self {
+        MulDiv {
+            config,
+            a: 0,
+            b: 0,
+            lo: 0,
+            hi: 0,
+            pending: None,
+        }
+    }
+
+    fn start(&mut self, op: u16) {
+        let (a, b) = (self.a, self.b);
+
+        self.pending = match op {
+            MULDIV_MUL => {
+                let product = a as u32 * b as u32;
+                Some((
+                    product as u16,
+                    (product >> 16) as u16,
+                    self.config.mul_cycles,
+                ))
+            }
+            MULDIV_DIV => match a.checked_div(b) {
+                Some(quotient) => Some((quotient, a % b, self.config.div_cycles)),
+                None => Some((0xffff, a, self.config.div_cycles)),
+            },
+            _ => None,
+        };
+    }
+}
+
+impl Default for MulDiv {
+    fn default() -> Self {
+        MulDiv::new(MulDivConfig::default())
+    }
+}
+
+impl Device for MulDiv {
+    fn read(&mut self, offset: u16) -> u16 {
+        match offset {
+            MULDIV_A => self.a,
+            MULDIV_B => self.b,
+            MULDIV_STATUS if self.pending.is_some() => MULDIV_BUSY,
+            MULDIV_LO => self.lo,
+            MULDIV_HI => self.hi,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset: u16, val: u16) {
+        match offset {
+            MULDIV_A => self.a = val,
+            MULDIV_B => self.b = val,
+            MULDIV_CTRL if self.pending.is_none() => self.start(val),
+            _ => {}
+        }
+    }
+
+    // the operation starts with the cycles of the store which started it
+    fn tick(&mut self, cycles: u64, _machine: &mut Machine) {
+        let Some((lo, hi, left)) = self.pending else {
+            return;
+        };
+
+        if left > cycles {
+            self.pending = Some((lo, hi, left - cycles));
+        } else {
+            (self.lo, self.hi) = (lo, hi);
+            self.pending = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::Assembler;
+    use crate::isa::{Reg, MULDIV_BASE, MULDIV_END};
+    use crate::procedures::arith::{def_divu16, def_mulu16};
+    use crate::sim::CPU;
+    use crate::testing::{call_with, reg, tick};
+    use crate::START_PC;
+    use Reg::*;
+
+    fn map_muldiv(cpu: &mut CPU) {
+        cpu.bus
+            .map("muldiv", MULDIV_BASE..=MULDIV_END, MulDiv::default());
+    }
+
+    // runs `emit` with R1 = a and R2 = b, returns (R1, R3, cycles)
+    fn run(muldiv: bool, a: u16, b: u16, emit: impl Fn(&mut Assembler)) -> (u16, u16, u64) {
+        let mut asm = Assembler::new();
+        asm.use_muldiv(muldiv).setw(R1, a, TMP).setw(R2, b, TMP);
+
+        let start = asm.len();
+        emit(&mut asm);
+        let instructions = asm.len() - start;
+        asm.halt();
+
+        let mut cpu = CPU::from(&asm.assemble(), START_PC);
+        map_muldiv(&mut cpu);
+
+        // run the setup, then the emitted code
+        let setup = asm.len() - instructions - 1;
+        (0..setup).for_each(|_| cpu.step());
+        let before = cpu.cycles;
+        cpu.run();
+
+        (reg(&cpu, R1), reg(&cpu, R3), cpu.cycles - before)
+    }
+
+    #[test]
+    fn test_device() {
+        let mut muldiv = MulDiv::new(MulDivConfig {
+            mul_cycles: 2,
+            div_cycles: 5,
+        });
+
+        muldiv.write(MULDIV_A, 0x1234);
+        muldiv.write(MULDIV_B, 0x100);
+        muldiv.write(MULDIV_CTRL, MULDIV_MUL);
+        assert_eq!(muldiv.read(MULDIV_STATUS), MULDIV_BUSY);
+        tick(&mut muldiv, 1);
+        assert_eq!(muldiv.read(MULDIV_LO), 0, "not done yet");
+        tick(&mut muldiv, 1);
+        assert_eq!(muldiv.read(MULDIV_STATUS), 0);
+        assert_eq!(
+            (muldiv.read(MULDIV_HI), muldiv.read(MULDIV_LO)),
+            (0x12, 0x3400)
+        );
+
+        muldiv.write(MULDIV_CTRL, MULDIV_DIV);
+        muldiv.write(MULDIV_CTRL, MULDIV_MUL);
+        tick(&mut muldiv, 5);
+        assert_eq!(
+            (muldiv.read(MULDIV_LO), muldiv.read(MULDIV_HI)),
+            (0x12, 0x34)
+        );
+
+        muldiv.write(MULDIV_B, 0);
+        muldiv.write(MULDIV_CTRL, MULDIV_DIV);
+        tick(&mut muldiv, 5);
+        assert_eq!(
+            (muldiv.read(MULDIV_LO), muldiv.read(MULDIV_HI)),
+            (0xffff, 0x1234)
+        );
+    }
+
+    #[test]
+    fn test_pseudo_ops() {
+        for muldiv in [false, true] {
+            for (a, b) in [(0, 5), (7, 0), (1234, 56), (0xffff, 0xffff), (3, 0x8000)] {
+                let (product, _, _) = run(muldiv, a, b, |asm| {
+                    asm.mul(R3, R1, R2).mov(R1, R3);
+                });
+                assert_eq!(product, a.wrapping_mul(b), "{a} * {b}");
+
+                let (rem, quot, _) = run(muldiv, a, b, |asm| {
+                    asm.divu(R3, R1, R2);
+                });
+                let expected = a.checked_div(b).map_or((0xffff, a), |q| (q, a % b));
+                assert_eq!((quot, rem), expected, "{a} / {b}");
+            }
+
+            for n in [0, 1, 8, 0x17, 0x3ff, 0xffff] {
+                let (product, _, _) = run(muldiv, 0x23, 0, |asm| {
+                    asm.muli(R3, R1, n).mov(R1, R3);
+                });
+                assert_eq!(product, 0x23u16.wrapping_mul(n), "0x23 * {n}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_muli2_tmp() {
+        // the coprocessor path only clobbers the given tmp register
+        let (product, tmp, _) = run(true, 0x23, 0, |asm| {
+            asm.set(TMP, 7)
+                .muli2(R3, R1, 0x3ff, R4)
+                .mov(R1, R3)
+                .mov(R3, TMP);
+        });
+
+        assert_eq!((product, tmp), (0x23u16.wrapping_mul(0x3ff), 7));
+    }
+
+    // cycle counts of the software routines and pseudo-ops against the coprocessor,
+    // run with --nocapture to see them
+    #[test]
+    fn test_cycles() {
+        let (a, b) = (50_000, 7);
+
+        let (_, mulu16) = call_with(&[def_mulu16], "mulu16", &[(R1, a), (R2, b)], |_| {});
+        let (_, divu16) = call_with(&[def_divu16], "divu16", &[(R1, a), (R2, b)], |_| {});
+        let mul = |muldiv| {
+            run(muldiv, a, b, |asm| {
+                asm.mul(R3, R1, R2);
+            })
+            .2
+        };
+        let divu = |muldiv| {
+            run(muldiv, a, b, |asm| {
+                asm.divu(R3, R1, R2);
+            })
+            .2
+        };
+        let muli = |muldiv| {
+            run(muldiv, a, 0, |asm| {
+                asm.muli(R3, R1, 0x3ff);
+            })
+            .2
+        };
+
+        let rows = [
+            ("mul", Some(mulu16), mul(false), mul(true)),
+            ("div", Some(divu16), divu(false), divu(true)),
+            ("muli 0x3ff", None, muli(false), muli(true)),
+        ];
+
+        println!(
+            "{:<12}{:>10}{:>10}{:>10}",
+            "", "routine", "software", "muldiv"
+        );
+
+        for (name, routine, software, muldiv) in rows {
+            let routine = routine.map_or("-".to_string(), |cycles| cycles.to_string());
+            println!("{name:<12}{routine:>10}{software:>10}{muldiv:>10}");
+            assert!(muldiv < software, "{name}: {muldiv} >= {software}");
+        }
+
+        assert!(mul(true) < mulu16 && divu(true) < divu16);
+    }
+}
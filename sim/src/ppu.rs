@@ -0,0 +1,280 @@
+use crate::bus::Device;
+use std::io::Write;
+use std::path::Path;
+
+pub const TILES_X: usize = 80;
+pub const TILES_Y: usize = 60;
+pub const TILE_SIZE: usize = 8;
+pub const WIDTH: usize = TILES_X * TILE_SIZE;
+pub const HEIGHT: usize = TILES_Y * TILE_SIZE;
+
+// 8x8 1-bit glyphs of the characters starting at 0x20 (ASCII),
+// each row is a byte with the leftmost pixel in the msb, top row first
+const PATTERN_TABLE_HEX: &str = include_str!("../../design/src/patternTable.hex");
+const FIRST_CHAR: u8 = 0x20;
+
+// PPU name table ports of Top.veryl, mapped at 0xfffe:
+// writing to 0xfffe selects a tile (13 bits), and every write to 0xffff
+// stores a character in the selected tile and moves to the next one.
+// The ports are backed by RAM on the board, so reads return the last value written.
+pub struct Ppu {
+    pub name_table: Box<[u8; TILES_X * TILES_Y]>,
+    pub tile_index: u16,
+    ports: [u16; 2],
+}
+
+impl Ppu {
+    pub fn new() -> Self {
+        Ppu {
+            name_table: Box::new([0; TILES_X * TILES_Y]),
+            tile_index: 0,
+            ports: [0; 2],
+        }
+    }
+
+    // the pattern of every character, indexed like PPU.veryl does:
+    // blank characters and characters past the end of the pattern table
+    // render as empty tiles
+    fn patterns() -> [u64; 256] {
+        let table: Vec<u64> = PATTERN_TABLE_HEX
+            .lines()
+            .map(|line| u64::from_str_radix(line.trim(), 16).expect("invalid pattern table"))
+            .collect();
+
+        std::array::from_fn(|c| match (c as u8).checked_sub(FIRST_CHAR) {
+            Some(index) if index > 0 => table.get(index as usize).copied().unwrap_or(0),
+            _ => 0,
+        })
+    }
+
+    // one byte per pixel, 0 (black) or 255 (white), row by row
+    pub fn render(&self) -> Vec<u8> {
+        let patterns = Self::patterns();
+        let mut frame = vec![0; WIDTH * HEIGHT];
+
+        for (tile, &c) in self.name_table.iter().enumerate() {
+            let pattern = patterns[c as usize];
+            let x0 = (tile % TILES_X) * TILE_SIZE;
+            let y0 = (tile / TILES_X) * TILE_SIZE;
+
+            for sub_y in 0..TILE_SIZE {
+                let row = (pattern >> (8 * (7 - sub_y))) as u8;
+
+                for sub_x in 0..TILE_SIZE {
+                    if row & (0x80 >> sub_x) != 0 {
+                        frame[(y0 + sub_y) * WIDTH + x0 + sub_x] = 0xff;
+                    }
+                }
+            }
+        }
+
+        frame
+    }
+
+    // binary RGB PPM (P6)
+    pub fn to_ppm(&self) -> Vec<u8> {
+        let mut ppm = format!("P6\n{WIDTH} {HEIGHT}\n255\n").into_bytes();
+        ppm.extend(self.render().into_iter().flat_map(|pixel| [pixel; 3]));
+        ppm
+    }
+
+    // 1-bit greyscale PNG, the image data is stored uncompressed
+    pub fn to_png(&self) -> Vec<u8> {
+        let frame = self.render();
+        let mut scanlines = Vec::with_capacity(HEIGHT * (1 + WIDTH / 8));
+
+        for row in frame.chunks_exact(WIDTH) {
+            scanlines.push(0); // no filter
+            scanlines.extend(row.chunks_exact(8).map(|pixels| {
+                pixels
+                    .iter()
+                    .fold(0u8, |byte, &pixel| (byte << 1) | (pixel & 1))
+            }));
+        }
+
+        let mut ihdr = Vec::new();
+        ihdr.extend((WIDTH as u32).to_be_bytes());
+        ihdr.extend((HEIGHT as u32).to_be_bytes());
+        ihdr.extend([1, 0, 0, 0, 0]); // bit depth, greyscale, deflate, no filter, no interlace
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png_chunk(&mut png, b"IHDR", &ihdr);
+        png_chunk(&mut png, b"IDAT", &zlib_stored(&scanlines));
+        png_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+
+    // PNG or PPM depending on the extension of `path`
+    pub fn screenshot(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let path = path.as_ref();
+        let image = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("png") => self.to_png(),
+            _ => self.to_ppm(),
+        };
+
+        std::fs::File::create(path)?.write_all(&image)
+    }
+
+    // the tile grid as text, one line per row of tiles,
+    // tiles that render as blank are spaces and other non-ASCII glyphs are '?'
+    pub fn text_dump(&self) -> String {
+        let patterns = Self::patterns();
+        let mut text = String::with_capacity((TILES_X + 1) * TILES_Y);
+
+        for row in self.name_table.chunks_exact(TILES_X) {
+            for &c in row {
+                text.push(match c {
+                    _ if patterns[c as usize] == 0 => ' ',
+                    0x21..=0x7e => c as char,
+                    _ => '?',
+                });
+            }
+
+            text.push('\n');
+        }
+
+        text
+    }
+}
+
+fn png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    png.extend(kind);
+    png.extend(data);
+
+    let crc = kind.iter().chain(data).fold(!0u32, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| {
+            if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    });
+
+    png.extend((!crc).to_be_bytes());
+}
+
+// zlib stream made of uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xffff).peekable();
+
+    while let Some(block) = blocks.next() {
+        zlib.push(blocks.peek().is_none() as u8); // BFINAL, BTYPE = 00
+        zlib.extend((block.len() as u16).to_le_bytes());
+        zlib.extend((!(block.len() as u16)).to_le_bytes());
+        zlib.extend(block);
+    }
+
+    let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), &byte| {
+        let a = (a + byte as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+
+    zlib.extend(((b << 16) | a).to_be_bytes());
+    zlib
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Device for Ppu {
+    fn read(&mut self, offset: u16) -> u16 {
+        self.ports[offset as usize]
+    }
+
+    fn write(&mut self, offset: u16, val: u16) {
+        self.ports[offset as usize] = val;
+
+        if offset == 0 {
+            self.tile_index = val & 0x1fff;
+        } else {
+            // writes past the last tile are dropped by the hardware
+            if let Some(tile) = self.name_table.get_mut(self.tile_index as usize) {
+                *tile = val as u8;
+            }
+
+            self.tile_index = (self.tile_index + 1) & 0x1fff;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn print(ppu: &mut Ppu, tile: u16, text: &str) {
+        ppu.write(0, tile);
+
+        for byte in text.bytes() {
+            ppu.write(1, byte as u16);
+        }
+    }
+
+    #[test]
+    fn test_name_table() {
+        let mut ppu = Ppu::new();
+        print(&mut ppu, 78, "abc");
+
+        assert_eq!(&ppu.name_table[78..81], b"abc");
+        assert_eq!(ppu.tile_index, 81);
+        assert_eq!(ppu.read(1), b'c' as u16);
+
+        // past the last tile
+        print(&mut ppu, 0x1fff, "xy");
+        assert_eq!(ppu.tile_index, 1);
+        assert_eq!(ppu.name_table[0], b'y');
+    }
+
+    #[test]
+    fn test_render() {
+        let mut ppu = Ppu::new();
+        print(&mut ppu, TILES_X as u16 + 1, "!");
+        let frame = ppu.render();
+
+        // '!' is 30 78 78 30 30 00 30 00
+        let glyph: Vec<u8> = (8..16)
+            .map(|y| {
+                frame[y * WIDTH + 8..y * WIDTH + 16]
+                    .iter()
+                    .fold(0, |byte, &pixel| (byte << 1) | (pixel & 1))
+            })
+            .collect();
+
+        assert_eq!(glyph, [0x30, 0x78, 0x78, 0x30, 0x30, 0x00, 0x30, 0x00]);
+        assert_eq!(frame.iter().filter(|&&p| p != 0).count(), 16);
+    }
+
+    #[test]
+    fn test_text_dump() {
+        let mut ppu = Ppu::new();
+        print(&mut ppu, 0, "Hello, world!");
+        print(&mut ppu, TILES_X as u16 * 2 + 3, "\0\x7f\n");
+
+        let text = ppu.text_dump();
+        let lines: Vec<&str> = text.lines().collect();
+
+        assert_eq!(lines.len(), TILES_Y);
+        assert!(lines.iter().all(|line| line.len() == TILES_X));
+        assert_eq!(lines[0].trim_end(), "Hello, world!");
+        assert_eq!(lines[2].trim_end(), "    ?");
+    }
+
+    #[test]
+    fn test_image_headers() {
+        let ppu = Ppu::new();
+
+        let ppm = ppu.to_ppm();
+        assert!(ppm.starts_with(b"P6\n640 480\n255\n"));
+        assert_eq!(ppm.len(), 15 + 3 * WIDTH * HEIGHT);
+
+        let png = ppu.to_png();
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR"));
+        // the IEND chunk has a well-known CRC
+        assert!(png.ends_with(&[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82]));
+    }
+}
@@ -1,6 +1,23 @@
 use crate::asm::Assembler;
 use crate::isa::Reg;
 
+pub mod arith;
+pub mod arith32;
+pub mod beeper;
+pub mod console;
+pub mod dma;
+pub mod fixed;
+pub mod hash;
+pub mod heap;
+pub mod interrupts;
+pub mod keyboard;
+pub mod random;
+pub mod semihosting;
+pub mod storage;
+pub mod string;
+pub mod timer;
+pub mod uart;
+
 // dst -> a // b, a -> a % b
 pub fn def_division(asm: &mut Assembler, procedure_name: &str, dst: Reg, a: Reg, b: Reg) {
     asm.label(procedure_name)
@@ -79,35 +96,36 @@ pub fn def_itoa(asm: &mut Assembler) {
 
     asm.label("itoa");
 
-    // variable addresses in RAM
-    let num = 100;
-    let str_ptr = 101;
-    let powers_of_10 = 102;
+    // static variables
+    let num = "itoa_num";
+    let str_ptr = "itoa_str_ptr";
+    let powers_of_10 = "itoa_powers_of_10";
+
+    asm.static_var(num, 1);
+    asm.static_var(str_ptr, 1);
+    asm.static_var(powers_of_10, 5);
 
     // store the arguments to RAM
-    asm.store(R1, Z, num);
-    asm.store(R2, Z, str_ptr);
+    asm.store_var(R1, num);
+    asm.store_var(R2, str_ptr);
 
     // powers of 10 LUT
-    asm.set(R1, powers_of_10);
     asm.setw(R2, 10_000, TMP);
-    asm.store(R2, R1, 0);
+    asm.store_var_at(R2, Z, powers_of_10, 0);
     asm.set(R2, 1000);
-    asm.store(R2, R1, 1);
+    asm.store_var_at(R2, Z, powers_of_10, 1);
     asm.set(R2, 100);
-    asm.store(R2, R1, 2);
+    asm.store_var_at(R2, Z, powers_of_10, 2);
     asm.set(R2, 10);
-    asm.store(R2, R1, 3);
+    asm.store_var_at(R2, Z, powers_of_10, 3);
     asm.setw(R2, 1, TMP);
-    asm.store(R2, R1, 4);
-
-    asm.load(R1, Z, powers_of_10 as u8);
+    asm.store_var_at(R2, Z, powers_of_10, 4);
 
     // check if num is zero
-    asm.load(R1, Z, num);
+    asm.load_var(R1, num);
     asm.cmp(R1, Z);
     asm.jump_if_ne("itoa_not_zero");
-    asm.load(R2, Z, str_ptr);
+    asm.load_var(R2, str_ptr);
     asm.set(TMP, b'0' as u16);
     asm.store(TMP, R2, 0);
     asm.store(Z, R2, 1); // null terminator
@@ -123,13 +141,13 @@ pub fn def_itoa(asm: &mut Assembler) {
     asm.jump_if_eq("itoa_end_main_loop");
     asm.set(R4, 0); // count
     asm.label("itoa_while_num_ge_power");
-    asm.load(R3, R1, powers_of_10 as u8); // power = powersOf10[i]
-    asm.load(TMP, Z, num);
+    asm.load_var_at(R3, R1, powers_of_10, 0); // power = powersOf10[i]
+    asm.load_var(TMP, num);
     asm.cmp(TMP, R3);
     asm.jmpnc("itoa_end_while_num_ge_power");
-    asm.load(TMP, Z, num);
+    asm.load_var(TMP, num);
     asm.sub(TMP, TMP, R3);
-    asm.store(TMP, Z, num);
+    asm.store_var(TMP, num);
     asm.inc(R4);
     asm.jmp("itoa_while_num_ge_power");
 
@@ -145,7 +163,7 @@ pub fn def_itoa(asm: &mut Assembler) {
 
     asm.label("itoa_append_digit");
     // str[pos] = digits[count];
-    asm.load(R3, Z, str_ptr);
+    asm.load_var(R3, str_ptr);
     asm.add(R3, R3, R2); // str_ptr + pos
     asm.set(TMP, 0x30); // 0 ascii
     asm.add(TMP, TMP, R4);
@@ -156,7 +174,7 @@ pub fn def_itoa(asm: &mut Assembler) {
     asm.label("itoa_end_main_loop");
 
     // add null terminator
-    asm.load(R1, Z, str_ptr);
+    asm.load_var(R1, str_ptr);
     asm.add(R1, R1, R2); // str_ptr + pos
     asm.store(Z, R1, 0);
 
@@ -164,34 +182,26 @@ pub fn def_itoa(asm: &mut Assembler) {
 }
 
 // R1: str pointer, R2: first tile index
+// writes a string to the PPU name table, see console.rs for a console with a cursor
 pub fn def_print(asm: &mut Assembler) {
     use Reg::*;
 
-    let char = 100;
-
     asm.label("print");
-    // R4 = 0xffff (PPU address)
+    // R4 = 0xfffe: a write there sets the tile address, each write to 0xffff
+    // stores a tile there and increments the address (see Top.veryl)
     asm.set(R4, 0);
     asm.dec(R4);
+    asm.dec(R4);
+    asm.store(R2, R4, 0);
 
     asm.label("print_loop");
     // check if null terminator
     asm.load(R3, R1, 0);
-    asm.store(R3, Z, char);
     asm.cmp(R3, Z);
     asm.jmpz("print_end");
 
-    // write to PPU
-
-    // set tile index
-    asm.setw(R3, 0x8000, TMP);
-    asm.add(R3, R3, R2);
-    asm.store(R3, R4, 0);
-    asm.inc(R2);
-
-    // set tile data
-    asm.load(R3, Z, char);
-    asm.store(R3, R4, 0);
+    // write to PPU, the tile index is incremented by the hardware
+    asm.store(R3, R4, 1);
     asm.inc(R1);
     asm.jmp("print_loop");
 
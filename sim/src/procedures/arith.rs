@@ -0,0 +1,161 @@
+use crate::asm::Assembler;
+use crate::isa::{Cond, Reg};
+
+// The routines below are fully unrolled and branch-free, so they always take
+// the same number of cycles regardless of their inputs (call overhead excluded,
+// divisions by zero take a shortcut).
+// Unless stated otherwise, R1-R4 and TMP are caller-saved.
+
+// R1: dividend, R2: divisor -> R1: quotient, R2: remainder
+// division by zero yields a quotient of 0xffff and leaves the dividend as the remainder
+// 464 cycles, clobbers R3
+pub fn def_divu16(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("divu16").set(R3, 0); // remainder
+
+    for _ in 0..16 {
+        asm.add(R1, R1, R1).adc(R3, R3, R3);
+        asm.restoring_div_step(R1, R3, R2);
+    }
+
+    asm.mov(R2, R3).ret();
+}
+
+// R1: dividend hi, R2: dividend lo, R3: divisor
+// -> R1: quotient hi, R2: quotient lo, R3: remainder
+// division by zero yields a quotient of 0xffffffff and leaves the dividend lo as the remainder
+// 1051 cycles, clobbers R4
+pub fn def_divu32(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("divu32")
+        // unlike divu16, the remainder would keep growing past 17 bits
+        .update_flags(R3)
+        .jmpz("divu32_by_zero")
+        .set(R4, 0); // remainder
+
+    for _ in 0..32 {
+        asm.add(R2, R2, R2).adc(R1, R1, R1).adc(R4, R4, R4);
+        asm.restoring_div_step(R2, R4, R3);
+    }
+
+    asm.mov(R3, R4).ret();
+
+    asm.label("divu32_by_zero")
+        .mov(R3, R2)
+        .dec2(R1, Z)
+        .mov(R2, R1)
+        .ret();
+}
+
+// R1: a, R2: b -> R1: (a * b) hi, R2: (a * b) lo
+// 343 cycles, clobbers R3, R4
+pub fn def_mulu16(asm: &mut Assembler) {
+    use Reg::*;
+
+    let hi = R3;
+    let lo = R4;
+
+    asm.label("mulu16").set(hi, 0).set(lo, 0);
+
+    // shift-and-add, starting from the msb of b
+    for _ in 0..16 {
+        asm.add(lo, lo, lo)
+            .adc(hi, hi, hi)
+            .add(R2, R2, R2) // carry = next bit of b
+            .add_if(lo, lo, R1, Cond::IfCarry)
+            .adc(hi, hi, Z);
+    }
+
+    asm.mov(R1, hi).mov(R2, lo).ret();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{call, reg, Rng};
+    use Reg::*;
+
+    const EDGE_CASES: [u16; 8] = [0, 1, 2, 3, 0x7fff, 0x8000, 0xfffe, 0xffff];
+
+    fn check_divu16(n: u16, d: u16) -> u64 {
+        let (cpu, cycles) = call(&[def_divu16], "divu16", &[(R1, n), (R2, d)]);
+        let (q, r) = n.checked_div(d).map_or((0xffff, n), |q| (q, n % d));
+
+        assert_eq!((reg(&cpu, R1), reg(&cpu, R2)), (q, r), "{n} / {d}");
+        cycles
+    }
+
+    fn check_divu32(n: u32, d: u16) -> u64 {
+        let args = [(R1, (n >> 16) as u16), (R2, n as u16), (R3, d)];
+        let (cpu, cycles) = call(&[def_divu32], "divu32", &args);
+        let (q, r) = n
+            .checked_div(d as u32)
+            .map_or((u32::MAX, n as u16), |q| (q, (n % d as u32) as u16));
+
+        let res = ((reg(&cpu, R1) as u32) << 16) | reg(&cpu, R2) as u32;
+        assert_eq!((res, reg(&cpu, R3)), (q, r), "{n} / {d}");
+        cycles
+    }
+
+    fn check_mulu16(a: u16, b: u16) -> u64 {
+        let (cpu, cycles) = call(&[def_mulu16], "mulu16", &[(R1, a), (R2, b)]);
+        let res = ((reg(&cpu, R1) as u32) << 16) | reg(&cpu, R2) as u32;
+
+        assert_eq!(res, a as u32 * b as u32, "{a} * {b}");
+        cycles
+    }
+
+    #[test]
+    fn test_divu16() {
+        let mut rng = Rng::new(26);
+
+        for &n in &EDGE_CASES {
+            for &d in &EDGE_CASES {
+                assert_eq!(check_divu16(n, d), 464);
+            }
+        }
+
+        for _ in 0..300 {
+            let n = rng.next_u16();
+            let d = rng.next_u16() >> rng.below(16);
+            assert_eq!(check_divu16(n, d), 464);
+        }
+    }
+
+    #[test]
+    fn test_divu32() {
+        let mut rng = Rng::new(32);
+        let dividends = [0, 1, 0xffff, 0x1_0000, 0x7fff_ffff, 0x8000_0000, u32::MAX];
+
+        for &n in &dividends {
+            for &d in &EDGE_CASES[1..] {
+                assert_eq!(check_divu32(n, d), 1051);
+            }
+
+            check_divu32(n, 0);
+        }
+
+        for _ in 0..200 {
+            let n = rng.next_u32() >> rng.below(32);
+            let d = (rng.next_u16() >> rng.below(16)).max(1);
+            assert_eq!(check_divu32(n, d), 1051);
+        }
+    }
+
+    #[test]
+    fn test_mulu16() {
+        let mut rng = Rng::new(16);
+
+        for &a in &EDGE_CASES {
+            for &b in &EDGE_CASES {
+                assert_eq!(check_mulu16(a, b), 343);
+            }
+        }
+
+        for _ in 0..300 {
+            assert_eq!(check_mulu16(rng.next_u16(), rng.next_u16()), 343);
+        }
+    }
+}
@@ -0,0 +1,484 @@
+use crate::asm::Assembler;
+use crate::isa::{Cond, Reg};
+
+// 32-bit values are passed in register pairs, high word first:
+// a = R1:R2, b = R3:R4, and results are returned in R1:R2.
+// R1-R4 and TMP are caller-saved.
+// `mul32` requires `mulu16` and the string conversions require `divu32` (see arith.rs).
+
+// static variables
+const DIVISOR_HI: &str = "arith32_divisor_hi";
+const DIVISOR_LO: &str = "arith32_divisor_lo";
+const COUNT: &str = "arith32_count";
+const STR_PTR: &str = "arith32_str_ptr";
+const SHIFT_FILL: &str = "arith32_shift_fill";
+
+fn neg32(asm: &mut Assembler, hi: Reg, lo: Reg) {
+    use Reg::*;
+
+    asm.sub(lo, Z, lo).sbc(hi, Z, hi);
+}
+
+pub fn def_arith32(asm: &mut Assembler) {
+    asm.static_var(DIVISOR_HI, 1);
+    asm.static_var(DIVISOR_LO, 1);
+    asm.static_var(COUNT, 1);
+    asm.static_var(STR_PTR, 1);
+    asm.static_var(SHIFT_FILL, 1);
+
+    def_neg32(asm);
+    def_mul32(asm);
+    def_udivmod32(asm);
+    def_sdivmod32(asm);
+    def_shifts32(asm);
+    def_cmp32(asm);
+    def_utoa32(asm);
+    def_htoa32(asm);
+}
+
+// R1:R2 -> R1:R2: -a
+fn def_neg32(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("neg32");
+    neg32(asm, R1, R2);
+    asm.ret();
+}
+
+// R1:R2: a, R3:R4: b -> R1:R2: the low 32 bits of a * b (signed or unsigned)
+fn def_mul32(asm: &mut Assembler) {
+    use Reg::*;
+
+    // a * b = (a_hi * b_lo + a_lo * b_hi) << 16 + a_lo * b_lo
+    asm.label("mul32")
+        .push(R4) // b_lo
+        .push(R2) // a_lo
+        .push(R3) // b_hi
+        .push(R2) // a_lo
+        .mov(R2, R4)
+        .call("mulu16") // a_hi * b_lo
+        .pop(R1)
+        .pop(R3)
+        .push(R2)
+        .mov(R2, R3)
+        .call("mulu16") // a_lo * b_hi
+        .pop(R3)
+        .add(R3, R3, R2)
+        .pop(R1)
+        .pop(R2)
+        .push(R3)
+        .call("mulu16") // a_lo * b_lo
+        .pop(R3)
+        .add(R1, R1, R3)
+        .ret();
+}
+
+// R1:R2: a, R3:R4: b -> R1:R2: a / b, R3:R4: a % b (unsigned)
+// division by zero yields a quotient of 0xffffffff and a remainder of a
+fn def_udivmod32(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("udivmod32")
+        .add(Z, R3, R3)
+        .jmpc("udivmod32_large_divisor")
+        .store_var(R3, DIVISOR_HI)
+        .store_var(R4, DIVISOR_LO)
+        .set(R3, 0) // remainder
+        .set(R4, 0)
+        .set(TMP, 32)
+        .store_var(TMP, COUNT)
+        .label("udivmod32_loop")
+        // shift the next bit of the dividend into the remainder,
+        // which is smaller than the divisor and thus can't overflow
+        .add(R2, R2, R2)
+        .adc(R1, R1, R1)
+        .adc(R4, R4, R4)
+        .adc(R3, R3, R3)
+        .load_var(TMP, DIVISOR_LO)
+        .sub(R4, R4, TMP)
+        .load_var(TMP, DIVISOR_HI)
+        .sbc(R3, R3, TMP)
+        .jmpc("udivmod32_set_bit")
+        // the divisor didn't fit, restore the remainder
+        .load_var(TMP, DIVISOR_LO)
+        .add(R4, R4, TMP)
+        .load_var(TMP, DIVISOR_HI)
+        .adc(R3, R3, TMP)
+        .jmp("udivmod32_next")
+        .label("udivmod32_set_bit")
+        .inc(R2)
+        .label("udivmod32_next")
+        .load_var(TMP, COUNT)
+        .dec(TMP)
+        .store_var(TMP, COUNT)
+        .jmpnz("udivmod32_loop")
+        .ret();
+
+    // b >= 2^31: the quotient is either 0 or 1
+    asm.label("udivmod32_large_divisor")
+        .sub(R2, R2, R4)
+        .sbc(R1, R1, R3)
+        .jmpc("udivmod32_large_divisor_fits")
+        .add(R2, R2, R4)
+        .adc(R1, R1, R3)
+        .mov(R3, R1)
+        .mov(R4, R2)
+        .set(R1, 0)
+        .set(R2, 0)
+        .ret()
+        .label("udivmod32_large_divisor_fits")
+        .mov(R3, R1)
+        .mov(R4, R2)
+        .set(R1, 0)
+        .set(R2, 1)
+        .ret();
+}
+
+// R1:R2: a, R3:R4: b -> R1:R2: a / b, R3:R4: a % b (signed)
+// rounds towards zero like Rust's `wrapping_div` and `wrapping_rem`
+fn def_sdivmod32(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("sdivmod32")
+        .push(R1) // the remainder has the sign of a
+        .xor(TMP, R1, R3)
+        .push(TMP) // the quotient is negative if the signs differ
+        .add(Z, R1, R1)
+        .jmpnc("sdivmod32_a_positive");
+    neg32(asm, R1, R2);
+    asm.label("sdivmod32_a_positive")
+        .add(Z, R3, R3)
+        .jmpnc("sdivmod32_b_positive");
+    neg32(asm, R3, R4);
+    asm.label("sdivmod32_b_positive")
+        .call("udivmod32")
+        .pop(TMP)
+        .add(Z, TMP, TMP)
+        .jmpnc("sdivmod32_quotient_positive");
+    neg32(asm, R1, R2);
+    asm.label("sdivmod32_quotient_positive")
+        .pop(TMP)
+        .add(Z, TMP, TMP)
+        .jmpnc("sdivmod32_end");
+    neg32(asm, R3, R4);
+    asm.label("sdivmod32_end").ret();
+}
+
+// R1:R2: a, R3: n -> R1:R2: a << n, a >> n (logical) or a >> n (arithmetic)
+// n is taken modulo 32 like Rust's `wrapping_shl` and `wrapping_shr`
+fn def_shifts32(asm: &mut Assembler) {
+    use Reg::*;
+
+    // shl32
+    asm.label("shl32")
+        .set(R4, 31)
+        .and(R3, R3, R4)
+        .set(R4, 16)
+        .cmp(R3, R4)
+        .jmpnc("shl32_small")
+        .mov(R1, R2)
+        .set(R2, 0)
+        .sub(R3, R3, R4)
+        .label("shl32_small")
+        .update_flags(R3)
+        .jmpz("shl32_end")
+        // 0 < n < 16
+        .sub(R4, R4, R3)
+        .shl(R1, R1, R3)
+        .shr(TMP, R2, R4)
+        .or(R1, R1, TMP)
+        .shl(R2, R2, R3)
+        .label("shl32_end")
+        .ret();
+
+    // shr32 and sar32 only differ by the word shifted into the high word
+    asm.label("shr32")
+        .store_var(Z, SHIFT_FILL)
+        .jmp("shr32_shift");
+
+    asm.label("sar32")
+        .set(TMP, 15)
+        .shr(TMP, R1, TMP)
+        .sub(TMP, Z, TMP) // 0xffff if a is negative
+        .store_var(TMP, SHIFT_FILL)
+        .label("shr32_shift")
+        .set(R4, 31)
+        .and(R3, R3, R4)
+        .set(R4, 16)
+        .cmp(R3, R4)
+        .jmpnc("shr32_small")
+        .mov(R2, R1)
+        .load_var(R1, SHIFT_FILL)
+        .sub(R3, R3, R4)
+        .label("shr32_small")
+        .update_flags(R3)
+        .jmpz("shr32_end")
+        // 0 < n < 16
+        .sub(R4, R4, R3)
+        .shr(R2, R2, R3)
+        .shl(TMP, R1, R4)
+        .or(R2, R2, TMP)
+        .shr(R1, R1, R3)
+        .load_var(TMP, SHIFT_FILL)
+        .shl(TMP, TMP, R4)
+        .or(R1, R1, TMP)
+        .label("shr32_end")
+        .ret();
+}
+
+// R1:R2: a, R3:R4: b -> R1: 0 if a == b, 1 if a > b, 0xffff if a < b
+fn def_cmp32(asm: &mut Assembler) {
+    use Reg::*;
+
+    // signed values only need an unsigned comparison when they have the same sign
+    asm.label("cmp32s")
+        .xor(TMP, R1, R3)
+        .add(Z, TMP, TMP)
+        .jmpnc("cmp32u")
+        .add(Z, R1, R1) // carry: a < 0
+        .set(R1, 1)
+        .sub_if(R1, Z, R1, Cond::IfCarry)
+        .ret();
+
+    asm.label("cmp32u")
+        .sub(R2, R2, R4)
+        .sbc(R1, R1, R3) // carry: a >= b
+        .set(R3, 1)
+        .sub_if(R3, Z, R3, Cond::IfNotCarry)
+        .or(R1, R1, R2)
+        .mov_if(R1, R3, Cond::IfNotZero)
+        .ret();
+}
+
+// utoa32: R1:R2: unsigned value, R3: str pointer
+// itoa32: R1:R2: signed value, R3: str pointer
+// writes the decimal representation of the value followed by a null terminator
+fn def_utoa32(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("itoa32")
+        .add(Z, R1, R1)
+        .jmpnc("utoa32")
+        .set(R4, b'-' as u16)
+        .store(R4, R3, 0)
+        .inc(R3);
+    neg32(asm, R1, R2);
+
+    // digits are pushed from the least significant one, above the null terminator
+    asm.label("utoa32")
+        .store_var(R3, STR_PTR)
+        .push(Z)
+        .label("utoa32_div_loop")
+        .set(R3, 10)
+        .call("divu32")
+        .set(R4, b'0' as u16)
+        .add(R3, R3, R4)
+        .push(R3)
+        .or(R4, R1, R2)
+        .jmpnz("utoa32_div_loop")
+        .load_var(R1, STR_PTR)
+        .label("utoa32_store_loop")
+        .pop(R2)
+        .store(R2, R1, 0)
+        .inc(R1)
+        .update_flags(R2)
+        .jmpnz("utoa32_store_loop")
+        .ret();
+}
+
+// R1:R2: value, R3: str pointer
+// writes 8 lowercase hex digits followed by a null terminator
+fn def_htoa32(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("htoa32");
+
+    for i in 0..8 {
+        // R4 = top nibble, R1:R2 <<= 4
+        asm.set(TMP, 12)
+            .shr(R4, R1, TMP)
+            .set(TMP, 4)
+            .shl(R1, R1, TMP)
+            .set(TMP, 12)
+            .shr(TMP, R2, TMP)
+            .or(R1, R1, TMP)
+            .set(TMP, 4)
+            .shl(R2, R2, TMP)
+            // to ascii
+            .set(TMP, 10)
+            .cmp(R4, TMP)
+            .set(TMP, (b'a' - b'0' - 10) as u16)
+            .add_if(R4, R4, TMP, Cond::IfCarry)
+            .set(TMP, b'0' as u16)
+            .add(R4, R4, TMP)
+            .store(R4, R3, i);
+    }
+
+    asm.store(Z, R3, 8).ret();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::procedures::arith::{def_divu32, def_mulu16};
+    use crate::sim::CPU;
+    use crate::testing::{call, call_with, reg, Rng};
+    use Reg::*;
+
+    const EDGE_CASES: [u32; 10] = [
+        0,
+        1,
+        2,
+        10,
+        0xffff,
+        0x1_0000,
+        0x7fff_ffff,
+        0x8000_0000,
+        0xffff_fffe,
+        u32::MAX,
+    ];
+
+    fn defs(asm: &mut Assembler) {
+        def_arith32(asm);
+        def_mulu16(asm);
+        def_divu32(asm);
+    }
+
+    fn args(a: u32, b: u32) -> [(Reg, u16); 4] {
+        [
+            (R1, (a >> 16) as u16),
+            (R2, a as u16),
+            (R3, (b >> 16) as u16),
+            (R4, b as u16),
+        ]
+    }
+
+    fn pair(cpu: &CPU, hi: Reg, lo: Reg) -> u32 {
+        ((reg(cpu, hi) as u32) << 16) | reg(cpu, lo) as u32
+    }
+
+    // edge cases against each other, then random values of random magnitudes
+    fn operands(seed: u64) -> Vec<(u32, u32)> {
+        let mut rng = Rng::new(seed);
+        let mut ops = vec![];
+
+        for &a in &EDGE_CASES {
+            for &b in &EDGE_CASES {
+                ops.push((a, b));
+            }
+        }
+
+        for _ in 0..100 {
+            let a = rng.next_u32() >> rng.below(32);
+            let b = rng.next_u32() >> rng.below(32);
+            ops.push((a, b));
+        }
+
+        ops
+    }
+
+    fn read_str(cpu: &CPU, addr: usize) -> String {
+        cpu.ram[addr..]
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| char::from(c as u8))
+            .collect()
+    }
+
+    #[test]
+    fn test_neg32() {
+        for &a in &EDGE_CASES {
+            let (cpu, _) = call(&[defs], "neg32", &args(a, 0)[..2]);
+            assert_eq!(pair(&cpu, R1, R2), a.wrapping_neg());
+        }
+    }
+
+    #[test]
+    fn test_mul32() {
+        for (a, b) in operands(1) {
+            let (cpu, _) = call(&[defs], "mul32", &args(a, b));
+            assert_eq!(pair(&cpu, R1, R2), a.wrapping_mul(b), "{a} * {b}");
+        }
+    }
+
+    #[test]
+    fn test_udivmod32() {
+        for (a, b) in operands(2) {
+            let (cpu, _) = call(&[defs], "udivmod32", &args(a, b));
+            let (q, r) = a.checked_div(b).map_or((u32::MAX, a), |q| (q, a % b));
+
+            assert_eq!(pair(&cpu, R1, R2), q, "{a} / {b}");
+            assert_eq!(pair(&cpu, R3, R4), r, "{a} % {b}");
+        }
+    }
+
+    #[test]
+    fn test_sdivmod32() {
+        for (a, b) in operands(3).into_iter().filter(|&(_, b)| b != 0) {
+            let (cpu, _) = call(&[defs], "sdivmod32", &args(a, b));
+            let (a, b) = (a as i32, b as i32);
+
+            assert_eq!(pair(&cpu, R1, R2) as i32, a.wrapping_div(b), "{a} / {b}");
+            assert_eq!(pair(&cpu, R3, R4) as i32, a.wrapping_rem(b), "{a} % {b}");
+        }
+    }
+
+    #[test]
+    fn test_shifts32() {
+        let mut rng = Rng::new(4);
+
+        for &a in EDGE_CASES.iter().chain(&[0x1234_5678, 0x8765_4321]) {
+            for _ in 0..8 {
+                let n = rng.below(40) as u32;
+                let args = &args(a, n << 16)[..3];
+
+                let (cpu, _) = call(&[defs], "shl32", args);
+                assert_eq!(pair(&cpu, R1, R2), a.wrapping_shl(n), "{a:x} << {n}");
+
+                let (cpu, _) = call(&[defs], "shr32", args);
+                assert_eq!(pair(&cpu, R1, R2), a.wrapping_shr(n), "{a:x} >> {n}");
+
+                let (cpu, _) = call(&[defs], "sar32", args);
+                let expected = (a as i32).wrapping_shr(n) as u32;
+                assert_eq!(pair(&cpu, R1, R2), expected, "{a:x} >>> {n}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_cmp32() {
+        let ordering = |ord: std::cmp::Ordering| ord as i8 as u16;
+
+        for (a, b) in operands(5)
+            .into_iter()
+            .chain([(7, 7), (0x8000_0001, 0x8000_0001)])
+        {
+            let (cpu, _) = call(&[defs], "cmp32u", &args(a, b));
+            assert_eq!(reg(&cpu, R1), ordering(a.cmp(&b)), "{a} <=> {b}");
+
+            let (cpu, _) = call(&[defs], "cmp32s", &args(a, b));
+            let expected = ordering((a as i32).cmp(&(b as i32)));
+            assert_eq!(reg(&cpu, R1), expected, "{} <=> {}", a as i32, b as i32);
+        }
+    }
+
+    #[test]
+    fn test_to_string32() {
+        const STR: u16 = 0x1000;
+
+        for (a, _) in operands(6).into_iter().step_by(10) {
+            let [hi, lo, ..] = args(a, 0);
+            let args = [hi, lo, (R3, STR)];
+            let garbage = |cpu: &mut CPU| cpu.ram[STR as usize..][..16].fill(0xffff);
+
+            let (cpu, _) = call_with(&[defs], "utoa32", &args, garbage);
+            assert_eq!(read_str(&cpu, STR as usize), a.to_string());
+
+            let (cpu, _) = call_with(&[defs], "itoa32", &args, garbage);
+            assert_eq!(read_str(&cpu, STR as usize), (a as i32).to_string());
+
+            let (cpu, _) = call_with(&[defs], "htoa32", &args, garbage);
+            assert_eq!(read_str(&cpu, STR as usize), format!("{a:08x}"));
+        }
+    }
+}
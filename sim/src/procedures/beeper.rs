@@ -0,0 +1,108 @@
+use crate::asm::Assembler;
+use crate::beeper::{BEEPER_BASE, BEEPER_CTRL, BEEPER_DIVIDER, BEEPER_ENABLE};
+use crate::isa::Reg;
+
+// Driver for the beeper of the simulator (see beeper.rs),
+// which must be mapped at BEEPER_BASE.
+// R1-R4 and TMP are caller-saved.
+
+// iterations of the delay loop per millisecond, a dec (4 cycles) and a jmpnz (7 cycles)
+const LOOPS_PER_MS: u16 = 9091;
+
+pub fn def_beeper(asm: &mut Assembler) {
+    def_beep_tone(asm);
+    def_beep_off(asm);
+    def_delay_ms(asm);
+    def_beep(asm);
+}
+
+// R1: divider, see `beeper::divider`
+fn def_beep_tone(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("beep_tone")
+        .setw(R4, BEEPER_BASE, TMP)
+        .store(R1, R4, BEEPER_DIVIDER as u8)
+        .set(R1, BEEPER_ENABLE)
+        .store(R1, R4, BEEPER_CTRL as u8)
+        .ret();
+}
+
+fn def_beep_off(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("beep_off")
+        .setw(R4, BEEPER_BASE, TMP)
+        .store(Z, R4, BEEPER_CTRL as u8)
+        .ret();
+}
+
+// R1: milliseconds, at least 1
+fn def_delay_ms(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("delay_ms")
+        .label("delay_ms_loop")
+        .setw(R2, LOOPS_PER_MS, TMP)
+        .label("delay_ms_inner")
+        .dec(R2)
+        .jmpnz("delay_ms_inner")
+        .dec(R1)
+        .jmpnz("delay_ms_loop")
+        .ret();
+}
+
+// R1: divider, R2: milliseconds, plays a tone then stops it
+fn def_beep(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("beep")
+        .push(R2)
+        .call("beep_tone")
+        .pop(R1)
+        .call("delay_ms")
+        .call("beep_off")
+        .ret();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beeper::{divider, Beeper, BEEPER_END};
+    use crate::sim::CPU;
+    use crate::testing::{assert_audio_snapshot, call_with};
+    use crate::START_PC;
+    use Reg::*;
+
+    #[test]
+    fn test_delay_ms() {
+        let (_, cycles) = call_with(&[def_beeper], "delay_ms", &[(R1, 3)], |_| {});
+        assert!((300_000..300_200).contains(&cycles), "{cycles}");
+    }
+
+    #[test]
+    fn test_tune() {
+        // C5, E5, G5 then a rest
+        let notes = [(523, 20), (659, 20), (784, 40)];
+        let mut asm = Assembler::new();
+        asm.init_sp();
+
+        for (frequency, ms) in notes {
+            asm.setw(R1, divider(frequency), TMP)
+                .set(R2, ms)
+                .call("beep");
+        }
+
+        asm.set(R1, 10).call("delay_ms").halt();
+        def_beeper(&mut asm);
+
+        let mut cpu = CPU::from(&asm.assemble(), START_PC);
+        cpu.bus
+            .map("beeper", BEEPER_BASE..=BEEPER_END, Beeper::new());
+        cpu.run();
+
+        let beeper = cpu.bus.device::<Beeper>("beeper").unwrap();
+        assert_eq!(beeper.samples().len() / 441, 9, "90 ms");
+        assert_audio_snapshot(beeper, "tune");
+    }
+}
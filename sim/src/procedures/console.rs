@@ -0,0 +1,416 @@
+use crate::asm::Assembler;
+use crate::isa::Reg;
+
+// Text console on top of the PPU name table (80x60 tiles of ASCII characters).
+//
+// The name table is written through two MMIO ports (see Top.veryl):
+// a write to 0xfffe selects the tile index, and each write to 0xffff stores
+// a character in the selected tile and moves to the next one.
+// The ports are write-only, so the console keeps a copy of the screen in RAM
+// which is used to redraw the name table when scrolling.
+//
+// All procedures clobber R1-R4 and TMP.
+// `console_print_dec` and `console_print_dec32` require `divu32` (see arith.rs).
+
+pub const CONSOLE_COLS: u16 = 80;
+pub const CONSOLE_ROWS: u16 = 60;
+pub const CONSOLE_TILES: u16 = CONSOLE_COLS * CONSOLE_ROWS;
+
+// copy of the name table
+pub const CONSOLE_BUFFER: u16 = 0x6000;
+
+// static variables
+const CURSOR_INDEX: &str = "console_cursor_index"; // tile index of the cursor
+const CURSOR_COL: &str = "console_cursor_col";
+
+// points `ports` at 0xfffe, the name table address port (0xffff is at offset 1)
+fn name_table_ports(asm: &mut Assembler, ports: Reg) {
+    asm.dec2(ports, Reg::Z).dec(ports);
+}
+
+pub fn def_console(asm: &mut Assembler) {
+    asm.static_var(CURSOR_INDEX, 1);
+    asm.static_var(CURSOR_COL, 1)
+        .static_var_at("console_buffer", CONSOLE_BUFFER, CONSOLE_TILES);
+
+    def_console_clear(asm);
+    def_console_set_cursor(asm);
+    def_console_putc(asm);
+    def_console_scroll(asm);
+    def_console_puts(asm);
+    def_console_print_hex(asm);
+    def_console_print_dec(asm);
+}
+
+// fills the screen with spaces and moves the cursor to the top left corner
+fn def_console_clear(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("console_clear");
+    name_table_ports(asm, R2);
+
+    asm.store(Z, R2, 0)
+        .setw(R1, CONSOLE_BUFFER, TMP)
+        .setw(R3, CONSOLE_TILES, TMP)
+        .set(R4, b' ' as u16)
+        .label("console_clear_loop")
+        .store(R4, R1, 0)
+        .store(R4, R2, 1)
+        .inc(R1)
+        .dec(R3)
+        .jmpnz("console_clear_loop")
+        .store_var(Z, CURSOR_INDEX)
+        .store_var(Z, CURSOR_COL)
+        .ret();
+}
+
+// R1: row, R2: col
+fn def_console_set_cursor(asm: &mut Assembler) {
+    use Reg::*;
+
+    // index = row * 80 + col = (row << 6) + (row << 4) + col
+    asm.label("console_set_cursor")
+        .store_var(R2, CURSOR_COL)
+        .set(R3, 6)
+        .shl(R4, R1, R3)
+        .set(R3, 4)
+        .shl(R1, R1, R3)
+        .add(R1, R1, R4)
+        .add(R1, R1, R2)
+        .store_var(R1, CURSOR_INDEX)
+        .ret();
+}
+
+// R1: char
+// '\n' moves to the start of the next line and '\r' to the start of the current one,
+// any other character is drawn at the cursor, wrapping at the end of the line.
+// Moving past the last line scrolls the screen up.
+fn def_console_putc(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("console_putc")
+        .set(R2, b'\n' as u16)
+        .cmp(R1, R2)
+        .jmpz("console_newline")
+        .set(R2, b'\r' as u16)
+        .cmp(R1, R2)
+        .jmpz("console_carriage_return")
+        .load_var(R2, CURSOR_INDEX);
+
+    name_table_ports(asm, R3);
+
+    asm.store(R2, R3, 0)
+        .store(R1, R3, 1)
+        .setw(R3, CONSOLE_BUFFER, TMP)
+        .add(R3, R3, R2)
+        .store(R1, R3, 0)
+        .inc(R2)
+        .store_var(R2, CURSOR_INDEX)
+        // wrap at the end of the line, the index already points to the next one
+        .load_var(R3, CURSOR_COL)
+        .inc(R3)
+        .set(R4, CONSOLE_COLS)
+        .cmp(R3, R4)
+        .jmpnz("console_putc_store_col")
+        .set(R3, 0)
+        .label("console_putc_store_col")
+        .store_var(R3, CURSOR_COL)
+        .jmp("console_check_scroll");
+
+    asm.label("console_newline")
+        .load_var(R2, CURSOR_INDEX)
+        .load_var(R3, CURSOR_COL)
+        .sub(R2, R2, R3)
+        .set(R3, CONSOLE_COLS)
+        .add(R2, R2, R3)
+        .store_var(R2, CURSOR_INDEX)
+        .store_var(Z, CURSOR_COL)
+        .label("console_check_scroll")
+        .load_var(R2, CURSOR_INDEX)
+        .setw(R3, CONSOLE_TILES, TMP)
+        .cmp(R2, R3)
+        .jmpc("console_scroll")
+        .ret();
+
+    asm.label("console_carriage_return")
+        .load_var(R2, CURSOR_INDEX)
+        .load_var(R3, CURSOR_COL)
+        .sub(R2, R2, R3)
+        .store_var(R2, CURSOR_INDEX)
+        .store_var(Z, CURSOR_COL)
+        .ret();
+}
+
+// moves every line up, clears the last one and moves the cursor up one line
+fn def_console_scroll(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("console_scroll")
+        .setw(R1, CONSOLE_BUFFER, TMP)
+        .set(R2, CONSOLE_COLS)
+        .add(R2, R2, R1)
+        .setw(R3, CONSOLE_TILES - CONSOLE_COLS, TMP)
+        .label("console_scroll_copy_loop")
+        .load(R4, R2, 0)
+        .store(R4, R1, 0)
+        .inc(R1)
+        .inc(R2)
+        .dec(R3)
+        .jmpnz("console_scroll_copy_loop")
+        .set(R3, CONSOLE_COLS)
+        .set(R4, b' ' as u16)
+        .label("console_scroll_clear_loop")
+        .store(R4, R1, 0)
+        .inc(R1)
+        .dec(R3)
+        .jmpnz("console_scroll_clear_loop");
+
+    // redraw the whole name table
+    name_table_ports(asm, R2);
+
+    asm.store(Z, R2, 0)
+        .setw(R1, CONSOLE_BUFFER, TMP)
+        .setw(R3, CONSOLE_TILES, TMP)
+        .label("console_scroll_draw_loop")
+        .load(R4, R1, 0)
+        .store(R4, R2, 1)
+        .inc(R1)
+        .dec(R3)
+        .jmpnz("console_scroll_draw_loop")
+        .load_var(R2, CURSOR_INDEX)
+        .set(R3, CONSOLE_COLS)
+        .sub(R2, R2, R3)
+        .store_var(R2, CURSOR_INDEX)
+        .ret();
+}
+
+// R1: null-terminated string
+fn def_console_puts(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("console_puts")
+        .load(R2, R1, 0)
+        .update_flags(R2)
+        .jmpz("console_puts_end")
+        .push(R1)
+        .mov(R1, R2)
+        .call("console_putc")
+        .pop(R1)
+        .inc(R1)
+        .jmp("console_puts")
+        .label("console_puts_end")
+        .ret();
+}
+
+// console_print_hex:   R1: value
+// console_print_hex32: R1: hi, R2: lo
+// prints lowercase hex digits, zero-padded to 4 or 8 digits
+fn def_console_print_hex(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("console_print_hex32")
+        .push(R2)
+        .call("console_print_hex")
+        .pop(R1);
+
+    asm.label("console_print_hex")
+        .set(R2, 4) // remaining digits
+        .label("console_print_hex_loop")
+        .push(R2)
+        // take the top nibble
+        .set(R2, 12)
+        .shr(R3, R1, R2)
+        .set(R2, 4)
+        .shl(R1, R1, R2)
+        .push(R1)
+        .set(R2, 10)
+        .cmp(R3, R2)
+        .set(R1, b'0' as u16)
+        .jmpnc("console_print_hex_digit")
+        .set(R1, b'a' as u16 - 10)
+        .label("console_print_hex_digit")
+        .add(R1, R1, R3)
+        .call("console_putc")
+        .pop(R1)
+        .pop(R2)
+        .dec(R2)
+        .jmpnz("console_print_hex_loop")
+        .ret();
+}
+
+// console_print_dec:   R1: value
+// console_print_dec32: R1: hi, R2: lo
+// prints an unsigned decimal number without leading zeros
+fn def_console_print_dec(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("console_print_dec").mov(R2, R1).set(R1, 0);
+
+    // digits are pushed from the least significant one, above a 0 sentinel
+    asm.label("console_print_dec32")
+        .push(Z)
+        .label("console_print_dec_div_loop")
+        .set(R3, 10)
+        .call("divu32")
+        .set(R4, b'0' as u16)
+        .add(R3, R3, R4)
+        .push(R3)
+        .or(R4, R1, R2)
+        .jmpnz("console_print_dec_div_loop")
+        .label("console_print_dec_print_loop")
+        .pop(R1)
+        .update_flags(R1)
+        .jmpz("console_print_dec_end")
+        .call("console_putc")
+        .jmp("console_print_dec_print_loop")
+        .label("console_print_dec_end")
+        .ret();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppu::Ppu;
+    use crate::procedures::arith::def_divu32;
+    use crate::sim::CPU;
+    use crate::testing::{assert_screen_snapshot, call_with, var_address, Rng};
+    use Reg::*;
+
+    const STR: u16 = 0x1000;
+
+    fn row(cpu: &CPU, row: u16) -> String {
+        let start = (CONSOLE_BUFFER + row * CONSOLE_COLS) as usize;
+        let tiles = &cpu.ram[start..start + CONSOLE_COLS as usize];
+
+        tiles
+            .iter()
+            .map(|&c| char::from(c as u8))
+            .collect::<String>()
+            .trim_end()
+            .to_string()
+    }
+
+    fn cursor(cpu: &CPU) -> (u16, u16) {
+        let index = cpu.ram[var_address(&[def_console], CURSOR_INDEX)];
+        let col = cpu.ram[var_address(&[def_console], CURSOR_COL)];
+        assert_eq!(index % CONSOLE_COLS, col);
+
+        (index / CONSOLE_COLS, col)
+    }
+
+    // clears the screen and prints `text` with console_puts
+    fn puts(text: &str) -> CPU {
+        let mut asm = Assembler::new();
+        asm.init_sp()
+            .call("console_clear")
+            .setw(R1, STR, TMP)
+            .call("console_puts")
+            .halt();
+        def_console(&mut asm);
+        def_divu32(&mut asm);
+
+        let mut cpu = CPU::from(&asm.assemble(), crate::START_PC);
+
+        for (i, byte) in text.bytes().enumerate() {
+            cpu.ram[STR as usize + i] = byte as u16;
+        }
+
+        cpu.run();
+        cpu
+    }
+
+    #[test]
+    fn test_console_puts() {
+        let cpu = puts("hello\nworld\rW\n\n!");
+
+        assert_eq!(row(&cpu, 0), "hello");
+        assert_eq!(row(&cpu, 1), "World");
+        assert_eq!(row(&cpu, 2), "");
+        assert_eq!(row(&cpu, 3), "!");
+        assert_eq!(cursor(&cpu), (3, 1));
+        // the last character was written through the name table ports
+        let ppu = cpu.bus.device::<Ppu>("ppu").unwrap();
+        assert_eq!(ppu.tile_index, 3 * CONSOLE_COLS + 1);
+        assert_eq!(ppu.name_table[3 * CONSOLE_COLS as usize], b'!');
+
+        // and the screen matches the copy in RAM
+        let buffer = &cpu.ram[CONSOLE_BUFFER as usize..][..CONSOLE_TILES as usize];
+        assert!(buffer
+            .iter()
+            .zip(ppu.name_table.iter())
+            .all(|(&a, &b)| a == b as u16));
+    }
+
+    #[test]
+    fn test_console_wrap() {
+        let line = "0123456789".repeat(8);
+        let cpu = puts(&format!("{line}abc"));
+
+        assert_eq!(row(&cpu, 0), line);
+        assert_eq!(row(&cpu, 1), "abc");
+        assert_eq!(cursor(&cpu), (1, 3));
+    }
+
+    #[test]
+    fn test_console_scroll() {
+        let text: String = (0..62).map(|i| format!("line {i}\n")).collect();
+        let cpu = puts(&text);
+
+        // the first 3 lines scrolled out, the cursor is on the last row
+        assert_eq!(row(&cpu, 0), "line 3");
+        assert_eq!(row(&cpu, 58), "line 61");
+        assert_eq!(row(&cpu, 59), "");
+        assert_eq!(cursor(&cpu), (59, 0));
+        assert_screen_snapshot(&cpu, "console_scroll");
+    }
+
+    #[test]
+    fn test_console_set_cursor() {
+        let (cpu, _) = call_with(
+            &[def_console, def_divu32],
+            "console_set_cursor",
+            &[(R1, 42), (R2, 17)],
+            |_| {},
+        );
+
+        assert_eq!(cursor(&cpu), (42, 17));
+    }
+
+    #[test]
+    fn test_console_print_numbers() {
+        let mut rng = Rng::new(28);
+        let mut values = vec![0, 1, 9, 10, 0xffff, 0x1_0000, u32::MAX];
+        values.extend((0..20).map(|_| rng.next_u32() >> rng.below(32)));
+
+        for value in values {
+            let (hi, lo) = ((value >> 16) as u16, value as u16);
+            let mut asm = Assembler::new();
+            asm.init_sp()
+                .call("console_clear")
+                .setw(R1, lo, TMP)
+                .call("console_print_hex")
+                .set(R1, b' ' as u16)
+                .call("console_putc")
+                .setw(R1, lo, TMP)
+                .call("console_print_dec")
+                .call("console_newline")
+                .setw(R1, hi, TMP)
+                .setw(R2, lo, TMP)
+                .call("console_print_hex32")
+                .set(R1, b' ' as u16)
+                .call("console_putc")
+                .setw(R1, hi, TMP)
+                .setw(R2, lo, TMP)
+                .call("console_print_dec32")
+                .halt();
+            def_console(&mut asm);
+            def_divu32(&mut asm);
+
+            let mut cpu = CPU::from(&asm.assemble(), crate::START_PC);
+            cpu.run();
+
+            assert_eq!(row(&cpu, 0), format!("{lo:04x} {lo}"));
+            assert_eq!(row(&cpu, 1), format!("{value:08x} {value}"));
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use crate::asm::Assembler;
+use crate::dma::{DMA_BASE, DMA_CTRL, DMA_DST, DMA_LEN, DMA_MODE, DMA_SRC, DMA_START, DMA_TO_PORT};
+use crate::isa::Reg;
+use crate::procedures::console::{CONSOLE_BUFFER, CONSOLE_TILES};
+
+// Driver for the DMA controller of the simulator (see dma.rs),
+// which must be mapped at DMA_BASE.
+// R1-R4 and TMP are caller-saved.
+
+pub fn def_dma(asm: &mut Assembler) {
+    def_dma_start(asm);
+    def_dma_console_redraw(asm);
+}
+
+// R1: source, R2: destination, R3: length, R4: mode
+// the CPU is stalled until the transfer is done
+fn def_dma_start(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("dma_start")
+        .push(R4)
+        .setw(R4, DMA_BASE, TMP)
+        .store(R1, R4, DMA_SRC as u8)
+        .store(R2, R4, DMA_DST as u8)
+        .store(R3, R4, DMA_LEN as u8)
+        .pop(R1)
+        .store(R1, R4, DMA_MODE as u8)
+        .set(R1, DMA_START)
+        .store(R1, R4, DMA_CTRL as u8)
+        .ret();
+}
+
+// copies the console buffer to the name table (see console.rs)
+fn def_dma_console_redraw(asm: &mut Assembler) {
+    use Reg::*;
+
+    // 0xfffe selects the tile index, 0xffff is the data port
+    asm.label("dma_console_redraw")
+        .dec2(R2, Z)
+        .dec(R2)
+        .store(Z, R2, 0)
+        .inc(R2)
+        .setw(R1, CONSOLE_BUFFER, TMP)
+        .setw(R3, CONSOLE_TILES, TMP)
+        .set(R4, DMA_TO_PORT)
+        .call("dma_start")
+        .ret();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dma::{Dma, DMA_END, DMA_FILL};
+    use crate::procedures::arith::def_divu32;
+    use crate::procedures::console::def_console;
+    use crate::testing::{assert_row_eq, call_with, screen};
+    use Reg::*;
+
+    fn with_dma(cpu: &mut crate::sim::CPU) {
+        cpu.bus.map("dma", DMA_BASE..=DMA_END, Dma::new());
+    }
+
+    #[test]
+    fn test_dma_fill() {
+        let (cpu, cycles) = call_with(
+            &[def_dma],
+            "dma_start",
+            &[(R1, 0xbeef), (R2, 0x1000), (R3, 300), (R4, DMA_FILL)],
+            with_dma,
+        );
+
+        assert_eq!(&cpu.ram[0x1000..0x1000 + 300], &[0xbeef; 300]);
+        assert_eq!(cpu.ram[0x1000 + 300], 0);
+        assert!(cycles > 300, "{cycles}");
+    }
+
+    #[test]
+    fn test_dma_console_redraw() {
+        let text = b"hello, dma";
+        let (cpu, dma_cycles) = call_with(&[def_dma], "dma_console_redraw", &[], |cpu| {
+            with_dma(cpu);
+
+            let buffer = CONSOLE_BUFFER as usize;
+            cpu.ram[buffer..buffer + CONSOLE_TILES as usize].fill(b' ' as u16);
+
+            for (i, &byte) in text.iter().enumerate() {
+                cpu.ram[buffer + 81 + i] = byte as u16;
+            }
+        });
+
+        assert_row_eq(&cpu, 1, " hello, dma");
+        assert_eq!(screen(&cpu).iter().filter(|row| !row.is_empty()).count(), 1);
+
+        // against the store loop of console_clear
+        let (_, loop_cycles) = call_with(&[def_console, def_divu32], "console_clear", &[], |_| {});
+        assert!(dma_cycles * 3 < loop_cycles, "{dma_cycles} {loop_cycles}");
+    }
+}
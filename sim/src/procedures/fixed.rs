@@ -0,0 +1,406 @@
+use crate::asm::Assembler;
+use crate::isa::{Cond, Reg};
+
+// Fixed-point and trigonometric procedures.
+//
+// Q8.8 and Q1.15 values are signed 16-bit integers with 8 and 15 fractional bits.
+// Angles are binary angles: 0x10000 is a full turn, so 0x4000 is pi / 2.
+// R1-R4 and TMP are caller-saved.
+// All procedures require `mulu16` and `divu32` (see arith.rs), the trigonometric
+// ones read the lookup tables generated by build.rs, which are emitted by `def_fixed`.
+
+include!(concat!(env!("OUT_DIR"), "/tables.rs"));
+
+pub fn def_fixed(asm: &mut Assembler) {
+    def_qadd(asm);
+    def_muls16(asm);
+    def_qmul(asm, "q88_mul", 8);
+    def_qmul(asm, "q15_mul", 15);
+    def_qdiv(asm, "q88_div", 8);
+    def_qdiv(asm, "q15_div", 15);
+    def_isqrt(asm);
+    def_sin_cos(asm);
+    def_atan2(asm);
+
+    asm.label("sin_table").data(&SIN_TABLE);
+    asm.label("atan_table").data(&ATAN_TABLE);
+}
+
+// R1: a, R2: b -> R1: a + b, saturated to [-0x8000, 0x7fff]
+// the same procedure works for both formats
+fn def_qadd(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("q88_add")
+        .label("q15_add")
+        .add(R3, R1, R2)
+        // overflow if a and b have the same sign and the sum doesn't
+        .xor(R4, R1, R2)
+        .add(Z, R4, R4)
+        .jmpc("qadd_end")
+        .xor(R4, R1, R3)
+        .add(Z, R4, R4)
+        .jmpnc("qadd_end")
+        // saturate towards the sign of a
+        .set(R3, 1)
+        .set(R4, 15)
+        .shl(R3, R3, R4) // 0x8000
+        .set(R4, 1)
+        .add(Z, R1, R1)
+        .sub_if(R3, R3, R4, Cond::IfNotCarry) // 0x7fff if a >= 0
+        .label("qadd_end")
+        .mov(R1, R3)
+        .ret();
+}
+
+// R1: a, R2: b -> R1: (a * b) hi, R2: (a * b) lo (signed)
+fn def_muls16(asm: &mut Assembler) {
+    use Reg::*;
+
+    // the unsigned product is off by b << 16 if a < 0 and by a << 16 if b < 0
+    asm.label("muls16")
+        .push(R1)
+        .push(R2)
+        .call("mulu16")
+        .pop(R3)
+        .pop(R4)
+        .add(Z, R4, R4)
+        .sub_if(R1, R1, R3, Cond::IfCarry)
+        .add(Z, R3, R3)
+        .sub_if(R1, R1, R4, Cond::IfCarry)
+        .ret();
+}
+
+// R1: a, R2: b -> R1: (a * b) >> frac_bits
+// rounds towards negative infinity, overflows wrap around
+fn def_qmul(asm: &mut Assembler, name: &str, frac_bits: u16) {
+    use Reg::*;
+
+    asm.label(name)
+        .call("muls16")
+        .set(R3, 16 - frac_bits)
+        .shl(R1, R1, R3)
+        .set(R3, frac_bits)
+        .shr(R2, R2, R3)
+        .or(R1, R1, R2)
+        .ret();
+}
+
+// R1: a, R2: b -> R1: (a << frac_bits) / b
+// rounds towards zero, overflows wrap around and division by zero is unspecified
+fn def_qdiv(asm: &mut Assembler, name: &str, frac_bits: u16) {
+    use Reg::*;
+
+    let a_positive = format!("{name}_a_positive");
+    let b_positive = format!("{name}_b_positive");
+    let end = format!("{name}_end");
+
+    asm.label(name)
+        .xor(TMP, R1, R2)
+        .push(TMP) // the quotient is negative if the signs differ
+        .add(Z, R1, R1)
+        .jmpnc(&a_positive)
+        .sub(R1, Z, R1)
+        .label(&a_positive)
+        .add(Z, R2, R2)
+        .jmpnc(&b_positive)
+        .sub(R2, Z, R2)
+        .label(&b_positive)
+        .mov(R3, R2)
+        .set(TMP, frac_bits)
+        .shl(R2, R1, TMP)
+        .set(TMP, 16 - frac_bits)
+        .shr(R1, R1, TMP)
+        .call("divu32")
+        .mov(R1, R2)
+        .pop(TMP)
+        .add(Z, TMP, TMP)
+        .jmpnc(&end)
+        .sub(R1, Z, R1)
+        .label(&end)
+        .ret();
+}
+
+// R1: n -> R1: floor(sqrt(n)) (unsigned)
+// digit-by-digit method, unrolled: 266 cycles
+fn def_isqrt(asm: &mut Assembler) {
+    use Reg::*;
+
+    let n = R1;
+    let res = R2;
+    let bit = R3;
+
+    asm.label("isqrt")
+        .set(res, 0)
+        .set(bit, 1)
+        .set(R4, 14)
+        .shl(bit, bit, R4);
+
+    for _ in 0..8 {
+        asm.add(R4, res, bit)
+            .set(TMP, 1)
+            .shr(res, res, TMP)
+            .cmp(n, R4)
+            .sub_if(n, n, R4, Cond::IfCarry) // keeps the carry set
+            .add_if(res, res, bit, Cond::IfCarry)
+            .set(TMP, 2)
+            .shr(bit, bit, TMP);
+    }
+
+    asm.mov(R1, res).ret();
+}
+
+// R1: angle -> R1: sin(angle) or cos(angle) in Q1.15
+// the angle is rounded to the nearest of 1024 steps, so |error| <= 0.0032,
+// and 1.0 is clamped to 0x7fff
+fn def_sin_cos(asm: &mut Assembler) {
+    use Reg::*;
+
+    // cos(a) = sin(a + pi / 2)
+    asm.label("cos")
+        .set(R2, 1)
+        .set(R3, 14)
+        .shl(R2, R2, R3)
+        .add(R1, R1, R2);
+
+    asm.label("sin")
+        .set(R2, 32)
+        .add(R1, R1, R2)
+        .set(R2, 6)
+        .shr(R1, R1, R2) // phase in 0..1024
+        .set(R2, 0xff)
+        .and(R3, R1, R2) // index in the quarter wave table
+        .set(R2, 8)
+        .shr(R4, R1, R2) // quadrant
+        // the 2nd and 4th quadrants read the table backwards
+        .set(R2, 1)
+        .and(Z, R4, R2)
+        .jmpz("sin_lookup")
+        .set(R2, 256)
+        .sub(R3, R2, R3)
+        .label("sin_lookup")
+        .lea(R2, "sin_table")
+        .add(R2, R2, R3)
+        .load(R1, R2, 0)
+        // the 3rd and 4th quadrants are negative
+        .set(R2, 2)
+        .and(Z, R4, R2)
+        .jmpz("sin_end")
+        .sub(R1, Z, R1)
+        .label("sin_end")
+        .ret();
+}
+
+// R1: y, R2: x -> R1: atan2(y, x) as a binary angle
+// |error| <= 24 (about 0.0023 radians), atan2(0, 0) is 0
+fn def_atan2(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("atan2")
+        .push(R1)
+        .push(R2)
+        .add(Z, R1, R1)
+        .jmpnc("atan2_y_positive")
+        .sub(R1, Z, R1)
+        .label("atan2_y_positive")
+        .add(Z, R2, R2)
+        .jmpnc("atan2_x_positive")
+        .sub(R2, Z, R2)
+        .label("atan2_x_positive")
+        // R1 = min(|x|, |y|), R2 = max(|x|, |y|), R4 = swapped
+        .cmp(R2, R1)
+        .set(R4, 0)
+        .jmpc("atan2_sorted")
+        .mov(R3, R1)
+        .mov(R1, R2)
+        .mov(R2, R3)
+        .set(R4, 1)
+        .label("atan2_sorted")
+        .update_flags(R2)
+        .jmpnz("atan2_nonzero")
+        .set(R1, 0)
+        .jmp("atan2_octant");
+
+    // index = round((min << 8) / max) in 0..=256
+    asm.label("atan2_nonzero")
+        .push(R4)
+        .mov(R3, R2)
+        .set(TMP, 8)
+        .shl(R2, R1, TMP)
+        .shr(R1, R1, TMP)
+        .set(TMP, 1)
+        .shr(R4, R3, TMP)
+        .add(R2, R2, R4)
+        .adc(R1, R1, Z)
+        .call("divu32")
+        .lea(R1, "atan_table")
+        .add(R1, R1, R2)
+        .load(R1, R1, 0)
+        // atan(max / min) = pi / 2 - atan(min / max)
+        .pop(R4)
+        .update_flags(R4)
+        .jmpz("atan2_octant")
+        .set(R2, 1)
+        .set(R3, 14)
+        .shl(R2, R2, R3)
+        .sub(R1, R2, R1);
+
+    asm.label("atan2_octant")
+        .pop(R2)
+        .add(Z, R2, R2)
+        .jmpnc("atan2_x_nonnegative")
+        .set(R2, 1)
+        .set(R3, 15)
+        .shl(R2, R2, R3)
+        .sub(R1, R2, R1)
+        .label("atan2_x_nonnegative")
+        .pop(R2)
+        .add(Z, R2, R2)
+        .jmpnc("atan2_end")
+        .sub(R1, Z, R1)
+        .label("atan2_end")
+        .ret();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::procedures::arith::{def_divu32, def_mulu16};
+    use crate::testing::{call, reg, Rng};
+    use std::f64::consts::PI;
+    use Reg::*;
+
+    fn defs(asm: &mut Assembler) {
+        def_fixed(asm);
+        def_mulu16(asm);
+        def_divu32(asm);
+    }
+
+    fn call1(label: &str, a: u16) -> u16 {
+        let (cpu, _) = call(&[defs], label, &[(R1, a)]);
+        reg(&cpu, R1)
+    }
+
+    fn call2(label: &str, a: i16, b: i16) -> i16 {
+        let (cpu, _) = call(&[defs], label, &[(R1, a as u16), (R2, b as u16)]);
+        reg(&cpu, R1) as i16
+    }
+
+    fn random_pairs(seed: u64) -> Vec<(i16, i16)> {
+        let mut rng = Rng::new(seed);
+        let edge = [0, 1, -1, 0x100, -0x100, i16::MAX, i16::MIN];
+        let mut pairs: Vec<(i16, i16)> = edge
+            .iter()
+            .flat_map(|&a| edge.iter().map(move |&b| (a, b)))
+            .collect();
+
+        for _ in 0..100 {
+            let a = rng.next_u16() as i16 >> rng.below(16);
+            let b = rng.next_u16() as i16 >> rng.below(16);
+            pairs.push((a, b));
+        }
+
+        pairs
+    }
+
+    #[test]
+    fn test_qadd() {
+        for (a, b) in random_pairs(1) {
+            assert_eq!(call2("q88_add", a, b), a.saturating_add(b), "{a} + {b}");
+        }
+    }
+
+    #[test]
+    fn test_qmul() {
+        for (a, b) in random_pairs(2) {
+            let product = a as i32 * b as i32;
+            assert_eq!(call2("q88_mul", a, b), (product >> 8) as i16);
+            assert_eq!(call2("q15_mul", a, b), (product >> 15) as i16);
+
+            // without overflow, the result is at most 1 lsb below the exact product
+            let exact = a as f64 * b as f64 / 256.0;
+
+            if exact.abs() < i16::MAX as f64 {
+                let error = exact - call2("q88_mul", a, b) as f64;
+                assert!((0.0..1.0).contains(&error), "{a} * {b}: {error}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_qdiv() {
+        for (a, b) in random_pairs(3).into_iter().filter(|&(_, b)| b != 0) {
+            let (a32, b32) = (a as i32, b as i32);
+            assert_eq!(call2("q88_div", a, b), ((a32 << 8) / b32) as i16);
+            assert_eq!(call2("q15_div", a, b), ((a32 << 15) / b32) as i16);
+
+            // without overflow, the error is less than 1 lsb
+            let exact = a as f64 * 256.0 / b as f64;
+
+            if exact.abs() < i16::MAX as f64 {
+                let error = exact - call2("q88_div", a, b) as f64;
+                assert!(error.abs() < 1.0, "{a} / {b}: {error}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_isqrt() {
+        let mut rng = Rng::new(4);
+        let mut inputs = vec![0, 1, 2, 3, 4, 255, 256, 0x3fff, 0x4000, 0xfffe, 0xffff];
+        inputs.extend((0..50).map(|_| rng.next_u16()));
+
+        for n in inputs {
+            let (cpu, cycles) = call(&[defs], "isqrt", &[(R1, n)]);
+            assert_eq!(reg(&cpu, R1), (n as f64).sqrt() as u16, "sqrt({n})");
+            assert_eq!(cycles, 266);
+        }
+    }
+
+    #[test]
+    fn test_sin_cos() {
+        let mut rng = Rng::new(5);
+        let mut angles: Vec<u16> = (0..64).map(|i| i << 10).collect();
+        angles.extend((0..64).map(|_| rng.next_u16()));
+
+        for angle in angles {
+            let radians = angle as f64 / 32768.0 * PI;
+            let sin = call1("sin", angle) as i16 as f64 / 32768.0;
+            let cos = call1("cos", angle) as i16 as f64 / 32768.0;
+
+            assert!(
+                (sin - radians.sin()).abs() <= 0.0032,
+                "sin({angle:#x}) = {sin}"
+            );
+            assert!(
+                (cos - radians.cos()).abs() <= 0.0032,
+                "cos({angle:#x}) = {cos}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_atan2() {
+        let mut rng = Rng::new(6);
+        let mut points = vec![(0, 0), (0, 1), (1, 0), (0, -1), (-1, 0), (-1, -1)];
+        points.extend([(i16::MIN, i16::MIN), (i16::MAX, i16::MIN), (5, i16::MIN)]);
+        points.extend((0..100).map(|_| {
+            let y = rng.next_u16() as i16 >> rng.below(16);
+            let x = rng.next_u16() as i16 >> rng.below(16);
+            (y, x)
+        }));
+
+        for (y, x) in points {
+            let expected = (y as f64).atan2(x as f64) / PI * 32768.0;
+            let angle = call2("atan2", y, x) as f64;
+            // compare on the circle
+            let error = (angle - expected).rem_euclid(65536.0);
+            let error = error.min(65536.0 - error);
+
+            assert!(
+                error <= 24.0,
+                "atan2({y}, {x}) = {angle}, expected {expected}"
+            );
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use crate::asm::Assembler;
+use crate::isa::Reg;
+
+// Checksums over RAM buffers.
+//
+// Memory is word-addressed, so each routine comes in two flavours:
+// - `crc16` / `fnv16` hash every word as two bytes, high byte first
+//   (the order in which programs are uploaded to the board)
+// - `crc16_bytes` / `fnv16_bytes` hash the low byte of every word,
+//   which is how strings are stored
+// R1: buffer, R2: length in words -> R1: hash
+// R1-R4 and TMP are caller-saved.
+
+// static variables
+const CRC_POLY: &str = "crc16_poly";
+
+// CRC-16/CCITT-FALSE: polynomial 0x1021, initial value 0xffff, no reflection
+pub fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0xffff, |mut crc, &b| {
+        crc ^= (b as u16) << 8;
+
+        for _ in 0..8 {
+            crc = (crc << 1) ^ if crc & 0x8000 != 0 { CCITT_POLY } else { 0 };
+        }
+
+        crc
+    })
+}
+
+// FNV-1a with a 16-bit state: the offset basis and the prime are the
+// high and low words of their 32-bit counterparts
+pub fn fnv16(bytes: &[u8]) -> u16 {
+    bytes
+        .iter()
+        .fold(FNV_BASIS, |h, &b| (h ^ b as u16).wrapping_mul(FNV_PRIME))
+}
+
+// bytes hashed by `crc16` and `fnv16` for a buffer of words
+pub fn word_bytes(words: &[u16]) -> Vec<u8> {
+    words.iter().flat_map(|w| w.to_be_bytes()).collect()
+}
+
+const CCITT_POLY: u16 = 0x1021;
+const FNV_BASIS: u16 = 0x811c;
+const FNV_PRIME: u16 = 0x0193;
+
+pub fn def_hash(asm: &mut Assembler) {
+    asm.static_var(CRC_POLY, 1);
+
+    def_crc16(asm, "crc16", 16);
+    def_crc16(asm, "crc16_bytes", 8);
+    def_fnv16(asm, "fnv16", 16);
+    def_fnv16(asm, "fnv16_bytes", 8);
+}
+
+// bitwise CRC over the `bits` low bits of each word, clobbers R3, R4
+fn def_crc16(asm: &mut Assembler, name: &str, bits: u16) {
+    use Reg::*;
+
+    let loop_label = format!("{name}_loop");
+    let end_label = format!("{name}_end");
+
+    let crc = R3;
+
+    asm.label(name)
+        .setw(R4, CCITT_POLY, TMP)
+        .store_var(R4, CRC_POLY) // TMP is clobbered by the loop jump
+        .dec2(crc, Z)
+        .update_flags(R2)
+        .jmpz(&end_label)
+        .label(&loop_label)
+        .load(R4, R1, 0);
+
+    if bits < 16 {
+        asm.set(TMP, 16 - bits).shl(R4, R4, TMP);
+    }
+
+    asm.xor(crc, crc, R4).load_var(TMP, CRC_POLY);
+
+    for _ in 0..bits {
+        asm.add(crc, crc, crc) // carry = msb
+            .adc(R4, Z, Z)
+            .sub(R4, Z, R4) // all ones if the msb was set
+            .and(R4, R4, TMP)
+            .xor(crc, crc, R4);
+    }
+
+    asm.inc(R1)
+        .dec(R2)
+        .jmpnz(&loop_label)
+        .label(&end_label)
+        .mov(R1, crc)
+        .ret();
+}
+
+// dst = src * k, with one doubling per bit of k (Horner's method)
+fn mul_const(asm: &mut Assembler, dst: Reg, src: Reg, k: u16) {
+    asm.mov(dst, src);
+
+    for i in (0..15 - k.leading_zeros()).rev() {
+        asm.add(dst, dst, dst);
+
+        if k & (1 << i) != 0 {
+            asm.add(dst, dst, src);
+        }
+    }
+}
+
+// FNV-1a over the `bits` low bits of each word, clobbers R3, R4
+fn def_fnv16(asm: &mut Assembler, name: &str, bits: u16) {
+    use Reg::*;
+
+    let loop_label = format!("{name}_loop");
+    let end_label = format!("{name}_end");
+
+    let h = R3;
+
+    // h = (h ^ byte) * prime
+    let round = |asm: &mut Assembler, byte: Reg| {
+        asm.xor(h, h, byte);
+        mul_const(asm, TMP, h, FNV_PRIME);
+        asm.mov(h, TMP);
+    };
+
+    asm.label(name)
+        .setw(h, FNV_BASIS, TMP)
+        .update_flags(R2)
+        .jmpz(&end_label)
+        .label(&loop_label)
+        .load(R4, R1, 0);
+
+    if bits == 16 {
+        asm.set(TMP, 8).shr(TMP, R4, TMP);
+        round(asm, TMP);
+    }
+
+    asm.set(TMP, 0xff).and(R4, R4, TMP);
+    round(asm, R4);
+
+    asm.inc(R1)
+        .dec(R2)
+        .jmpnz(&loop_label)
+        .label(&end_label)
+        .mov(R1, h)
+        .ret();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{call_with, reg, Rng};
+    use Reg::*;
+
+    const BUF: u16 = 0x4000;
+
+    fn hash(label: &str, words: &[u16]) -> u16 {
+        let args = [(R1, BUF), (R2, words.len() as u16)];
+        let (cpu, _) = call_with(&[def_hash], label, &args, |cpu| {
+            cpu.ram[BUF as usize..][..words.len()].copy_from_slice(words);
+        });
+
+        reg(&cpu, R1)
+    }
+
+    fn str_words(s: &str) -> Vec<u16> {
+        s.bytes().map(|b| b as u16).collect()
+    }
+
+    #[test]
+    fn test_golden_vectors() {
+        let packed = [0x3132, 0x3334, 0x3536, 0x3738]; // "12345678"
+
+        assert_eq!(crc16(b"123456789"), 0x29b1);
+        assert_eq!(hash("crc16_bytes", &str_words("123456789")), 0x29b1);
+        assert_eq!(hash("crc16", &packed), 0xa12b);
+        assert_eq!(hash("crc16", &[]), 0xffff);
+        assert_eq!(hash("crc16_bytes", &[]), 0xffff);
+        assert_eq!(hash("crc16", &[0x0068, 0x0069]), 0x4b85);
+
+        assert_eq!(hash("fnv16", &packed), 0x05e4);
+        assert_eq!(hash("fnv16_bytes", &str_words("12345678")), 0x05e4);
+        assert_eq!(hash("fnv16", &[]), 0x811c);
+        assert_eq!(hash("fnv16", &[0x0061]), 0xe52f);
+    }
+
+    #[test]
+    fn test_random_buffers() {
+        let mut rng = Rng::new(31);
+
+        for _ in 0..50 {
+            let len = rng.below(40);
+            let words: Vec<u16> = (0..len).map(|_| rng.next_u16()).collect();
+            let low_bytes: Vec<u8> = words.iter().map(|&w| w as u8).collect();
+
+            assert_eq!(hash("crc16", &words), crc16(&word_bytes(&words)));
+            assert_eq!(hash("crc16_bytes", &words), crc16(&low_bytes));
+            assert_eq!(hash("fnv16", &words), fnv16(&word_bytes(&words)));
+            assert_eq!(hash("fnv16_bytes", &words), fnv16(&low_bytes));
+        }
+    }
+}
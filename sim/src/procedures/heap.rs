@@ -0,0 +1,499 @@
+use crate::asm::Assembler;
+use crate::isa::Reg;
+use crate::procedures::console::CONSOLE_BUFFER;
+
+// Dynamic memory allocator.
+//
+// The heap is a sequence of blocks, each starting with a one-word header
+// holding the size of the block in words (header included), with the msb set
+// when the block is in use. Pointers returned by `malloc` point right after
+// the header.
+// Allocation is first fit, and `free` merges the block with its free
+// neighbours, so two free blocks are never adjacent.
+//
+// heap_init: R1: start, R2: len (2 <= len < 0x8000)
+// malloc: R1: size -> R1: pointer, 0 if there is no free block large enough
+//   or if the size is 0
+// free: R1: pointer, 0 is ignored
+// realloc: R1: pointer, R2: size -> R1: new pointer, 0 on failure (the old
+//   block is then left untouched). The contents are moved to a new block
+//   unless the current one is large enough. Requires `memcpy` (see string.rs).
+// heap_check: -> R1: 0 if the heap is consistent, otherwise the address of
+//   the first invalid block header
+// R1-R4 and TMP are caller-saved.
+//
+// With `def_heap_debug`, malloc, free and realloc check the heap first and
+// halt with the address of the invalid header in R1 if it is corrupted.
+
+// default heap region, from above the zero page to the console buffer
+// at 0x6000-0x72c0. The I/O page (0x7e00-0x7eff) and the stack, which grows
+// up from STACK_POINTER_TOP (0x7f00), lie above the console buffer.
+pub const HEAP_START: u16 = 0x0100;
+pub const HEAP_END: u16 = CONSOLE_BUFFER;
+
+// static variables
+const HEAP_BEGIN_ADDR: &str = "heap_begin";
+const HEAP_END_ADDR: &str = "heap_end";
+
+pub fn def_heap(asm: &mut Assembler) {
+    def_heap_with(asm, false);
+}
+
+pub fn def_heap_debug(asm: &mut Assembler) {
+    def_heap_with(asm, true);
+    def_heap_assert(asm);
+}
+
+fn def_heap_with(asm: &mut Assembler, debug: bool) {
+    asm.static_var(HEAP_BEGIN_ADDR, 1);
+    asm.static_var(HEAP_END_ADDR, 1);
+
+    def_heap_init(asm);
+    def_malloc(asm, debug);
+    def_free(asm, debug);
+    def_realloc(asm, debug);
+    def_heap_check(asm);
+}
+
+fn check_heap(asm: &mut Assembler, debug: bool) {
+    if debug {
+        asm.call("heap_assert");
+    }
+}
+
+// dst = 0x8000, the used flag
+fn used_flag(asm: &mut Assembler, dst: Reg) {
+    asm.set(dst, 1).set(Reg::TMP, 15).shl(dst, dst, Reg::TMP);
+}
+
+fn def_heap_init(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("heap_init")
+        .store_var(R1, HEAP_BEGIN_ADDR)
+        .store(R2, R1, 0) // a single free block
+        .add(R1, R1, R2)
+        .store_var(R1, HEAP_END_ADDR)
+        .ret();
+}
+
+fn def_malloc(asm: &mut Assembler, debug: bool) {
+    use Reg::*;
+
+    asm.label("malloc");
+    check_heap(asm, debug);
+
+    asm.update_flags(R1)
+        .jmpz("malloc_fail")
+        .inc(R1) // header
+        .jmpz("malloc_fail")
+        .load_var(R2, HEAP_BEGIN_ADDR)
+        .label("malloc_loop")
+        .load_var(R3, HEAP_END_ADDR)
+        .cmp(R2, R3)
+        .jmpc("malloc_fail")
+        .load(R3, R2, 0)
+        .add(R4, R3, R3) // carry = used flag
+        .jmpc("malloc_used")
+        .cmp(R3, R1)
+        .jmpc("malloc_found")
+        .add(R2, R2, R3)
+        .jmp("malloc_loop")
+        .label("malloc_used")
+        .set(TMP, 1)
+        .shr(R4, R4, TMP)
+        .add(R2, R2, R4)
+        .jmp("malloc_loop");
+
+    // R2: block, R3: block size, R1: requested size
+    asm.label("malloc_found")
+        .sub(R4, R3, R1)
+        .set(TMP, 2)
+        .cmp(R4, TMP)
+        .jmpnc("malloc_take_block") // the rest would be too small to be split
+        .mov(R3, R1)
+        .add(TMP, R2, R1)
+        .store(R4, TMP, 0)
+        .label("malloc_take_block");
+
+    used_flag(asm, R4);
+
+    asm.or(R3, R3, R4)
+        .store(R3, R2, 0)
+        .mov(R1, R2)
+        .inc(R1)
+        .ret()
+        .label("malloc_fail")
+        .set(R1, 0)
+        .ret();
+}
+
+fn def_free(asm: &mut Assembler, debug: bool) {
+    use Reg::*;
+
+    let block = R2;
+    let cur = R3;
+    let prev = R4;
+
+    asm.label("free");
+    check_heap(asm, debug);
+
+    // look for the block from the start of the heap to find its predecessor
+    asm.update_flags(R1)
+        .jmpz("free_end")
+        .mov(block, R1)
+        .dec(block)
+        .load_var(cur, HEAP_BEGIN_ADDR)
+        .set(prev, 0)
+        .label("free_loop")
+        .cmp(cur, block)
+        .jmpz("free_found")
+        .jmpc("free_end") // not a block, ignored
+        .load_var(TMP, HEAP_END_ADDR)
+        .cmp(cur, TMP)
+        .jmpc("free_end")
+        .load(R1, cur, 0)
+        .add(R1, R1, R1)
+        .set(TMP, 1)
+        .shr(R1, R1, TMP)
+        .mov(prev, cur)
+        .add(cur, cur, R1)
+        .jmp("free_loop");
+
+    // R1: block size
+    asm.label("free_found")
+        .load(R1, block, 0)
+        .add(R1, R1, R1)
+        .set(TMP, 1)
+        .shr(R1, R1, TMP)
+        .store(R1, block, 0)
+        // merge with the next block
+        .add(cur, block, R1)
+        .load_var(TMP, HEAP_END_ADDR)
+        .cmp(cur, TMP)
+        .jmpc("free_merge_prev")
+        .load(cur, cur, 0)
+        .add(Z, cur, cur)
+        .jmpc("free_merge_prev")
+        .add(R1, R1, cur)
+        .store(R1, block, 0)
+        .label("free_merge_prev")
+        .update_flags(prev)
+        .jmpz("free_end")
+        .load(cur, prev, 0)
+        .add(Z, cur, cur)
+        .jmpc("free_end")
+        .add(cur, cur, R1)
+        .store(cur, prev, 0)
+        .label("free_end")
+        .ret();
+}
+
+fn def_realloc(asm: &mut Assembler, debug: bool) {
+    use Reg::*;
+
+    asm.label("realloc");
+    check_heap(asm, debug);
+
+    asm.update_flags(R1)
+        .jmpnz("realloc_not_null")
+        .mov(R1, R2)
+        .jmp("malloc")
+        .label("realloc_not_null")
+        .update_flags(R2)
+        .jmpnz("realloc_resize")
+        .call("free")
+        .set(R1, 0)
+        .ret();
+
+    // R3: capacity of the current block
+    asm.label("realloc_resize")
+        .mov(R3, R1)
+        .dec(R3)
+        .load(R3, R3, 0)
+        .add(R3, R3, R3)
+        .set(TMP, 1)
+        .shr(R3, R3, TMP)
+        .dec(R3)
+        .cmp(R3, R2)
+        .jmpc("realloc_end")
+        .push(R1)
+        .push(R3)
+        .mov(R1, R2)
+        .call("malloc")
+        .pop(R3)
+        .pop(R2)
+        .update_flags(R1)
+        .jmpz("realloc_end")
+        .push(R1)
+        .push(R2)
+        .call("memcpy")
+        .pop(R1)
+        .call("free")
+        .pop(R1)
+        .label("realloc_end")
+        .ret();
+}
+
+fn def_heap_check(asm: &mut Assembler) {
+    use Reg::*;
+
+    let size = R1;
+    let cur = R2;
+    let prev_free = R4;
+
+    asm.label("heap_check")
+        .load_var(cur, HEAP_BEGIN_ADDR)
+        .set(prev_free, 0)
+        .label("heap_check_loop")
+        .load_var(R3, HEAP_END_ADDR)
+        .cmp(cur, R3)
+        .jmpz("heap_check_ok")
+        .load(size, cur, 0)
+        .add(size, size, size)
+        .adc(R3, Z, Z) // used flag
+        .set(TMP, 1)
+        .shr(size, size, TMP)
+        // two adjacent free blocks should have been merged
+        .xor(R3, R3, TMP)
+        .and(TMP, R3, prev_free)
+        .jmpnz("heap_check_invalid")
+        .mov(prev_free, R3)
+        // a block holds at least its header and one word
+        .set(TMP, 2)
+        .cmp(size, TMP)
+        .jmpnc("heap_check_invalid")
+        // and ends before the end of the heap
+        .add(R3, cur, size)
+        .jmpc("heap_check_invalid")
+        .load_var(TMP, HEAP_END_ADDR)
+        .cmp(TMP, R3)
+        .jmpnc("heap_check_invalid")
+        .mov(cur, R3)
+        .jmp("heap_check_loop")
+        .label("heap_check_ok")
+        .set(R1, 0)
+        .ret()
+        .label("heap_check_invalid")
+        .mov(R1, cur)
+        .ret();
+}
+
+// halts if the heap is corrupted, preserves R1 and R2
+fn def_heap_assert(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("heap_assert")
+        .push(R1)
+        .push(R2)
+        .call("heap_check")
+        .update_flags(R1)
+        .jmpz("heap_assert_ok")
+        .halt()
+        .label("heap_assert_ok")
+        .pop(R2)
+        .pop(R1)
+        .ret();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::procedures::string::def_memcpy;
+    use crate::testing::{reg, Rng, Session};
+    use Reg::*;
+
+    const LEN: u16 = 0x400;
+
+    fn session(def: fn(&mut Assembler)) -> Session {
+        let mut s = Session::new(&[def, def_memcpy]);
+        s.call("heap_init", &[(R1, HEAP_START), (R2, LEN)]);
+        s
+    }
+
+    // (address, size, used) of every block
+    fn blocks(s: &Session) -> Vec<(u16, u16, bool)> {
+        let mut blocks = vec![];
+        let mut addr = HEAP_START;
+
+        while addr < HEAP_START + LEN {
+            let header = s.cpu.ram[addr as usize];
+            let size = header & 0x7fff;
+            assert!(size >= 2, "invalid block at {addr:#x}");
+            blocks.push((addr, size, header & 0x8000 != 0));
+            addr += size;
+        }
+
+        assert_eq!(addr, HEAP_START + LEN);
+        blocks
+    }
+
+    fn heap_check(s: &mut Session) -> u16 {
+        s.call("heap_check", &[])
+    }
+
+    #[test]
+    fn test_default_region() {
+        let mut s = Session::new(&[def_heap, def_memcpy]);
+        let len = HEAP_END - HEAP_START;
+        s.call("heap_init", &[(R1, HEAP_START), (R2, len)]);
+
+        // the whole region minus a header, and it stays clear of the console buffer
+        let a = s.call("malloc", &[(R1, len - 1)]);
+        assert_eq!(a, HEAP_START + 1);
+        assert_eq!(s.call("malloc", &[(R1, 1)]), 0);
+        assert_eq!(heap_check(&mut s), 0);
+        assert_eq!(s.cpu.ram[HEAP_END as usize], 0);
+    }
+
+    #[test]
+    fn test_malloc_free() {
+        let mut s = session(def_heap);
+
+        assert_eq!(s.call("malloc", &[(R1, 0)]), 0);
+        assert_eq!(s.call("malloc", &[(R1, LEN)]), 0);
+        assert_eq!(s.call("malloc", &[(R1, 0xffff)]), 0);
+
+        let a = s.call("malloc", &[(R1, 10)]);
+        let b = s.call("malloc", &[(R1, 20)]);
+        assert_eq!(a, HEAP_START + 1);
+        assert_eq!(b, a + 11);
+        assert_eq!(s.cpu.ram[HEAP_START as usize], 0x8000 | 11);
+
+        // the whole heap minus a header
+        assert_eq!(s.call("malloc", &[(R1, LEN - 32 - 1)]), b + 21);
+        assert_eq!(s.call("malloc", &[(R1, 1)]), 0);
+        assert_eq!(heap_check(&mut s), 0);
+
+        // freed blocks are reused
+        s.call("free", &[(R1, a)]);
+        s.call("free", &[(R1, 0)]);
+        assert_eq!(s.call("malloc", &[(R1, 9)]), a);
+        assert_eq!(heap_check(&mut s), 0);
+    }
+
+    #[test]
+    fn test_coalescing() {
+        for order in [[0, 1, 2], [2, 1, 0], [0, 2, 1], [1, 0, 2]] {
+            let mut s = session(def_heap);
+            let ptrs: Vec<u16> = (0..3).map(|_| s.call("malloc", &[(R1, 5)])).collect();
+
+            for i in order {
+                s.call("free", &[(R1, ptrs[i])]);
+                assert_eq!(heap_check(&mut s), 0, "{order:?}");
+            }
+
+            assert_eq!(blocks(&s), [(HEAP_START, LEN, false)], "{order:?}");
+        }
+    }
+
+    #[test]
+    fn test_realloc() {
+        let mut s = session(def_heap);
+
+        let a = s.call("realloc", &[(R1, 0), (R2, 4)]);
+        let guard = s.call("malloc", &[(R1, 1)]);
+        s.cpu.ram[a as usize..][..4].copy_from_slice(&[1, 2, 3, 4]);
+
+        // shrinking keeps the block
+        assert_eq!(s.call("realloc", &[(R1, a), (R2, 2)]), a);
+
+        let b = s.call("realloc", &[(R1, a), (R2, 8)]);
+        assert!(b > guard);
+        assert_eq!(&s.cpu.ram[b as usize..][..4], &[1, 2, 3, 4]);
+        assert_eq!(blocks(&s)[0], (HEAP_START, 5, false));
+
+        // failures leave the block untouched
+        assert_eq!(s.call("realloc", &[(R1, b), (R2, LEN)]), 0);
+        assert_eq!(&s.cpu.ram[b as usize..][..4], &[1, 2, 3, 4]);
+
+        assert_eq!(s.call("realloc", &[(R1, b), (R2, 0)]), 0);
+        s.call("free", &[(R1, guard)]);
+        assert_eq!(blocks(&s), [(HEAP_START, LEN, false)]);
+    }
+
+    #[test]
+    fn test_heap_check() {
+        let mut s = session(def_heap_debug);
+        let a = s.call("malloc", &[(R1, 3)]);
+        let b = s.call("malloc", &[(R1, 3)]);
+        assert_eq!(heap_check(&mut s), 0);
+
+        // overflowing `a` overwrites the header of `b`
+        s.cpu.ram[a as usize + 3] = 0;
+        assert_eq!(heap_check(&mut s), b - 1);
+        assert!(!s.try_call("malloc", &[(R1, 1)]));
+        assert_eq!(reg(&s.cpu, R1), b - 1);
+
+        s.cpu.ram[b as usize - 1] = 0x8000 | (LEN + 1);
+        assert_eq!(heap_check(&mut s), b - 1);
+
+        // adjacent free blocks
+        s.cpu.ram[a as usize - 1] = 4;
+        s.cpu.ram[b as usize - 1] = 4;
+        assert_eq!(heap_check(&mut s), b - 1);
+    }
+
+    #[test]
+    fn test_stress() {
+        let mut rng = Rng::new(32);
+        let mut s = session(def_heap_debug);
+        // (pointer, size, fill value)
+        let mut live: Vec<(u16, u16, u16)> = vec![];
+
+        let fill = |s: &mut Session, (ptr, size, val): (u16, u16, u16)| {
+            s.cpu.ram[ptr as usize..][..size as usize].fill(val);
+        };
+
+        for _ in 0..600 {
+            let op = rng.below(3);
+
+            if op == 0 || live.is_empty() {
+                let size = 1 + rng.below(48) as u16;
+                let first_fit = blocks(&s)
+                    .into_iter()
+                    .find(|&(_, block_size, used)| !used && block_size > size);
+                let ptr = s.call("malloc", &[(R1, size)]);
+
+                assert_eq!(ptr, first_fit.map_or(0, |(addr, _, _)| addr + 1));
+
+                if ptr != 0 {
+                    let alloc = (ptr, size, rng.next_u16());
+                    fill(&mut s, alloc);
+                    live.push(alloc);
+                }
+            } else if op == 1 {
+                let (ptr, _, _) = live.swap_remove(rng.below(live.len()));
+                s.call("free", &[(R1, ptr)]);
+            } else {
+                let i = rng.below(live.len());
+                let (ptr, size, val) = live[i];
+                let new_size = 1 + rng.below(64) as u16;
+                let new_ptr = s.call("realloc", &[(R1, ptr), (R2, new_size)]);
+
+                if new_ptr != 0 {
+                    let kept = size.min(new_size) as usize;
+                    assert!(s.cpu.ram[new_ptr as usize..][..kept]
+                        .iter()
+                        .all(|&w| w == val));
+                    live[i] = (new_ptr, new_size, val);
+                    fill(&mut s, live[i]);
+                }
+            }
+
+            assert_eq!(heap_check(&mut s), 0);
+
+            for &(ptr, size, val) in &live {
+                assert!(s.cpu.ram[ptr as usize..][..size as usize]
+                    .iter()
+                    .all(|&w| w == val));
+            }
+        }
+
+        for (ptr, _, _) in live {
+            s.call("free", &[(R1, ptr)]);
+        }
+
+        assert_eq!(blocks(&s), [(HEAP_START, LEN, false)]);
+    }
+}
@@ -0,0 +1,209 @@
+use crate::asm::Assembler;
+use crate::isa::Reg;
+
+// Example interrupt handlers, see `Assembler::interrupt_handler`.
+// The CPU has a single NMI vector, so a program defines at most one of them,
+// and must be assembled with `Assembler::assemble_at` for the vector to be laid out.
+
+// static variables
+const TICKS: &str = "ticks"; // 32-bit, high word first
+const BUTTON_PRESSES: &str = "button_presses";
+const BUTTON_PENDING: &str = "button_pending";
+
+// counts timer interrupts
+pub fn def_timer_handler(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.static_var(TICKS, 2)
+        .interrupt_handler("timer_handler")
+        .load_var_at(R1, Z, TICKS, 0)
+        .load_var_at(R2, Z, TICKS, 1)
+        .inc(R2)
+        .adc(R1, R1, Z)
+        .store_var_at(R1, Z, TICKS, 0)
+        .store_var_at(R2, Z, TICKS, 1)
+        .restore()
+        .end_interrupt_handler();
+}
+
+// counts button presses and flags them for the main loop,
+// which clears `button_pending` once it has handled them
+pub fn def_button_handler(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.static_var(BUTTON_PRESSES, 1)
+        .static_var(BUTTON_PENDING, 1)
+        .interrupt_handler("button_handler")
+        .load_var(R1, BUTTON_PRESSES)
+        .inc(R1)
+        .store_var(R1, BUTTON_PRESSES)
+        .set(R1, 1)
+        .store_var(R1, BUTTON_PENDING)
+        .restore()
+        .end_interrupt_handler();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isa::{ControlOp, Inst, INTERRUPT_ADDR, STACK_POINTER_TOP};
+    use crate::sim::CPU;
+    use crate::START_PC;
+    use Reg::*;
+
+    const VECTOR: usize = (INTERRUPT_ADDR - START_PC) as usize;
+
+    fn program(def: impl FnOnce(&mut Assembler)) -> (Assembler, Vec<u16>) {
+        let mut asm = Assembler::new();
+        asm.init_sp().halt();
+        def(&mut asm);
+
+        let image = asm.assemble_at(START_PC);
+        (asm, image)
+    }
+
+    // raises an NMI and runs the handler until it returns
+    fn run_handler(cpu: &mut CPU) {
+        let pc = cpu.regs[PC as usize];
+        cpu.raise_nmi();
+        cpu.step();
+        assert_eq!(cpu.regs[PC as usize], INTERRUPT_ADDR);
+
+        for _ in 0..1000 {
+            if cpu.regs[PC as usize] == pc {
+                return;
+            }
+
+            cpu.step();
+        }
+
+        panic!("the interrupt handler did not return");
+    }
+
+    fn handler_words<'a>(asm: &Assembler, image: &'a [u16], label: &str) -> &'a [u16] {
+        let start = asm.label_address(label).unwrap();
+        let end = image[start..]
+            .iter()
+            .position(|&inst| {
+                inst == u16::from(Inst::Ctl {
+                    op: ControlOp::Restore,
+                })
+            })
+            .unwrap();
+
+        &image[start..=start + end]
+    }
+
+    #[test]
+    fn test_timer_handler() {
+        let (asm, image) = program(def_timer_handler);
+        let ticks = asm.var_address(TICKS).unwrap() as usize;
+
+        // small enough to be copied to the vector
+        assert_eq!(
+            &image[VECTOR..],
+            handler_words(&asm, &image, "timer_handler")
+        );
+
+        let mut cpu = CPU::from(&image, START_PC);
+        cpu.ram[ticks..ticks + 2].copy_from_slice(&[0, 0xfffe]);
+
+        for _ in 0..3 {
+            run_handler(&mut cpu);
+        }
+
+        assert_eq!(&cpu.ram[ticks..ticks + 2], &[1, 1]);
+    }
+
+    #[test]
+    fn test_button_handler() {
+        let (asm, image) = program(def_button_handler);
+        let presses = asm.var_address(BUTTON_PRESSES).unwrap() as usize;
+        let pending = asm.var_address(BUTTON_PENDING).unwrap() as usize;
+
+        let mut cpu = CPU::from(&image, START_PC);
+        run_handler(&mut cpu);
+        assert_eq!((cpu.ram[presses], cpu.ram[pending]), (1, 1));
+
+        cpu.ram[pending] = 0;
+        run_handler(&mut cpu);
+        run_handler(&mut cpu);
+        assert_eq!((cpu.ram[presses], cpu.ram[pending]), (3, 1));
+    }
+
+    #[test]
+    fn test_trampoline() {
+        for nops in [0, 20] {
+            let (asm, image) = program(|asm| {
+                asm.label("count")
+                    .load(R1, Z, 0)
+                    .inc(R1)
+                    .store(R1, Z, 0)
+                    .ret()
+                    .interrupt_handler("handler");
+
+                // calling a procedure or not fitting in the vector needs a trampoline
+                if nops == 0 {
+                    asm.call("count");
+                } else {
+                    for _ in 0..nops {
+                        asm.nop();
+                    }
+
+                    asm.load(R1, Z, 0).inc(R1).store(R1, Z, 0);
+                }
+
+                asm.restore().end_interrupt_handler();
+            });
+
+            assert_eq!(image.len(), VECTOR + 6);
+            assert_ne!(&image[VECTOR..], handler_words(&asm, &image, "handler"));
+
+            let mut cpu = CPU::from(&image, START_PC);
+            cpu.regs[SP as usize] = STACK_POINTER_TOP;
+            run_handler(&mut cpu);
+            run_handler(&mut cpu);
+
+            assert_eq!(cpu.ram[0], 2);
+        }
+    }
+
+    #[test]
+    fn test_no_handler() {
+        let mut asm = Assembler::new();
+        asm.halt();
+
+        assert_eq!(
+            asm.assemble_at(START_PC),
+            [u16::from(Inst::Ctl {
+                op: ControlOp::Halt
+            })]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "must end with restore")]
+    fn test_handler_without_restore() {
+        Assembler::new()
+            .interrupt_handler("handler")
+            .nop()
+            .end_interrupt_handler();
+    }
+
+    #[test]
+    #[should_panic(expected = "single interrupt vector")]
+    fn test_two_handlers() {
+        let mut asm = Assembler::new();
+        def_timer_handler(&mut asm);
+        def_button_handler(&mut asm);
+    }
+
+    #[test]
+    #[should_panic(expected = "overlaps with the interrupt vector")]
+    fn test_program_overlapping_vector() {
+        let mut asm = Assembler::new();
+        asm.data(&vec![0; VECTOR + 1]);
+        def_timer_handler(&mut asm);
+        asm.assemble_at(START_PC);
+    }
+}
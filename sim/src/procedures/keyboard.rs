@@ -0,0 +1,75 @@
+use crate::asm::Assembler;
+use crate::isa::Reg;
+use crate::keyboard::{KEYBOARD_BASE, KEYBOARD_CTRL, KEYBOARD_DATA, KEYBOARD_NMI};
+
+// Polling driver for the keyboard of the simulator (see keyboard.rs),
+// which must be mapped at KEYBOARD_BASE.
+// R1-R4 and TMP are caller-saved.
+
+pub fn def_keyboard(asm: &mut Assembler) {
+    def_kbd_getc(asm);
+    def_kbd_enable_nmi(asm);
+}
+
+// -> R1: the character of the next key pressed,
+// skips releases and keys without a character
+fn def_kbd_getc(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("kbd_getc")
+        .setw(R2, KEYBOARD_BASE, TMP)
+        .label("kbd_getc_loop")
+        .load(R1, R2, KEYBOARD_DATA as u8)
+        .setw(R3, 0x8000, TMP)
+        .and(R4, R1, R3)
+        .jmpnz("kbd_getc_loop")
+        .set(R3, 0xff)
+        .and(R1, R1, R3)
+        .jmpz("kbd_getc_loop")
+        .ret();
+}
+
+// raises an NMI whenever a key is pressed
+fn def_kbd_enable_nmi(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("kbd_enable_nmi")
+        .setw(R2, KEYBOARD_BASE, TMP)
+        .set(R1, KEYBOARD_NMI)
+        .store(R1, R2, KEYBOARD_CTRL as u8)
+        .ret();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Device;
+    use crate::keyboard::{Keyboard, KEYBOARD_END};
+    use crate::testing::call_with;
+    use Reg::*;
+
+    #[test]
+    fn test_kbd_getc() {
+        let (cpu, cycles) = call_with(&[def_keyboard], "kbd_getc", &[], |cpu| {
+            let mut keyboard = Keyboard::new();
+            keyboard.type_text("A").unwrap();
+            cpu.bus
+                .map("keyboard", KEYBOARD_BASE..=KEYBOARD_END, keyboard);
+        });
+
+        // shift is pressed first
+        assert_eq!(cpu.regs[R1 as usize], b'A' as u16);
+        assert!(cycles > crate::keyboard::TYPING_INTERVAL, "{cycles}");
+    }
+
+    #[test]
+    fn test_kbd_enable_nmi() {
+        let (mut cpu, _) = call_with(&[def_keyboard], "kbd_enable_nmi", &[], |cpu| {
+            cpu.bus
+                .map("keyboard", KEYBOARD_BASE..=KEYBOARD_END, Keyboard::new());
+        });
+
+        let keyboard = cpu.bus.device_mut::<Keyboard>("keyboard").unwrap();
+        assert_eq!(keyboard.read(KEYBOARD_CTRL), KEYBOARD_NMI);
+    }
+}
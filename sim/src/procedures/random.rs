@@ -0,0 +1,168 @@
+use crate::asm::Assembler;
+use crate::isa::{Cond, Reg};
+
+// Pseudo-random number generators.
+// Each generator has a Rust counterpart so that the sequence produced by a
+// program can be predicted from a seed.
+// R1-R4 and TMP are caller-saved.
+
+// static variables
+const RAND_STATE: &str = "rand_state";
+
+// xorshift with the (7, 9, 8) triple, period 2^16 - 1 (0 is a fixed point)
+pub fn xorshift16(mut x: u16) -> u16 {
+    x ^= x << 7;
+    x ^= x >> 9;
+    x ^= x << 8;
+    x
+}
+
+// Galois LFSR for x^16 + x^14 + x^13 + x^11 + 1, period 2^16 - 1 (0 is a fixed point)
+pub fn lfsr16(x: u16) -> u16 {
+    (x >> 1) ^ (0u16.wrapping_sub(x & 1) & LFSR_TAPS)
+}
+
+const LFSR_TAPS: u16 = 0xb400;
+
+pub fn def_random(asm: &mut Assembler) {
+    asm.static_var(RAND_STATE, 1);
+
+    def_xorshift16(asm);
+    def_lfsr16(asm);
+    def_rand(asm);
+}
+
+// R1: state -> R1: next state
+// clobbers R2
+fn def_xorshift16(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("xorshift16")
+        .set(TMP, 7)
+        .shl(R2, R1, TMP)
+        .xor(R1, R1, R2)
+        .set(TMP, 9)
+        .shr(R2, R1, TMP)
+        .xor(R1, R1, R2)
+        .set(TMP, 8)
+        .shl(R2, R1, TMP)
+        .xor(R1, R1, R2)
+        .ret();
+}
+
+// R1: state -> R1: next state
+// clobbers R2, R3
+fn def_lfsr16(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("lfsr16")
+        .set(R2, 1)
+        .and(R2, R1, R2)
+        .sub(R2, Z, R2) // all ones if the lsb is set
+        .set(TMP, 1)
+        .shr(R1, R1, TMP)
+        .setw(R3, LFSR_TAPS, TMP)
+        .and(R3, R3, R2)
+        .xor(R1, R1, R3)
+        .ret();
+}
+
+// srand: R1: seed
+// a seed of 0 is replaced by 1, since xorshift would get stuck at 0
+// rand: -> R1: next xorshift16 value of the global state
+// clobbers R2
+fn def_rand(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("srand")
+        .update_flags(R1)
+        .set(TMP, 1)
+        .mov_if(R1, TMP, Cond::IfZero)
+        .store_var(R1, RAND_STATE)
+        .ret();
+
+    asm.label("rand")
+        .load_var(R1, RAND_STATE)
+        .call("xorshift16")
+        .store_var(R1, RAND_STATE)
+        .ret();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{call, call_with, reg, var_address};
+    use Reg::*;
+
+    fn period(next: fn(u16) -> u16) -> usize {
+        let mut x = next(1);
+        let mut n = 1;
+
+        while x != 1 {
+            x = next(x);
+            n += 1;
+        }
+
+        n
+    }
+
+    #[test]
+    fn test_reference_periods() {
+        assert_eq!(period(xorshift16), 0xffff);
+        assert_eq!(period(lfsr16), 0xffff);
+        assert_eq!(xorshift16(0), 0);
+        assert_eq!(lfsr16(0), 0);
+    }
+
+    #[test]
+    fn test_xorshift16() {
+        let golden = [
+            0x8181, 0x6021, 0xe999, 0x2e0b, 0xb59e, 0xd9a3, 0x2f27, 0x45f9,
+        ];
+        let mut x = 1;
+
+        for expected in golden {
+            assert_eq!(xorshift16(x), expected);
+            let (cpu, _) = call(&[def_random], "xorshift16", &[(R1, x)]);
+            x = reg(&cpu, R1);
+            assert_eq!(x, expected);
+        }
+    }
+
+    #[test]
+    fn test_lfsr16() {
+        let golden = [
+            0xe270, 0x7138, 0x389c, 0x1c4e, 0x0e27, 0xb313, 0xed89, 0xc2c4,
+        ];
+        let mut x = 0xace1;
+
+        for expected in golden {
+            assert_eq!(lfsr16(x), expected);
+            let (cpu, _) = call(&[def_random], "lfsr16", &[(R1, x)]);
+            x = reg(&cpu, R1);
+            assert_eq!(x, expected);
+        }
+    }
+
+    #[test]
+    fn test_rand() {
+        let rand_state = var_address(&[def_random], RAND_STATE);
+
+        for (seed, state) in [(0, 1), (1, 1), (0xbeef, 0xbeef)] {
+            let (cpu, _) = call(&[def_random], "srand", &[(R1, seed)]);
+            assert_eq!(cpu.ram[rand_state], state);
+        }
+
+        let mut state = 0x1234;
+
+        for _ in 0..16 {
+            let (cpu, _) = call_with(&[def_random], "rand", &[], |cpu| {
+                cpu.ram[rand_state] = state;
+            });
+
+            state = xorshift16(state);
+            assert_eq!(reg(&cpu, R1), state);
+            assert_eq!(cpu.ram[rand_state], state);
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use crate::asm::Assembler;
+use crate::isa::Reg;
+use crate::semihosting::{
+    SEMIHOSTING_BASE, SEMI_ABORT, SEMI_CYCLES, SEMI_CYCLES_LOW, SEMI_EXIT, SEMI_PUTC, SEMI_PUTS,
+    SEMI_PUT_HEX,
+};
+
+// Wrappers around the semihosting ports of the simulator (see semihosting.rs),
+// for test programs which report their results with an exit code.
+// R1-R4 and TMP are caller-saved.
+
+pub fn def_semihosting(asm: &mut Assembler) {
+    def_semi_port(asm, "semi_putc", SEMI_PUTC);
+    def_semi_port(asm, "semi_puts", SEMI_PUTS);
+    def_semi_port(asm, "semi_print_hex", SEMI_PUT_HEX);
+    def_semi_port(asm, "semi_exit", SEMI_EXIT);
+    def_semi_port(asm, "semi_abort", SEMI_ABORT);
+    def_semi_cycles(asm);
+    def_semi_assert_eq(asm);
+}
+
+// R1: value written to the port
+fn def_semi_port(asm: &mut Assembler, name: &str, port: u16) {
+    use Reg::*;
+
+    asm.label(name)
+        .setw(R4, SEMIHOSTING_BASE, TMP)
+        .store(R1, R4, port as u8)
+        .ret();
+}
+
+// -> R1: high word, R2: low word of the low 32 bits of the cycle counter
+fn def_semi_cycles(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("semi_cycles")
+        .setw(R4, SEMIHOSTING_BASE, TMP)
+        .load(R1, R4, SEMI_CYCLES as u8) // latches the counter
+        .load(R1, R4, SEMI_CYCLES_LOW as u8 - 1)
+        .load(R2, R4, SEMI_CYCLES_LOW as u8)
+        .ret();
+}
+
+// R1: actual, R2: expected, R3: message
+// prints "<message>: <actual> != <expected>" and exits with 1 if they differ
+fn def_semi_assert_eq(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("semi_assert_eq")
+        .cmp(R1, R2)
+        .jump_if_ne("semi_assert_eq_fail")
+        .ret()
+        .label("semi_assert_eq_fail")
+        .setw(R4, SEMIHOSTING_BASE, TMP)
+        .store(R3, R4, SEMI_PUTS as u8)
+        .set(R3, b':' as u16)
+        .store(R3, R4, SEMI_PUTC as u8)
+        .set(R3, b' ' as u16)
+        .store(R3, R4, SEMI_PUTC as u8)
+        .store(R1, R4, SEMI_PUT_HEX as u8)
+        .store(R3, R4, SEMI_PUTC as u8)
+        .set(R1, b'!' as u16)
+        .store(R1, R4, SEMI_PUTC as u8)
+        .set(R1, b'=' as u16)
+        .store(R1, R4, SEMI_PUTC as u8)
+        .store(R3, R4, SEMI_PUTC as u8)
+        .store(R2, R4, SEMI_PUT_HEX as u8)
+        .set(R1, b'\n' as u16)
+        .store(R1, R4, SEMI_PUTC as u8)
+        .set(R1, 1)
+        .store(R1, R4, SEMI_EXIT as u8)
+        .halt();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::run_semihosted;
+    use Reg::*;
+
+    // checks sum(1..=n) with semi_assert_eq, then exits with 0
+    fn sum_test(n: u16, expected: u16) -> Vec<u16> {
+        let mut asm = Assembler::new();
+        asm.init_sp()
+            .set(R1, 0)
+            .set(R2, n)
+            .label("loop")
+            .add(R1, R1, R2)
+            .dec(R2)
+            .jmpnz("loop")
+            .setw(R2, expected, TMP)
+            .lea(R3, "message")
+            .call("semi_assert_eq")
+            .set(R1, 0)
+            .call("semi_exit")
+            .halt()
+            .label("message")
+            .data(&[b's' as u16, b'u' as u16, b'm' as u16, 0]);
+
+        def_semihosting(&mut asm);
+        asm.assemble()
+    }
+
+    #[test]
+    fn test_assert_eq() {
+        assert_eq!(run_semihosted(&sum_test(10, 55)), (Some(0), String::new()));
+        assert_eq!(
+            run_semihosted(&sum_test(10, 56)),
+            (Some(1), "sum: 0037 != 0038\n".to_string())
+        );
+    }
+
+    #[test]
+    fn test_cycles() {
+        let mut asm = Assembler::new();
+        asm.init_sp()
+            .call("semi_cycles")
+            .push(R2)
+            .call("semi_cycles")
+            .pop(R1)
+            .sub(R1, R2, R1)
+            .call("semi_print_hex")
+            .halt();
+        def_semihosting(&mut asm);
+
+        let (code, output) = run_semihosted(&asm.assemble());
+        let elapsed = u16::from_str_radix(&output, 16).unwrap();
+
+        assert_eq!(code, None);
+        // a call to semi_cycles and a push
+        assert!((50..100).contains(&elapsed), "{elapsed}");
+    }
+}
@@ -0,0 +1,164 @@
+use crate::asm::Assembler;
+use crate::isa::Reg;
+use crate::storage::{
+    SECTOR_WORDS, STORAGE_BASE, STORAGE_BUSY, STORAGE_CMD, STORAGE_DATA, STORAGE_ERROR,
+    STORAGE_INDEX, STORAGE_READ, STORAGE_SECTOR, STORAGE_SECTOR_LOW, STORAGE_STATUS, STORAGE_WRITE,
+};
+
+// Driver for the block storage of the simulator (see storage.rs),
+// which must be mapped at STORAGE_BASE. Sectors are SECTOR_WORDS words,
+// only the first 0x10000 sectors of an image can be addressed.
+// R1-R4 and TMP are caller-saved.
+
+pub fn def_storage(asm: &mut Assembler) {
+    def_blk_command(asm);
+    def_blk_read(asm);
+    def_blk_write(asm);
+}
+
+// R1: sector, R3: command, leaves STORAGE_BASE in R4
+// -> R1: 0 or STORAGE_ERROR, with the flags set accordingly
+fn def_blk_command(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("blk_command")
+        .setw(R4, STORAGE_BASE, TMP)
+        .store(Z, R4, STORAGE_SECTOR as u8)
+        .store(R1, R4, STORAGE_SECTOR_LOW as u8)
+        .store(R3, R4, STORAGE_CMD as u8)
+        .set(R3, STORAGE_BUSY)
+        .label("blk_command_wait")
+        .load(R1, R4, STORAGE_STATUS as u8)
+        .and(TMP, R1, R3)
+        .jmpnz("blk_command_wait")
+        .set(R3, STORAGE_ERROR)
+        .and(R1, R1, R3)
+        .ret();
+}
+
+// R1: sector, R2: destination
+// -> R1: 0 or STORAGE_ERROR, the destination is left untouched on error
+fn def_blk_read(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("blk_read")
+        .set(R3, STORAGE_READ)
+        .call("blk_command")
+        .update_flags(R1)
+        .jmpnz("blk_read_end")
+        .store(Z, R4, STORAGE_INDEX as u8)
+        .set(R3, SECTOR_WORDS as u16)
+        .label("blk_read_loop")
+        .load(TMP, R4, STORAGE_DATA as u8)
+        .store(TMP, R2, 0)
+        .inc(R2)
+        .dec(R3)
+        .jmpnz("blk_read_loop")
+        .label("blk_read_end")
+        .ret();
+}
+
+// R1: sector, R2: source
+// -> R1: 0 or STORAGE_ERROR
+fn def_blk_write(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("blk_write")
+        .setw(R4, STORAGE_BASE, TMP)
+        .store(Z, R4, STORAGE_INDEX as u8)
+        .set(R3, SECTOR_WORDS as u16)
+        .label("blk_write_loop")
+        .load(TMP, R2, 0)
+        .store(TMP, R4, STORAGE_DATA as u8)
+        .inc(R2)
+        .dec(R3)
+        .jmpnz("blk_write_loop")
+        .set(R3, STORAGE_WRITE)
+        .call("blk_command")
+        .ret();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::CPU;
+    use crate::storage::{Storage, STORAGE_END};
+    use crate::testing::call_with;
+    use crate::START_PC;
+    use Reg::*;
+
+    const BUFFER: u16 = 0x1000;
+
+    fn with_image(sectors: usize) -> impl FnOnce(&mut CPU) {
+        move |cpu| {
+            // every word holds its index in the image
+            let image = (0..sectors * SECTOR_WORDS)
+                .flat_map(|i| (i as u16).to_le_bytes())
+                .collect();
+
+            cpu.bus.map(
+                "storage",
+                STORAGE_BASE..=STORAGE_END,
+                Storage::from_bytes(image),
+            );
+        }
+    }
+
+    #[test]
+    fn test_blk_read() {
+        let (cpu, _) = call_with(
+            &[def_storage],
+            "blk_read",
+            &[(R1, 2), (R2, BUFFER)],
+            with_image(3),
+        );
+
+        let start = BUFFER as usize;
+        assert_eq!(cpu.regs[R1 as usize], 0);
+        assert_eq!(cpu.ram[start], 0x200);
+        assert_eq!(cpu.ram[start + SECTOR_WORDS - 1], 0x2ff);
+        assert_eq!(cpu.ram[start + SECTOR_WORDS], 0);
+    }
+
+    #[test]
+    fn test_blk_read_out_of_range() {
+        let (cpu, _) = call_with(
+            &[def_storage],
+            "blk_read",
+            &[(R1, 3), (R2, BUFFER)],
+            with_image(3),
+        );
+
+        assert_eq!(cpu.regs[R1 as usize], STORAGE_ERROR);
+        assert_eq!(cpu.ram[BUFFER as usize + 1], 0);
+    }
+
+    #[test]
+    fn test_blk_write() {
+        let copy = BUFFER + SECTOR_WORDS as u16;
+        let mut asm = Assembler::new();
+        asm.init_sp()
+            .set(R1, 1)
+            .setw(R2, BUFFER, TMP)
+            .call("blk_write")
+            .push(R1)
+            .set(R1, 1)
+            .setw(R2, copy, TMP)
+            .call("blk_read")
+            .pop(R2)
+            .halt();
+        def_storage(&mut asm);
+
+        let mut cpu = CPU::from(&asm.assemble(), START_PC);
+        with_image(2)(&mut cpu);
+        cpu.ram[BUFFER as usize..][..SECTOR_WORDS].fill(0xbeef);
+        cpu.run();
+
+        assert_eq!(cpu.regs[R1 as usize], 0);
+        assert_eq!(cpu.regs[R2 as usize], 0);
+        assert_eq!(
+            cpu.ram[copy as usize..][..SECTOR_WORDS],
+            cpu.ram[BUFFER as usize..][..SECTOR_WORDS]
+        );
+    }
+}
@@ -0,0 +1,461 @@
+use crate::asm::Assembler;
+use crate::isa::{Cond, Reg};
+
+// Memory is word-addressed: buffers are arrays of words and strings hold one
+// character per word, terminated by a 0 word (as written by `itoa`).
+// Arguments are passed in R1, R2, R3 in C order, results are returned in R1.
+// R1-R4 and TMP are caller-saved.
+
+// R1: dst, R2: src, R3: len
+// overlapping buffers are handled like memmove
+pub fn def_memcpy(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("memcpy")
+        .update_flags(R3)
+        .jmpz("memcpy_end")
+        // copy backwards when dst is after src
+        .cmp(R2, R1)
+        .jmpc("memcpy_forward_loop")
+        .add(R1, R1, R3)
+        .add(R2, R2, R3)
+        .label("memcpy_backward_loop")
+        .dec(R1)
+        .dec(R2)
+        .load(R4, R2, 0)
+        .store(R4, R1, 0)
+        .dec(R3)
+        .jmpnz("memcpy_backward_loop")
+        .ret()
+        .label("memcpy_forward_loop")
+        .load(R4, R2, 0)
+        .store(R4, R1, 0)
+        .inc(R1)
+        .inc(R2)
+        .dec(R3)
+        .jmpnz("memcpy_forward_loop")
+        .label("memcpy_end")
+        .ret();
+}
+
+// R1: dst, R2: value, R3: len
+pub fn def_memset(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("memset")
+        .update_flags(R3)
+        .jmpz("memset_end")
+        .label("memset_loop")
+        .store(R2, R1, 0)
+        .inc(R1)
+        .dec(R3)
+        .jmpnz("memset_loop")
+        .label("memset_end")
+        .ret();
+}
+
+// R1: a, R2: b, R3: len -> R1: 0 if equal, 1 if a > b, 0xffff if a < b
+// words are compared as unsigned values
+pub fn def_memcmp(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("memcmp")
+        .update_flags(R3)
+        .jmpz("memcmp_equal")
+        .label("memcmp_loop")
+        .load(R4, R1, 0)
+        .load(TMP, R2, 0)
+        .cmp(R4, TMP)
+        .jmpnz("memcmp_diff")
+        .inc(R1)
+        .inc(R2)
+        .dec(R3)
+        .jmpnz("memcmp_loop")
+        .label("memcmp_equal")
+        .set(R1, 0)
+        .ret()
+        .label("memcmp_diff");
+
+    order_result(asm, R4, R2);
+}
+
+// R1: str -> R1: length, not counting the null terminator
+pub fn def_strlen(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("strlen")
+        .mov(R2, R1)
+        .label("strlen_loop")
+        .load(R3, R1, 0)
+        .update_flags(R3)
+        .jmpz("strlen_end")
+        .inc(R1)
+        .jmp("strlen_loop")
+        .label("strlen_end")
+        .sub(R1, R1, R2)
+        .ret();
+}
+
+// R1: a, R2: b -> R1: 0 if equal, 1 if a > b, 0xffff if a < b
+pub fn def_strcmp(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("strcmp")
+        .load(R3, R1, 0)
+        .load(R4, R2, 0)
+        .cmp(R3, R4)
+        .jmpnz("strcmp_diff")
+        .update_flags(R3)
+        .jmpz("strcmp_end")
+        .inc(R1)
+        .inc(R2)
+        .jmp("strcmp")
+        .label("strcmp_end")
+        .set(R1, 0)
+        .ret()
+        .label("strcmp_diff");
+
+    order_result(asm, R3, R2);
+}
+
+// R1: dst, R2: src -> R1: address of the null terminator copied to dst
+pub fn def_strcpy(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("strcpy")
+        .load(R3, R2, 0)
+        .store(R3, R1, 0)
+        .update_flags(R3)
+        .jmpz("strcpy_end")
+        .inc(R1)
+        .inc(R2)
+        .jmp("strcpy")
+        .label("strcpy_end")
+        .ret();
+}
+
+// R1: str, R2: char -> R1: address of the first occurrence of char, 0 if not found
+// searching for 0 returns the address of the null terminator
+pub fn def_strchr(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("strchr")
+        .load(R3, R1, 0)
+        .cmp(R3, R2)
+        .jmpz("strchr_end")
+        .update_flags(R3)
+        .jmpz("strchr_not_found")
+        .inc(R1)
+        .jmp("strchr")
+        .label("strchr_not_found")
+        .set(R1, 0)
+        .label("strchr_end")
+        .ret();
+}
+
+// R1: str -> R1: value
+// parses an optional '-' followed by decimal digits, stopping at the first
+// non-digit character. Overflows wrap around like u16 arithmetic.
+pub fn def_atoi(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("atoi")
+        .set(R2, 0) // value
+        .load(R3, R1, 0)
+        .set(R4, b'-' as u16)
+        .cmp(R3, R4)
+        .set(R4, 0) // is negative
+        .jmpnz("atoi_push_sign")
+        .inc(R1)
+        .set(R4, 1)
+        .label("atoi_push_sign")
+        .push(R4)
+        .label("atoi_loop")
+        .load(R3, R1, 0)
+        .set(R4, b'0' as u16)
+        .sub(R3, R3, R4)
+        .set(R4, 10)
+        .cmp(R3, R4)
+        .jmpc("atoi_end") // not a digit
+        // value = value * 10 + digit
+        .add(R4, R2, R2)
+        .add(R2, R4, R4)
+        .add(R2, R2, R2)
+        .add(R2, R2, R4)
+        .add(R2, R2, R3)
+        .inc(R1)
+        .jmp("atoi_loop")
+        .label("atoi_end")
+        .mov(R1, R2)
+        .pop(R4)
+        .update_flags(R4)
+        .sub_if(R1, Z, R2, Cond::IfNotZero)
+        .ret();
+}
+
+// R1: buffer, R2: len
+// reverses the words of the buffer in place
+pub fn def_reverse(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("reverse")
+        .add(R2, R1, R2) // end of the buffer
+        .label("reverse_loop")
+        // stop when less than two words remain between R1 and R2
+        .sub(R3, R2, R1)
+        .set(R4, 2)
+        .cmp(R3, R4)
+        .jmpnc("reverse_end")
+        .dec(R2)
+        .load(R3, R1, 0)
+        .load(R4, R2, 0)
+        .store(R4, R1, 0)
+        .store(R3, R2, 0)
+        .inc(R1)
+        .jmp("reverse_loop")
+        .label("reverse_end")
+        .ret();
+}
+
+// a != b -> R1: 1 if a > b, 0xffff otherwise
+// taken jumps clobber the flags, so the comparison is redone here
+fn order_result(asm: &mut Assembler, a: Reg, b_ptr: Reg) {
+    use Reg::*;
+
+    asm.load(TMP, b_ptr, 0)
+        .cmp(a, TMP)
+        .set(R1, 1)
+        .sub_if(R1, Z, R1, Cond::IfNotCarry)
+        .ret();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::CPU;
+    use crate::testing::{call_with, reg, Rng};
+    use std::cmp::Ordering;
+    use Reg::*;
+
+    const A: u16 = 0x1000;
+    const B: u16 = 0x2000;
+
+    fn write(cpu: &mut CPU, addr: u16, words: &[u16]) {
+        cpu.ram[addr as usize..addr as usize + words.len()].copy_from_slice(words);
+    }
+
+    fn random_str(rng: &mut Rng, alphabet: &[u8]) -> Vec<u16> {
+        let len = rng.below(20);
+        let mut s: Vec<u16> = (0..len)
+            .map(|_| alphabet[rng.below(alphabet.len())] as u16)
+            .collect();
+        s.push(0);
+        s
+    }
+
+    fn ordering(ord: Ordering) -> u16 {
+        match ord {
+            Ordering::Less => 0xffff,
+            Ordering::Equal => 0,
+            Ordering::Greater => 1,
+        }
+    }
+
+    #[test]
+    fn test_memcpy() {
+        let mut rng = Rng::new(27);
+
+        for i in 0..200 {
+            let data: Vec<u16> = (0..64).map(|_| rng.next_u16()).collect();
+            let len = if i < 10 { 0 } else { rng.below(32) as u16 };
+            // overlapping in both directions
+            let src = A + rng.below(32) as u16;
+            let dst = A + rng.below(32) as u16;
+
+            let (cpu, _) = call_with(
+                &[def_memcpy],
+                "memcpy",
+                &[(R1, dst), (R2, src), (R3, len)],
+                |cpu| write(cpu, A, &data),
+            );
+
+            let mut expected = data.clone();
+            let offset = |addr: u16| (addr - A) as usize;
+            expected.copy_within(offset(src)..offset(src) + len as usize, offset(dst));
+            assert_eq!(&cpu.ram[A as usize..A as usize + 64], &expected[..]);
+        }
+    }
+
+    #[test]
+    fn test_memset() {
+        let mut rng = Rng::new(28);
+
+        for _ in 0..100 {
+            let len = rng.below(16) as u16;
+            let val = rng.next_u16();
+            let (cpu, _) = call_with(
+                &[def_memset],
+                "memset",
+                &[(R1, A + 1), (R2, val), (R3, len)],
+                |cpu| write(cpu, A, &[0x1234; 18]),
+            );
+
+            let mut expected = [0x1234; 18];
+            expected[1..=len as usize].fill(val);
+            assert_eq!(&cpu.ram[A as usize..A as usize + 18], &expected[..]);
+        }
+    }
+
+    #[test]
+    fn test_memcmp() {
+        let mut rng = Rng::new(29);
+
+        for i in 0..200 {
+            let len = if i < 10 { 0 } else { rng.below(16) };
+            let a: Vec<u16> = (0..len).map(|_| rng.next_u16() & 0x8003).collect();
+            let mut b = a.clone();
+
+            if len > 0 && rng.below(3) != 0 {
+                b[rng.below(len)] = rng.next_u16() & 0x8003;
+            }
+
+            let (cpu, _) = call_with(
+                &[def_memcmp],
+                "memcmp",
+                &[(R1, A), (R2, B), (R3, len as u16)],
+                |cpu| {
+                    write(cpu, A, &a);
+                    write(cpu, B, &b);
+                },
+            );
+
+            assert_eq!(reg(&cpu, R1), ordering(a.cmp(&b)), "{a:?} {b:?}");
+        }
+    }
+
+    #[test]
+    fn test_strlen() {
+        let mut rng = Rng::new(30);
+
+        for _ in 0..100 {
+            let s = random_str(&mut rng, b"abc xyz");
+            let (cpu, _) = call_with(&[def_strlen], "strlen", &[(R1, A)], |cpu| write(cpu, A, &s));
+
+            assert_eq!(reg(&cpu, R1) as usize, s.len() - 1);
+        }
+    }
+
+    #[test]
+    fn test_strcmp() {
+        let mut rng = Rng::new(31);
+
+        for _ in 0..200 {
+            // a small alphabet makes common prefixes likely
+            let a = random_str(&mut rng, b"ab");
+            let b = random_str(&mut rng, b"ab");
+            let b = if rng.below(4) == 0 { a.clone() } else { b };
+
+            let (cpu, _) = call_with(&[def_strcmp], "strcmp", &[(R1, A), (R2, B)], |cpu| {
+                write(cpu, A, &a);
+                write(cpu, B, &b);
+            });
+
+            assert_eq!(reg(&cpu, R1), ordering(a.cmp(&b)), "{a:?} {b:?}");
+        }
+    }
+
+    #[test]
+    fn test_strcpy() {
+        let mut rng = Rng::new(32);
+
+        for _ in 0..100 {
+            let s = random_str(&mut rng, b"hello");
+            let (cpu, _) = call_with(&[def_strcpy], "strcpy", &[(R1, B), (R2, A)], |cpu| {
+                write(cpu, A, &s);
+                write(cpu, B, &[0xffff; 32]);
+            });
+
+            assert_eq!(&cpu.ram[B as usize..B as usize + s.len()], &s[..]);
+            assert_eq!(cpu.ram[B as usize + s.len()], 0xffff);
+            assert_eq!(reg(&cpu, R1) as usize, B as usize + s.len() - 1);
+        }
+    }
+
+    #[test]
+    fn test_strchr() {
+        let mut rng = Rng::new(33);
+
+        for _ in 0..200 {
+            let s = random_str(&mut rng, b"abcdef");
+            let c = b"abcdefg\0"[rng.below(8)] as u16;
+            let (cpu, _) = call_with(&[def_strchr], "strchr", &[(R1, A), (R2, c)], |cpu| {
+                write(cpu, A, &s)
+            });
+
+            let expected = s.iter().position(|&x| x == c).map_or(0, |i| A + i as u16);
+            assert_eq!(reg(&cpu, R1), expected);
+        }
+    }
+
+    #[test]
+    fn test_atoi() {
+        let mut rng = Rng::new(34);
+        let mut inputs = vec![
+            "".to_string(),
+            "-".to_string(),
+            "0".to_string(),
+            "65535".to_string(),
+            "65536".to_string(),
+            "-32768".to_string(),
+            "12abc".to_string(),
+            "x12".to_string(),
+        ];
+
+        // '/' comes right before '0', it must end the number like any other non-digit
+        for _ in 0..100 {
+            inputs.push(format!("{}/9", rng.next_u32() as i32 >> rng.below(32)));
+        }
+
+        for input in inputs {
+            let mut s: Vec<u16> = input.bytes().map(|b| b as u16).collect();
+            s.push(0);
+
+            let (cpu, _) = call_with(&[def_atoi], "atoi", &[(R1, A)], |cpu| write(cpu, A, &s));
+
+            let digits = input.strip_prefix('-').unwrap_or(&input);
+            let value = digits
+                .bytes()
+                .take_while(u8::is_ascii_digit)
+                .fold(0u16, |acc, d| {
+                    acc.wrapping_mul(10).wrapping_add((d - b'0') as u16)
+                });
+            let expected = if input.starts_with('-') {
+                value.wrapping_neg()
+            } else {
+                value
+            };
+
+            assert_eq!(reg(&cpu, R1), expected, "{input:?}");
+            assert_eq!(reg(&cpu, SP), crate::isa::STACK_POINTER_TOP);
+        }
+    }
+
+    #[test]
+    fn test_reverse() {
+        let mut rng = Rng::new(35);
+
+        for i in 0..100 {
+            let len = if i < 5 { i } else { rng.below(32) };
+            let data: Vec<u16> = (0..len).map(|_| rng.next_u16()).collect();
+            let (cpu, _) = call_with(
+                &[def_reverse],
+                "reverse",
+                &[(R1, A), (R2, len as u16)],
+                |cpu| write(cpu, A, &data),
+            );
+
+            let expected: Vec<u16> = data.iter().rev().copied().collect();
+            assert_eq!(&cpu.ram[A as usize..A as usize + len], &expected[..]);
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use crate::asm::Assembler;
+use crate::isa::Reg;
+use crate::timer::{
+    TIMER_BASE, TIMER_CTRL, TIMER_ENABLE, TIMER_NMI, TIMER_PRESCALER, TIMER_RELOAD,
+};
+
+// Driver for the timer of the simulator (see timer.rs),
+// which must be mapped at TIMER_BASE. See `interrupts::def_timer_handler`
+// for a handler counting its interrupts.
+// R1-R4 and TMP are caller-saved.
+
+pub fn def_timer(asm: &mut Assembler) {
+    def_timer_start(asm);
+    def_timer_stop(asm);
+}
+
+// R1: reload, R2: prescaler
+// raises an NMI every `R1 * (R2 + 1)` cycles
+fn def_timer_start(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("timer_start")
+        .setw(R4, TIMER_BASE, TMP)
+        .store(R1, R4, TIMER_RELOAD as u8)
+        .store(R2, R4, TIMER_PRESCALER as u8)
+        .set(R3, TIMER_ENABLE | TIMER_NMI)
+        .store(R3, R4, TIMER_CTRL as u8)
+        .ret();
+}
+
+fn def_timer_stop(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("timer_stop")
+        .setw(R4, TIMER_BASE, TMP)
+        .store(Z, R4, TIMER_CTRL as u8)
+        .ret();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::procedures::interrupts::def_timer_handler;
+    use crate::sim::CPU;
+    use crate::timer::{Timer, TIMER_END};
+    use crate::START_PC;
+    use Reg::*;
+
+    #[test]
+    fn test_periodic_interrupts() {
+        let mut asm = Assembler::new();
+        asm.init_sp()
+            .setw(R1, 1000, TMP)
+            .set(R2, 9)
+            .call("timer_start")
+            .label("wait")
+            .load_var_at(R1, Z, "ticks", 1)
+            .set(R2, 5)
+            .cmp(R1, R2)
+            .jump_if_ne("wait")
+            .call("timer_stop")
+            .halt();
+
+        def_timer(&mut asm);
+        def_timer_handler(&mut asm);
+
+        let mut cpu = CPU::from(&asm.assemble_at(START_PC), START_PC);
+        cpu.bus.map("timer", TIMER_BASE..=TIMER_END, Timer::new());
+        cpu.run();
+
+        // an interrupt every 10000 cycles
+        assert!((50_000..51_000).contains(&cpu.cycles), "{}", cpu.cycles);
+    }
+}
@@ -0,0 +1,133 @@
+use crate::asm::Assembler;
+use crate::isa::Reg;
+use crate::uart::{UART_BASE, UART_DATA, UART_RX_READY, UART_STATUS, UART_TX_IDLE, UART_TX_READY};
+
+// Polling driver for the UART of the simulator (see uart.rs),
+// which must be mapped at UART_BASE.
+// R1-R4 and TMP are caller-saved.
+
+pub fn def_uart(asm: &mut Assembler) {
+    def_uart_wait(asm, "uart_wait_tx_ready", UART_TX_READY);
+    def_uart_wait(asm, "uart_wait_rx_ready", UART_RX_READY);
+    def_uart_wait(asm, "uart_flush", UART_TX_IDLE);
+    def_uart_putc(asm);
+    def_uart_getc(asm);
+    def_uart_puts(asm);
+}
+
+// waits until a status bit is set, leaves UART_BASE in R2
+fn def_uart_wait(asm: &mut Assembler, name: &str, mask: u16) {
+    use Reg::*;
+
+    let loop_label = format!("{name}_loop");
+
+    asm.label(name)
+        .setw(R2, UART_BASE, TMP)
+        .setw(R3, mask, TMP)
+        .label(&loop_label)
+        .load(R4, R2, UART_STATUS as u8)
+        .and(R4, R4, R3)
+        .jmpz(&loop_label)
+        .ret();
+}
+
+// R1: byte
+fn def_uart_putc(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("uart_putc")
+        .call("uart_wait_tx_ready")
+        .store(R1, R2, UART_DATA as u8)
+        .ret();
+}
+
+// -> R1: byte, waits until one is received
+fn def_uart_getc(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("uart_getc")
+        .call("uart_wait_rx_ready")
+        .load(R1, R2, UART_DATA as u8)
+        .ret();
+}
+
+// R1: null-terminated string
+fn def_uart_puts(asm: &mut Assembler) {
+    use Reg::*;
+
+    asm.label("uart_puts")
+        .push(R1)
+        .label("uart_puts_loop")
+        .pop(R1)
+        .load(TMP, R1, 0)
+        .inc(R1)
+        .push(R1)
+        .mov(R1, TMP)
+        .update_flags(R1)
+        .jmpz("uart_puts_end")
+        .call("uart_putc")
+        .jmp("uart_puts_loop")
+        .label("uart_puts_end")
+        .pop(R1)
+        .ret();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Device;
+    use crate::sim::CPU;
+    use crate::testing::call_with;
+    use crate::uart::{Uart, UartConfig, UART_END};
+    use Reg::*;
+
+    const STR: u16 = 0x1000;
+
+    fn with_uart(input: &'static [u8]) -> impl FnOnce(&mut CPU) {
+        move |cpu| {
+            let mut uart = Uart::new(UartConfig::baud(4, 115_200));
+            uart.send(input);
+            cpu.bus.map("uart", UART_BASE..=UART_END, uart);
+        }
+    }
+
+    fn uart(cpu: &mut CPU) -> &mut Uart {
+        cpu.bus.device_mut::<Uart>("uart").unwrap()
+    }
+
+    #[test]
+    fn test_uart_puts() {
+        let text = b"hello, world!\n";
+        let (mut cpu, cycles) = call_with(&[def_uart], "uart_puts", &[(R1, STR)], |cpu| {
+            with_uart(b"")(cpu);
+
+            for (i, &byte) in text.iter().enumerate() {
+                cpu.ram[STR as usize + i] = byte as u16;
+            }
+        });
+
+        // the FIFO holds 4 bytes, puts waits for the line
+        let cycles_per_byte = UartConfig::baud(4, 115_200).cycles_per_byte;
+        assert!(cycles >= (text.len() as u64 - 5) * cycles_per_byte);
+
+        uart(&mut cpu).flush();
+        assert_eq!(uart(&mut cpu).sent(), text);
+    }
+
+    #[test]
+    fn test_uart_getc() {
+        let (mut cpu, _) = call_with(&[def_uart], "uart_getc", &[], with_uart(b"ab"));
+        assert_eq!(cpu.regs[R1 as usize], b'a' as u16);
+        assert_eq!(uart(&mut cpu).read(UART_DATA), 0, "b is still on the line");
+    }
+
+    #[test]
+    fn test_uart_flush() {
+        let (mut cpu, _) = call_with(&[def_uart], "uart_flush", &[], |cpu| {
+            with_uart(b"")(cpu);
+            uart(cpu).write(UART_DATA, b'x' as u16);
+        });
+
+        assert_eq!(uart(&mut cpu).sent(), b"x");
+    }
+}
@@ -0,0 +1,200 @@
+use crate::bus::{CycleCounter, Device, Machine};
+use crate::isa::IO_PAGE;
+
+pub const SEMIHOSTING_BASE: u16 = IO_PAGE + 0x10;
+pub const SEMIHOSTING_END: u16 = SEMIHOSTING_BASE + SEMI_CYCLES_LOW;
+
+// registers, relative to SEMIHOSTING_BASE
+// writing prints the low byte as a character
+pub const SEMI_PUTC: u16 = 0;
+// writing prints the null-terminated string at that address
+pub const SEMI_PUTS: u16 = 1;
+// writing prints the value as 4 hex digits
+pub const SEMI_PUT_HEX: u16 = 2;
+// writing exits with that code, 0 means success
+pub const SEMI_EXIT: u16 = 3;
+// writing prints the null-terminated message at that address and exits with ABORT_CODE
+pub const SEMI_ABORT: u16 = 4;
+// the 64-bit cycle counter, high word first:
+// reading the high word latches the whole counter
+pub const SEMI_CYCLES: u16 = 5;
+pub const SEMI_CYCLES_LOW: u16 = SEMI_CYCLES + 3;
+
+pub const ABORT_CODE: u16 = 0xffff;
+
+// Host services for programs running in the simulator,
+// a program ends with an exit code by writing to SEMI_EXIT.
+pub struct Semihosting {
+    // print the output to stdout as well
+    pub echo: bool,
+    output: String,
+    // a string to print once the RAM is accessible
+    pending: Option<(u16, Option<u16>)>,
+    cycles: CycleCounter,
+    exit_code: Option<u16>,
+}
+
+impl Semihosting {
+    pub fn new() -> Self {
+        Semihosting {
+            echo: true,
+            output: String::new(),
+            pending: None,
+            cycles: CycleCounter::default(),
+            exit_code: None,
+        }
+    }
+
+    // everything the program printed
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    fn print(&mut self, text: &str) {
+        if self.echo {
+            print!("{text}");
+        }
+
+        self.output.push_str(text);
+    }
+}
+
+impl Default for Semihosting {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Device for Semihosting {
+    fn read(&mut self, offset: u16) -> u16 {
+        match offset {
+            SEMI_CYCLES..=SEMI_CYCLES_LOW => self.cycles.read(offset - SEMI_CYCLES),
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset: u16, val: u16) {
+        match offset {
+            SEMI_PUTC => self.print(&char::from(val as u8).to_string()),
+            SEMI_PUTS => self.pending = Some((val, None)),
+            SEMI_PUT_HEX => self.print(&format!("{val:04x}")),
+            SEMI_EXIT => self.exit_code = Some(val),
+            SEMI_ABORT => self.pending = Some((val, Some(ABORT_CODE))),
+            _ => {}
+        }
+    }
+
+    // strings are read from RAM right after the store, before the next instruction
+    fn tick(&mut self, cycles: u64, machine: &mut Machine) {
+        self.cycles.tick(cycles);
+
+        if let Some((addr, exit_code)) = self.pending.take() {
+            let text: String = (addr..=u16::MAX)
+                .map(|addr| machine.ram[addr as usize])
+                .take_while(|&c| c != 0)
+                .map(|c| char::from(c as u8))
+                .collect();
+
+            if exit_code.is_some() {
+                self.print(&format!("abort: {text}\n"));
+                self.exit_code = exit_code;
+            } else {
+                self.print(&text);
+            }
+        }
+
+        if let Some(code) = self.exit_code.take() {
+            machine.exit(code);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::Assembler;
+    use crate::isa::Reg;
+    use crate::sim::CPU;
+    use crate::START_PC;
+    use Reg::*;
+
+    const MESSAGE: u16 = 0x1000;
+
+    fn run(asm: &mut Assembler) -> (CPU, Option<u16>) {
+        let mut cpu = CPU::from(&asm.assemble(), START_PC);
+        let mut semihosting = Semihosting::new();
+        semihosting.echo = false;
+        cpu.bus.map(
+            "semihosting",
+            SEMIHOSTING_BASE..=SEMIHOSTING_END,
+            semihosting,
+        );
+
+        for (i, byte) in b"bad state".iter().enumerate() {
+            cpu.ram[MESSAGE as usize + i] = *byte as u16;
+        }
+
+        let code = cpu.run();
+        (cpu, code)
+    }
+
+    fn output(cpu: &CPU) -> &str {
+        cpu.bus
+            .device::<Semihosting>("semihosting")
+            .unwrap()
+            .output()
+    }
+
+    #[test]
+    fn test_print_and_exit() {
+        let (cpu, code) = run(Assembler::new()
+            .setw(R4, SEMIHOSTING_BASE, TMP)
+            .set(R1, b'>' as u16)
+            .store(R1, R4, SEMI_PUTC as u8)
+            .setw(R1, MESSAGE, TMP)
+            .store(R1, R4, SEMI_PUTS as u8)
+            .setw(R1, 0xbeef, TMP)
+            .store(R1, R4, SEMI_PUT_HEX as u8)
+            .set(R1, 3)
+            .store(R1, R4, SEMI_EXIT as u8)
+            .set(R1, b'!' as u16)
+            .store(R1, R4, SEMI_PUTC as u8)
+            .halt());
+
+        assert_eq!(code, Some(3));
+        assert_eq!(output(&cpu), ">bad statebeef");
+    }
+
+    #[test]
+    fn test_abort() {
+        let (cpu, code) = run(Assembler::new()
+            .setw(R4, SEMIHOSTING_BASE, TMP)
+            .setw(R1, MESSAGE, TMP)
+            .store(R1, R4, SEMI_ABORT as u8)
+            .halt());
+
+        assert_eq!(code, Some(ABORT_CODE));
+        assert_eq!(output(&cpu), "abort: bad state\n");
+    }
+
+    #[test]
+    fn test_halt_without_exit() {
+        let (_, code) = run(Assembler::new().halt());
+        assert_eq!(code, None);
+    }
+
+    #[test]
+    fn test_cycles() {
+        let (cpu, _) = run(Assembler::new()
+            .setw(R4, SEMIHOSTING_BASE, TMP)
+            .load(R1, R4, SEMI_CYCLES as u8)
+            .load(R2, R4, SEMI_CYCLES_LOW as u8)
+            .load(R3, R4, SEMI_CYCLES_LOW as u8)
+            .halt());
+
+        // setw (set, set, shl, set, or) before the first load
+        assert_eq!(cpu.regs[R1 as usize], 0);
+        assert_eq!(cpu.regs[R2 as usize], 3 + 3 + 4 + 3 + 4);
+        assert_eq!(cpu.regs[R3 as usize], 3 + 3 + 4 + 3 + 4, "latched");
+    }
+}
@@ -1,14 +1,45 @@
-use crate::isa::{AluOp, Cond, ControlOp, Inst, Reg, STACK_POINTER_TOP};
+use crate::bus::{Bus, Machine};
+use crate::isa::{AluOp, Cond, ControlOp, Inst, Reg, INTERRUPT_ADDR, STACK_POINTER_TOP};
 use serde::Serialize;
+use std::ops::Range;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum MemoryModel {
+    // instructions are fetched from the RAM that loads and stores access,
+    // like on the board where the programmer writes the program into RAM
+    #[default]
+    Unified,
+    // instructions are fetched from `rom`, which stores never modify
+    Harvard,
+}
 
 pub struct CPU {
     pub regs: [u16; 8],
     pub halted: bool,
     pub carry: bool,
     pub zero: bool,
-    pub rom: [u16; 0x10000],
-    pub ram: [u16; 0x10000],
+    pub rom: Box<[u16; 0x10000]>,
+    pub ram: Box<[u16; 0x10000]>,
+    pub cycles: u64,
+    pub memory_model: MemoryModel,
+    // devices mapped over the RAM, see `Bus::board`
+    pub bus: Bus,
+    // report stores into the program loaded by `CPU::new` or `CPU::from` in `warnings`
+    pub warn_on_code_writes: bool,
+    pub warnings: Vec<String>,
+    pub exit_code: Option<u16>,
+    code: Range<usize>,
     next_pc: u16,
+    nmi_pending: bool,
+    backup: InterruptBackup,
+}
+
+// registers R1-PC and flags saved when an interrupt is taken
+#[derive(Default)]
+struct InterruptBackup {
+    regs: [u16; 7],
+    zero: bool,
+    carry: bool,
 }
 
 #[derive(Serialize, Debug)]
@@ -26,23 +57,89 @@ pub struct CPUState {
 }
 
 impl CPU {
-    pub fn new(rom: [u16; 0x10000], start_address: u16) -> Self {
+    // `image` is loaded into both the ROM and the RAM, see `MemoryModel`.
+    // The program is taken to end at its last non-zero word.
+    pub fn new(image: [u16; 0x10000], start_address: u16) -> Self {
+        let start = start_address as usize;
+        let end = image[start..]
+            .iter()
+            .rposition(|&word| word != 0)
+            .map_or(start, |last| start + last + 1);
+
+        let mut cpu = Self::with_rom(Box::new(image), start_address);
+        cpu.ram.copy_from_slice(&image);
+        cpu.code = start..end;
+
+        cpu
+    }
+
+    fn with_rom(rom: Box<[u16; 0x10000]>, start_address: u16) -> Self {
         Self {
             regs: [0, 0, 0, 0, 0, 0, 0, start_address],
             halted: false,
             carry: false,
             zero: false,
             rom,
-            ram: [0; 0x10000],
+            ram: Box::new([0; 0x10000]),
+            cycles: 0,
+            memory_model: MemoryModel::default(),
+            bus: Bus::board(),
+            warn_on_code_writes: false,
+            warnings: Vec::new(),
+            exit_code: None,
+            code: 0..0,
             next_pc: start_address,
+            nmi_pending: false,
+            backup: InterruptBackup::default(),
         }
     }
 
     pub fn from(prog: &[u16], start_address: u16) -> Self {
-        let mut rom = [0; 0x10000];
-        rom[(start_address as usize)..(start_address as usize + prog.len())].copy_from_slice(prog);
+        let mut rom = Box::new([0; 0x10000]);
+        let range = (start_address as usize)..(start_address as usize + prog.len());
+        rom[range.clone()].copy_from_slice(prog);
+
+        // the board loads programs into RAM, so data emitted with the program can be read
+        let mut cpu = Self::with_rom(rom, start_address);
+        cpu.ram[range.clone()].copy_from_slice(prog);
+        cpu.code = range;
+
+        cpu
+    }
+
+    // data accesses go through the bus, the RAM answers where no device is mapped
+    pub fn read(&mut self, addr: u16) -> u16 {
+        match self.bus.read(addr) {
+            Some(val) => val,
+            None => self.ram[addr as usize],
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, val: u16) {
+        if !self.bus.write(addr, val) {
+            self.ram[addr as usize] = val;
+        }
+    }
+
+    // reads an instruction according to the memory model
+    pub fn fetch(&self, addr: u16) -> u16 {
+        match self.memory_model {
+            MemoryModel::Unified => self.ram[addr as usize],
+            MemoryModel::Harvard => self.rom[addr as usize],
+        }
+    }
 
-        Self::new(rom, start_address)
+    // the reset of RegisterFile.veryl: registers and flags are cleared and
+    // the CPU restarts at `start_address`, memory and devices are left as is
+    pub fn reset(&mut self, start_address: u16) {
+        self.regs = [0, 0, 0, 0, 0, 0, 0, start_address];
+        self.next_pc = start_address;
+        self.halted = false;
+        self.exit_code = None;
+        self.carry = false;
+        self.zero = false;
+        self.nmi_pending = false;
+        self.backup = InterruptBackup::default();
     }
 
     pub fn set_reg(&mut self, reg: Reg, val: u16) {
@@ -53,10 +150,14 @@ impl CPU {
         }
     }
 
-    pub fn run(&mut self) {
+    // returns the exit code of the program if it exited through a device
+    // (see semihosting.rs) rather than with a halt instruction
+    pub fn run(&mut self) -> Option<u16> {
         while !self.halted {
             self.step();
         }
+
+        self.exit_code
     }
 
     pub fn run_with_fuel(&mut self, fuel: usize, verbose: bool) -> Option<usize> {
@@ -82,10 +183,74 @@ impl CPU {
         }
     }
 
+    // latches a non-maskable interrupt, which is taken at the next fetch.
+    // A halted CPU never takes it, and nothing prevents an interrupt raised
+    // inside the handler from overwriting the backup.
+    pub fn raise_nmi(&mut self) {
+        self.nmi_pending = true;
+    }
+
+    pub fn nmi_pending(&self) -> bool {
+        self.nmi_pending
+    }
+
+    // backs up the registers and flags and jumps to the handler instead of
+    // fetching an instruction, in a single cycle like the fetch stage of CPU.veryl
+    fn take_interrupt(&mut self) {
+        self.backup = InterruptBackup {
+            regs: self.regs[1..].try_into().unwrap(),
+            zero: self.zero,
+            carry: self.carry,
+        };
+
+        self.nmi_pending = false;
+        self.regs[Reg::PC as usize] = INTERRUPT_ADDR;
+        self.cycles += 1;
+        self.tick(1);
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        let mut machine = Machine::new(&mut self.ram);
+        self.bus.tick(cycles, &mut machine);
+        let (nmi, exit_code, stall) = (machine.nmi, machine.exit_code, machine.stall);
+
+        for (addr, val) in machine.writes {
+            if !self.bus.write(addr, val) {
+                self.ram[addr as usize] = val;
+            }
+        }
+
+        if nmi {
+            self.raise_nmi();
+        }
+
+        if exit_code.is_some() {
+            self.exit_code = exit_code;
+            self.halted = true;
+        }
+
+        if stall > 0 {
+            self.cycles += stall;
+            self.tick(stall);
+        }
+    }
+
     pub fn step(&mut self) {
+        // the hardware stops clocking the pipeline once halted
+        if self.halted {
+            return;
+        }
+
+        if self.nmi_pending {
+            self.take_interrupt();
+            return;
+        }
+
         let pc = self.regs[Reg::PC as usize];
-        let inst = Inst::from(self.rom[pc as usize]);
+        let inst = Inst::from(self.fetch(pc));
         self.next_pc = pc.wrapping_add(1);
+        let cycles = inst.cycles();
+        self.cycles += cycles;
 
         match inst {
             Inst::Ctl { op } => {
@@ -95,8 +260,12 @@ impl CPU {
                     ControlOp::Clrz => self.zero = false,
                     ControlOp::Setc => self.carry = true,
                     ControlOp::Clrc => self.carry = false,
+                    // restores the flags and the registers, including PC
                     ControlOp::Restore => {
-                        todo!("Restore after interrupt not implemented");
+                        self.zero = self.backup.zero;
+                        self.carry = self.backup.carry;
+                        self.regs[1..].copy_from_slice(&self.backup.regs);
+                        self.next_pc = self.backup.regs[Reg::PC as usize - 1];
                     }
                 };
             }
@@ -112,9 +281,15 @@ impl CPU {
                 let addr = self.regs[addr as usize].wrapping_add(offset as u16);
 
                 if load {
-                    self.set_reg(dst, self.ram[addr as usize]);
+                    let val = self.read(addr);
+                    self.set_reg(dst, val);
                 } else {
-                    self.ram[addr as usize] = self.regs[dst as usize];
+                    if self.warn_on_code_writes && self.code.contains(&(addr as usize)) {
+                        let warning = format!("{pc:04x}: store to {addr:04x} modifies the program");
+                        self.warnings.push(warning);
+                    }
+
+                    self.write(addr, self.regs[dst as usize]);
                 }
             }
             Inst::Alu {
@@ -126,7 +301,9 @@ impl CPU {
                 let a = self.regs[src1 as usize];
                 let b = self.regs[src2 as usize];
                 let mut cond_met = true;
-                let mut new_carry = self.carry;
+                // ALU.veryl sets the flags to {out == 0, out[16]} for every operation,
+                // the logic and shift operations leave out[16] at 0 and so clear the carry
+                let mut new_carry = false;
 
                 let out = match op {
                     AluOp::And => a & b,
@@ -170,6 +347,7 @@ impl CPU {
         }
 
         self.regs[Reg::PC as usize] = self.next_pc;
+        self.tick(cycles);
     }
 
     pub fn get_state(&self) -> CPUState {
@@ -208,7 +386,7 @@ impl std::fmt::Display for CPU {
             write!(f, "  {}: {:04x}", Reg::from((i + 1) as u16), reg)?;
         }
 
-        let inst = self.rom[self.regs[Reg::PC as usize] as usize];
+        let inst = self.fetch(self.regs[Reg::PC as usize]);
         let inst = Inst::from(inst);
 
         write!(
@@ -235,3 +413,231 @@ impl std::fmt::Display for CPU {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::Assembler;
+    use crate::START_PC;
+    use Reg::*;
+
+    const NOPS: u16 = 8;
+
+    // sets every register and flag, then executes nops before halting
+    fn program() -> CPU {
+        let mut asm = Assembler::new();
+
+        asm.init_sp()
+            .set(R1, 1)
+            .set(R2, 2)
+            .set(R3, 3)
+            .set(R4, 4)
+            .set(TMP, 5)
+            .setc()
+            .clrz()
+            .label("nops");
+
+        for _ in 0..NOPS {
+            asm.nop();
+        }
+
+        asm.halt()
+            .interrupt_handler("handler")
+            .set(R1, 0x11)
+            .set(R2, 0x22)
+            .set(R3, 0x33)
+            .set(R4, 0x44)
+            .set(TMP, 0x55)
+            .set(SP, 0x66)
+            .clrc()
+            .setz()
+            .restore()
+            .end_interrupt_handler();
+
+        let nops = START_PC + asm.label_address("nops").unwrap() as u16;
+        let mut cpu = CPU::from(&asm.assemble_at(START_PC), START_PC);
+
+        while cpu.regs[PC as usize] != nops {
+            cpu.step();
+        }
+
+        cpu
+    }
+
+    fn state(cpu: &CPU) -> ([u16; 8], bool, bool) {
+        (cpu.regs, cpu.zero, cpu.carry)
+    }
+
+    // steps until a restore instruction has been executed
+    fn run_handler(cpu: &mut CPU) {
+        loop {
+            let inst = Inst::from(cpu.fetch(cpu.regs[PC as usize]));
+            cpu.step();
+
+            if let Inst::Ctl {
+                op: ControlOp::Restore,
+            } = inst
+            {
+                return;
+            }
+        }
+    }
+
+    // overwrites `set r1, 1` with `set r1, 2` before executing it
+    fn self_modifying() -> CPU {
+        let patch = Inst::Set { dst: R1, val: 2 };
+        let mut asm = Assembler::new();
+
+        asm.setw(R2, patch.into(), TMP)
+            .lea(R3, "patched")
+            .store(R2, R3, 0)
+            .label("patched")
+            .set(R1, 1)
+            .halt();
+
+        CPU::from(&asm.assemble(), START_PC)
+    }
+
+    #[test]
+    fn test_logic_ops_clear_carry() {
+        let mut asm = Assembler::new();
+        asm.set(R1, 1).setc().shr(R1, R1, R1).halt();
+
+        let mut cpu = CPU::from(&asm.assemble(), START_PC);
+        cpu.run();
+
+        assert_eq!((cpu.carry, cpu.zero), (false, true));
+    }
+
+    #[test]
+    fn test_unified_memory() {
+        let mut cpu = self_modifying();
+        cpu.run();
+
+        assert_eq!(cpu.regs[R1 as usize], 2);
+        assert!(cpu.warnings.is_empty());
+
+        let mut cpu = self_modifying();
+        cpu.warn_on_code_writes = true;
+        cpu.run();
+
+        assert_eq!(cpu.warnings.len(), 1);
+    }
+
+    #[test]
+    fn test_code_writes_in_image() {
+        let prog = self_modifying().ram;
+        let mut cpu = CPU::new(*prog, START_PC);
+        cpu.warn_on_code_writes = true;
+        cpu.run();
+
+        assert_eq!(cpu.regs[R1 as usize], 2);
+        assert_eq!(cpu.warnings.len(), 1);
+    }
+
+    #[test]
+    fn test_harvard_memory() {
+        let mut cpu = self_modifying();
+        cpu.memory_model = MemoryModel::Harvard;
+        cpu.run();
+
+        // the store only reaches the RAM
+        assert_eq!(cpu.regs[R1 as usize], 1);
+        assert_ne!(
+            cpu.fetch(cpu.regs[R3 as usize]),
+            cpu.ram[cpu.regs[R3 as usize] as usize]
+        );
+    }
+
+    #[test]
+    fn test_stack_overflow_into_code() {
+        // a program right after the stack, which pushes until it overwrites itself
+        let mut asm = Assembler::new();
+        asm.set(R1, 0).label("loop").push(R1).jmp("loop").halt();
+
+        let start = STACK_POINTER_TOP + 0x10;
+
+        for (model, halts) in [(MemoryModel::Unified, true), (MemoryModel::Harvard, false)] {
+            let mut cpu = CPU::from(&asm.assemble(), start);
+            cpu.memory_model = model;
+            cpu.regs[SP as usize] = STACK_POINTER_TOP;
+
+            // the pushed zeros are halt instructions
+            assert_eq!(cpu.run_with_fuel(1000, false).is_some(), halts);
+        }
+    }
+
+    // follows CPU.veryl: the fetch stage backs up R1-PC and the flags and
+    // writes INTERRUPT_ADDR to PC on the same edge, restore reinstates them
+    #[test]
+    fn test_nmi_round_trip() {
+        let mut cpu = program();
+        cpu.step();
+        let before = state(&cpu);
+        let cycles = cpu.cycles;
+
+        cpu.raise_nmi();
+        assert_eq!(state(&cpu), before, "taken at the next fetch");
+        assert!(cpu.nmi_pending());
+
+        // entering the handler executes no instruction
+        cpu.step();
+        assert_eq!(cpu.regs[PC as usize], INTERRUPT_ADDR);
+        assert_eq!(cpu.regs[..PC as usize], before.0[..PC as usize]);
+        assert_eq!(cpu.cycles, cycles + 1);
+        assert!(!cpu.nmi_pending());
+
+        run_handler(&mut cpu);
+        assert_eq!(state(&cpu), before);
+
+        cpu.run();
+        assert_eq!(cpu.regs[R1 as usize], 1);
+    }
+
+    #[test]
+    fn test_nmi_latch() {
+        let mut cpu = program();
+        let pc = cpu.regs[PC as usize];
+
+        // raising the line several times before the fetch is a single interrupt
+        cpu.raise_nmi();
+        cpu.raise_nmi();
+        cpu.step();
+        run_handler(&mut cpu);
+
+        assert_eq!(cpu.regs[PC as usize], pc);
+        cpu.step();
+        assert_eq!(cpu.regs[PC as usize], pc + 1);
+    }
+
+    #[test]
+    fn test_nmi_while_halted() {
+        let mut cpu = program();
+        cpu.run();
+        let before = state(&cpu);
+
+        cpu.raise_nmi();
+        cpu.step();
+
+        assert_eq!(state(&cpu), before);
+        assert!(cpu.nmi_pending());
+    }
+
+    #[test]
+    fn test_nested_nmi_overwrites_backup() {
+        let mut cpu = program();
+        cpu.raise_nmi();
+        cpu.step();
+        cpu.step(); // set r1, 0x11
+
+        // nothing masks interrupts inside the handler
+        cpu.raise_nmi();
+        cpu.step();
+        assert_eq!(cpu.regs[PC as usize], INTERRUPT_ADDR);
+
+        // the first return goes back to the handler
+        run_handler(&mut cpu);
+        assert_eq!(cpu.regs[PC as usize], INTERRUPT_ADDR + 1);
+        assert_eq!(cpu.regs[R1 as usize], 0x11);
+    }
+}
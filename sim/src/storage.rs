@@ -0,0 +1,297 @@
+use crate::bus::{Device, Machine};
+use crate::isa::IO_PAGE;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+
+pub const STORAGE_BASE: u16 = IO_PAGE + 0x40;
+pub const STORAGE_END: u16 = STORAGE_BASE + STORAGE_SECTORS_LOW;
+
+// registers, relative to STORAGE_BASE
+// writing starts a command, ignored while busy
+pub const STORAGE_CMD: u16 = 0;
+pub const STORAGE_STATUS: u16 = 1;
+// the 32-bit sector of the next command, high word first
+pub const STORAGE_SECTOR: u16 = 2;
+pub const STORAGE_SECTOR_LOW: u16 = STORAGE_SECTOR + 1;
+// the word of the sector buffer accessed through STORAGE_DATA
+pub const STORAGE_INDEX: u16 = 4;
+// the data window: accesses the sector buffer at STORAGE_INDEX and increments it
+pub const STORAGE_DATA: u16 = 5;
+// the number of sectors of the image, high word first
+pub const STORAGE_SECTORS: u16 = 6;
+pub const STORAGE_SECTORS_LOW: u16 = STORAGE_SECTORS + 1;
+
+// commands
+// loads the sector into the sector buffer
+pub const STORAGE_READ: u16 = 1;
+// writes the sector buffer to the sector
+pub const STORAGE_WRITE: u16 = 2;
+
+// status bits
+pub const STORAGE_BUSY: u16 = 1 << 0;
+// the last command failed: the sector is out of the image, or the host failed
+pub const STORAGE_ERROR: u16 = 1 << 1;
+
+pub const SECTOR_WORDS: usize = 256;
+// words are stored low byte first, like bin files
+pub const SECTOR_BYTES: u64 = 2 * SECTOR_WORDS as u64;
+// time taken by a command, 100 µs like a typical SD card
+pub const COMMAND_CYCLES: u64 = 10_000;
+
+trait Image: Read + Write + Seek {}
+
+impl<T: Read + Write + Seek> Image for T {}
+
+// An SD-card-like block device backed by an image on the host,
+// which is read and written one sector at a time.
+pub struct Storage {
+    image: Box<dyn Image>,
+    sectors: u32,
+    buffer: [u16; SECTOR_WORDS],
+    index: usize,
+    sector: u32,
+    status: u16,
+    // the command in progress and the cycles it has left
+    command: Option<(u16, u64)>,
+}
+
+impl Storage {
+    fn new(image: Box<dyn Image>, len: u64) -> Self {
+        Storage {
+            image,
+            sectors: (len / SECTOR_BYTES) as u32,
+            buffer: [0; SECTOR_WORDS],
+            index: 0,
+            sector: 0,
+            status: 0,
+            command: None,
+        }
+    }
+
+    // an image file, see `create_image`
+    pub fn open(path: &str) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let len = file.metadata()?.len();
+        Ok(Storage::new(Box::new(file), len))
+    }
+
+    // an image held in memory
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        let len = bytes.len() as u64;
+        Storage::new(Box::new(Cursor::new(bytes)), len)
+    }
+
+    pub fn sectors(&self) -> u32 {
+        self.sectors
+    }
+
+    fn run_command(&mut self, command: u16) -> io::Result<()> {
+        if self.sector >= self.sectors {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+
+        self.image
+            .seek(SeekFrom::Start(self.sector as u64 * SECTOR_BYTES))?;
+
+        match command {
+            STORAGE_READ => {
+                let mut bytes = [0; SECTOR_BYTES as usize];
+                self.image.read_exact(&mut bytes)?;
+
+                for (word, chunk) in self.buffer.iter_mut().zip(bytes.chunks_exact(2)) {
+                    *word = u16::from_le_bytes([chunk[0], chunk[1]]);
+                }
+            }
+            _ => {
+                let bytes: Vec<u8> = self.buffer.iter().flat_map(|w| w.to_le_bytes()).collect();
+                self.image.write_all(&bytes)?;
+                self.image.flush()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Device for Storage {
+    fn read(&mut self, offset: u16) -> u16 {
+        match offset {
+            STORAGE_STATUS => self.status,
+            STORAGE_SECTOR => (self.sector >> 16) as u16,
+            STORAGE_SECTOR_LOW => self.sector as u16,
+            STORAGE_INDEX => self.index as u16,
+            STORAGE_DATA => {
+                let word = self.buffer[self.index];
+                self.index = (self.index + 1) % SECTOR_WORDS;
+                word
+            }
+            STORAGE_SECTORS => (self.sectors >> 16) as u16,
+            STORAGE_SECTORS_LOW => self.sectors as u16,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset: u16, val: u16) {
+        match offset {
+            STORAGE_CMD if self.command.is_none() => {
+                if matches!(val, STORAGE_READ | STORAGE_WRITE) {
+                    self.command = Some((val, COMMAND_CYCLES));
+                    self.status = STORAGE_BUSY;
+                } else {
+                    self.status = STORAGE_ERROR;
+                }
+            }
+            STORAGE_SECTOR => self.sector = (val as u32) << 16 | (self.sector & 0xffff),
+            STORAGE_SECTOR_LOW => self.sector = (self.sector & 0xffff_0000) | val as u32,
+            STORAGE_INDEX => self.index = val as usize % SECTOR_WORDS,
+            STORAGE_DATA => {
+                self.buffer[self.index] = val;
+                self.index = (self.index + 1) % SECTOR_WORDS;
+            }
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self, cycles: u64, _machine: &mut Machine) {
+        let Some((command, left)) = self.command else {
+            return;
+        };
+
+        if left > cycles {
+            self.command = Some((command, left - cycles));
+            return;
+        }
+
+        self.command = None;
+        self.index = 0;
+        self.status = match self.run_command(command) {
+            Ok(()) => 0,
+            Err(_) => STORAGE_ERROR,
+        };
+    }
+}
+
+// a zeroed image of `sectors` sectors
+pub fn create_image(path: &str, sectors: u32) -> io::Result<()> {
+    let file = File::create(path)?;
+    file.set_len(sectors as u64 * SECTOR_BYTES)
+}
+
+// writes the bytes of a host file to the image, from the start of `sector`
+pub fn write_image(path: &str, sector: u32, bytes: &[u8]) -> io::Result<()> {
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    let len = file.metadata()?.len();
+    let start = sector as u64 * SECTOR_BYTES;
+
+    if start + bytes.len() as u64 > len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} bytes do not fit at sector {sector}", bytes.len()),
+        ));
+    }
+
+    file.seek(SeekFrom::Start(start))?;
+    file.write_all(bytes)
+}
+
+// a hex dump of a sector, 8 words per line prefixed with the index of the first one
+pub fn dump_sector(path: &str, sector: u32) -> io::Result<String> {
+    let mut storage = Storage::open(path)?;
+    storage.sector = sector;
+    storage.run_command(STORAGE_READ)?;
+
+    let lines: Vec<String> = storage
+        .buffer
+        .chunks(8)
+        .enumerate()
+        .map(|(i, words)| {
+            let words: Vec<String> = words.iter().map(|w| format!("{w:04x}")).collect();
+            format!("{:02x}: {}", 8 * i, words.join(" "))
+        })
+        .collect();
+
+    Ok(lines.join("\n") + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::tick;
+
+    fn command(storage: &mut Storage, sector: u32, command: u16) -> u16 {
+        storage.write(STORAGE_SECTOR, (sector >> 16) as u16);
+        storage.write(STORAGE_SECTOR_LOW, sector as u16);
+        storage.write(STORAGE_CMD, command);
+        assert_eq!(storage.read(STORAGE_STATUS), STORAGE_BUSY);
+
+        tick(storage, COMMAND_CYCLES - 1);
+        assert_eq!(storage.read(STORAGE_STATUS), STORAGE_BUSY);
+        tick(storage, 1);
+        storage.read(STORAGE_STATUS)
+    }
+
+    #[test]
+    fn test_read_write() {
+        let mut image = vec![0; 2 * SECTOR_BYTES as usize];
+        image[SECTOR_BYTES as usize..][..4].copy_from_slice(&[0x34, 0x12, 0xcd, 0xab]);
+        let mut storage = Storage::from_bytes(image);
+
+        assert_eq!(storage.read(STORAGE_SECTORS_LOW), 2);
+        assert_eq!(command(&mut storage, 1, STORAGE_READ), 0);
+        assert_eq!(storage.read(STORAGE_DATA), 0x1234);
+        assert_eq!(storage.read(STORAGE_DATA), 0xabcd);
+        assert_eq!(storage.read(STORAGE_INDEX), 2);
+
+        storage.write(STORAGE_INDEX, SECTOR_WORDS as u16 - 1);
+        storage.write(STORAGE_DATA, 7);
+        assert_eq!(storage.read(STORAGE_INDEX), 0, "the index wraps around");
+        assert_eq!(command(&mut storage, 0, STORAGE_WRITE), 0);
+        assert_eq!(command(&mut storage, 0, STORAGE_READ), 0);
+
+        storage.write(STORAGE_INDEX, SECTOR_WORDS as u16 - 1);
+        assert_eq!(storage.read(STORAGE_DATA), 7);
+        assert_eq!(storage.read(STORAGE_DATA), 0x1234);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut storage = Storage::from_bytes(vec![0; SECTOR_BYTES as usize]);
+
+        assert_eq!(command(&mut storage, 0x10000, STORAGE_READ), STORAGE_ERROR);
+        storage.write(STORAGE_CMD, 3);
+        assert_eq!(storage.read(STORAGE_STATUS), STORAGE_ERROR);
+        assert_eq!(command(&mut storage, 0, STORAGE_READ), 0);
+    }
+
+    #[test]
+    fn test_busy() {
+        let mut storage = Storage::from_bytes(vec![0; SECTOR_BYTES as usize]);
+        storage.write(STORAGE_CMD, STORAGE_READ);
+        storage.write(STORAGE_CMD, 3);
+
+        assert_eq!(storage.read(STORAGE_STATUS), STORAGE_BUSY);
+    }
+
+    #[test]
+    fn test_image_file() {
+        let path = std::env::temp_dir().join(format!("cpu16-image-{}.img", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        create_image(path, 4).unwrap();
+        write_image(path, 2, &[1, 0, 2, 0, 3]).unwrap();
+        assert!(write_image(path, 3, &[0; SECTOR_BYTES as usize + 1]).is_err());
+
+        let storage = Storage::open(path).unwrap();
+        assert_eq!(storage.sectors(), 4);
+
+        let dump = dump_sector(path, 2).unwrap();
+        assert_eq!(dump.lines().count(), SECTOR_WORDS / 8);
+        assert_eq!(
+            dump.lines().next(),
+            Some("00: 0001 0002 0003 0000 0000 0000 0000 0000")
+        );
+        assert!(dump_sector(path, 4).is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
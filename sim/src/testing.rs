@@ -0,0 +1,395 @@
+use crate::asm::Assembler;
+use crate::beeper::Beeper;
+use crate::bus::{Device, Machine};
+use crate::isa::{Reg, STACK_POINTER_TOP};
+use crate::ppu::Ppu;
+use crate::semihosting::{Semihosting, SEMIHOSTING_BASE, SEMIHOSTING_END};
+use crate::sim::CPU;
+use crate::START_PC;
+use std::path::PathBuf;
+
+const FUEL: usize = 10_000_000;
+
+// xorshift64, deterministic so that failing inputs can be reproduced
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed.max(1))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    pub fn next_u16(&mut self) -> u16 {
+        (self.next_u64() >> 48) as u16
+    }
+
+    // uniform enough for test inputs
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+// assembles `init_sp; setw args; call label; halt` followed by the procedure
+// definitions, lets `init` prepare the CPU and runs it until it halts.
+// returns the CPU along with the number of cycles spent inside the procedure
+pub fn call_with(
+    defs: &[fn(&mut Assembler)],
+    label: &str,
+    args: &[(Reg, u16)],
+    init: impl FnOnce(&mut CPU),
+) -> (CPU, u64) {
+    let mut asm = Assembler::new();
+    asm.init_sp();
+
+    for &(reg, val) in args {
+        asm.setw(reg, val, Reg::TMP);
+    }
+
+    asm.call(label);
+    let halt_addr = START_PC + asm.len() as u16;
+    asm.halt();
+
+    for def in defs {
+        def(&mut asm);
+    }
+
+    let entry_addr = START_PC + asm.label_address(label).expect("undefined procedure") as u16;
+    let mut cpu = CPU::from(&asm.assemble(), START_PC);
+    init(&mut cpu);
+
+    let mut entry_cycles = None;
+
+    for _ in 0..FUEL {
+        let pc = cpu.regs[Reg::PC as usize];
+
+        if pc == entry_addr && entry_cycles.is_none() {
+            entry_cycles = Some(cpu.cycles);
+        }
+
+        if pc == halt_addr {
+            let cycles = cpu.cycles - entry_cycles.unwrap_or(cpu.cycles);
+            cpu.step();
+            return (cpu, cycles);
+        }
+
+        cpu.step();
+        assert!(!cpu.halted, "{label} halted the CPU");
+    }
+
+    panic!("{label} did not return after {FUEL} instructions");
+}
+
+// a CPU loaded with procedure definitions, for tests calling several
+// procedures in a row on the same memory
+pub struct Session {
+    pub cpu: CPU,
+    asm: Assembler,
+}
+
+impl Session {
+    // the program starts with a halt instruction which procedures return to
+    pub fn new(defs: &[fn(&mut Assembler)]) -> Self {
+        let mut asm = Assembler::new();
+        asm.halt();
+
+        for def in defs {
+            def(&mut asm);
+        }
+
+        let cpu = CPU::from(&asm.assemble(), START_PC);
+        Session { cpu, asm }
+    }
+
+    // runs `label` with a fresh stack until it returns,
+    // returns false if it halted the CPU instead
+    pub fn try_call(&mut self, label: &str, args: &[(Reg, u16)]) -> bool {
+        let entry = START_PC + self.asm.label_address(label).expect("undefined procedure") as u16;
+        let cpu = &mut self.cpu;
+
+        for &(reg, val) in args {
+            cpu.regs[reg as usize] = val;
+        }
+
+        cpu.ram[STACK_POINTER_TOP as usize] = START_PC;
+        cpu.regs[Reg::SP as usize] = STACK_POINTER_TOP + 1;
+        cpu.regs[Reg::PC as usize] = entry;
+        cpu.halted = false;
+
+        for _ in 0..FUEL {
+            if cpu.regs[Reg::PC as usize] == START_PC {
+                return true;
+            }
+
+            cpu.step();
+
+            if cpu.halted {
+                return false;
+            }
+        }
+
+        panic!("{label} did not return after {FUEL} instructions");
+    }
+
+    // calls `label` and returns R1
+    pub fn call(&mut self, label: &str, args: &[(Reg, u16)]) -> u16 {
+        assert!(self.try_call(label, args), "{label} halted the CPU");
+        reg(&self.cpu, Reg::R1)
+    }
+}
+
+pub fn call(defs: &[fn(&mut Assembler)], label: &str, args: &[(Reg, u16)]) -> (CPU, u64) {
+    call_with(defs, label, args, |_| {})
+}
+
+// address of a static variable declared by the procedure definitions
+pub fn var_address(defs: &[fn(&mut Assembler)], var: &str) -> usize {
+    let mut asm = Assembler::new();
+
+    for def in defs {
+        def(&mut asm);
+    }
+
+    asm.var_address(var).expect("undeclared static variable") as usize
+}
+
+// runs a program with the semihosting ports mapped,
+// returns its exit code and what it printed
+pub fn run_semihosted(prog: &[u16]) -> (Option<u16>, String) {
+    let mut cpu = CPU::from(prog, START_PC);
+    let mut semihosting = Semihosting::new();
+    semihosting.echo = false;
+    cpu.bus.map(
+        "semihosting",
+        SEMIHOSTING_BASE..=SEMIHOSTING_END,
+        semihosting,
+    );
+
+    assert!(
+        cpu.run_with_fuel(FUEL, false).is_some(),
+        "the program did not halt after {FUEL} instructions"
+    );
+
+    let semihosting = cpu.bus.device::<Semihosting>("semihosting").unwrap();
+    (cpu.exit_code, semihosting.output().to_string())
+}
+
+pub fn reg(cpu: &CPU, reg: Reg) -> u16 {
+    cpu.regs[reg as usize]
+}
+
+// what a device did to the rest of the machine during a tick
+#[derive(Debug, PartialEq, Eq)]
+pub struct Ticked {
+    pub nmi: bool,
+    pub writes: Vec<(u16, u16)>,
+    pub stall: u64,
+}
+
+// ticks a device on its own, with a zeroed RAM
+pub fn tick(device: &mut impl Device, cycles: u64) -> Ticked {
+    tick_with_ram(device, cycles, &mut Box::new([0; 0x10000]))
+}
+
+pub fn tick_with_ram(device: &mut impl Device, cycles: u64, ram: &mut [u16; 0x10000]) -> Ticked {
+    let mut machine = Machine::new(ram);
+    device.tick(cycles, &mut machine);
+
+    Ticked {
+        nmi: machine.nmi,
+        writes: machine.writes,
+        stall: machine.stall,
+    }
+}
+
+// the rows of the PPU tile grid, without trailing blanks
+pub fn screen(cpu: &CPU) -> Vec<String> {
+    let ppu = cpu.bus.device::<Ppu>("ppu").expect("no PPU mapped");
+
+    ppu.text_dump()
+        .lines()
+        .map(|line| line.trim_end().to_string())
+        .collect()
+}
+
+// the screen up to its last non-empty row, with row numbers
+fn show_screen(rows: &[String]) -> String {
+    let len = rows
+        .iter()
+        .rposition(|row| !row.is_empty())
+        .map_or(0, |i| i + 1);
+
+    rows[..len]
+        .iter()
+        .enumerate()
+        .map(|(i, row)| format!("{i:2} |{row}\n"))
+        .collect()
+}
+
+pub fn assert_screen_contains(cpu: &CPU, text: &str) {
+    let rows = screen(cpu);
+
+    assert!(
+        rows.iter().any(|row| row.contains(text)),
+        "{text:?} is not on the screen:\n{}",
+        show_screen(&rows)
+    );
+}
+
+pub fn assert_row_eq(cpu: &CPU, row: usize, expected: &str) {
+    let rows = screen(cpu);
+
+    assert!(
+        rows[row] == expected.trim_end(),
+        "row {row} differs\n  expected: {expected:?}\n    actual: {:?}\nscreen:\n{}",
+        rows[row],
+        show_screen(&rows)
+    );
+}
+
+// compares the screen with the golden file screens/<name>.txt,
+// run the tests with UPDATE_SCREENS=1 to (re)write it
+pub fn assert_screen_snapshot(cpu: &CPU, name: &str) {
+    let rows = screen(cpu);
+    let path: PathBuf = [
+        env!("CARGO_MANIFEST_DIR"),
+        "screens",
+        &format!("{name}.txt"),
+    ]
+    .iter()
+    .collect();
+
+    let mut actual = rows.join("\n");
+    actual.truncate(actual.trim_end().len());
+    actual.push('\n');
+
+    if std::env::var_os("UPDATE_SCREENS").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, &actual).unwrap();
+        return;
+    }
+
+    let expected = std::fs::read_to_string(&path).unwrap_or_else(|_| {
+        panic!(
+            "missing snapshot {}, run with UPDATE_SCREENS=1 to create it, screen:\n{}",
+            path.display(),
+            show_screen(&rows)
+        )
+    });
+
+    if let Some(diff) = screen_diff(&expected, &actual) {
+        panic!("screen differs from {}:\n{diff}", path.display());
+    }
+}
+
+// compares what the beeper played with the golden file audio/<name>.wav,
+// run the tests with UPDATE_AUDIO=1 to (re)write it
+pub fn assert_audio_snapshot(beeper: &Beeper, name: &str) {
+    let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "audio", &format!("{name}.wav")]
+        .iter()
+        .collect();
+
+    if std::env::var_os("UPDATE_AUDIO").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        beeper.save_wav(path.to_str().unwrap()).unwrap();
+        return;
+    }
+
+    let expected = std::fs::read(&path).unwrap_or_else(|_| {
+        panic!(
+            "missing snapshot {}, run with UPDATE_AUDIO=1 to create it",
+            path.display()
+        )
+    });
+    let actual = beeper.to_wav();
+
+    if let Some(i) =
+        (0..expected.len().max(actual.len())).find(|&i| expected.get(i) != actual.get(i))
+    {
+        panic!(
+            "audio differs from {} at byte {i} ({} bytes expected, {} played)",
+            path.display(),
+            expected.len(),
+            actual.len()
+        );
+    }
+}
+
+// the rows that differ, as `-expected` / `+actual` pairs
+fn screen_diff(expected: &str, actual: &str) -> Option<String> {
+    let expected: Vec<&str> = expected.lines().map(str::trim_end).collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    let mut diff = String::new();
+
+    for i in 0..expected.len().max(actual.len()) {
+        let (a, b) = (expected.get(i).copied(), actual.get(i).copied());
+
+        if a.unwrap_or("") != b.unwrap_or("") {
+            diff += &format!("{i:2} -|{}\n   +|{}\n", a.unwrap_or(""), b.unwrap_or(""));
+        }
+    }
+
+    (!diff.is_empty()).then_some(diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Device;
+
+    fn cpu_with_screen(text: &str) -> CPU {
+        let mut cpu = CPU::from(&[], START_PC);
+        let ppu = cpu.bus.device_mut::<Ppu>("ppu").unwrap();
+        ppu.write(0, 0);
+
+        for (i, line) in text.lines().enumerate() {
+            ppu.write(0, (i * crate::ppu::TILES_X) as u16);
+
+            for byte in line.bytes() {
+                ppu.write(1, byte as u16);
+            }
+        }
+
+        cpu
+    }
+
+    #[test]
+    fn test_screen_assertions() {
+        let cpu = cpu_with_screen("hello\n\n  world  ");
+
+        assert_row_eq(&cpu, 0, "hello");
+        assert_row_eq(&cpu, 1, "");
+        assert_row_eq(&cpu, 2, "  world");
+        assert_screen_contains(&cpu, "wor");
+    }
+
+    #[test]
+    #[should_panic(expected = "row 0 differs")]
+    fn test_row_mismatch() {
+        assert_row_eq(&cpu_with_screen("hello"), 0, "hell");
+    }
+
+    #[test]
+    #[should_panic(expected = "\"bye\" is not on the screen:\n 0 |hello\n")]
+    fn test_screen_without_text() {
+        assert_screen_contains(&cpu_with_screen("hello"), "bye");
+    }
+
+    #[test]
+    fn test_screen_diff() {
+        assert_eq!(screen_diff("a\nb  \n", "a\nb\n\n"), None);
+        assert_eq!(
+            screen_diff("a\nb\n", "a\nc\nd\n").unwrap(),
+            " 1 -|b\n   +|c\n 2 -|\n   +|d\n"
+        );
+    }
+}
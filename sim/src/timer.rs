@@ -0,0 +1,206 @@
+use crate::bus::{CycleCounter, Device, Machine};
+use crate::isa::IO_PAGE;
+
+pub const TIMER_BASE: u16 = IO_PAGE + 0x20;
+pub const TIMER_END: u16 = TIMER_BASE + TIMER_CYCLES_LOW;
+
+// registers, relative to TIMER_BASE
+pub const TIMER_CTRL: u16 = 0;
+// writing 1 to a bit clears it
+pub const TIMER_STATUS: u16 = 1;
+// the number of ticks between two expiries, 0 means 0x10000
+pub const TIMER_RELOAD: u16 = 2;
+// the timer ticks once every `prescaler + 1` cycles
+pub const TIMER_PRESCALER: u16 = 3;
+// ticks left before the next expiry, writing restarts the count from that value
+pub const TIMER_COUNT: u16 = 4;
+// the free-running 64-bit cycle counter, high word first:
+// reading the high word latches the whole counter
+pub const TIMER_CYCLES: u16 = 5;
+pub const TIMER_CYCLES_LOW: u16 = TIMER_CYCLES + 3;
+
+// control bits
+pub const TIMER_ENABLE: u16 = 1 << 0;
+// raise an NMI on expiry
+pub const TIMER_NMI: u16 = 1 << 1;
+// clear TIMER_ENABLE on expiry instead of reloading
+pub const TIMER_ONE_SHOT: u16 = 1 << 2;
+
+// status bits
+pub const TIMER_EXPIRED: u16 = 1 << 0;
+// the timer expired again before TIMER_EXPIRED was cleared
+pub const TIMER_MISSED: u16 = 1 << 1;
+
+// A down-counting timer. Enabling it loads the count from the reload value,
+// it then expires every `reload * (prescaler + 1)` cycles.
+#[derive(Default)]
+pub struct Timer {
+    ctrl: u16,
+    status: u16,
+    reload: u16,
+    prescaler: u16,
+    // 1..=0x10000 while enabled
+    count: u32,
+    // cycles since the last tick
+    prescaled: u64,
+    cycles: CycleCounter,
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Timer::default()
+    }
+
+    fn period(&self) -> u32 {
+        match self.reload {
+            0 => 0x10000,
+            reload => reload as u32,
+        }
+    }
+
+    fn restart(&mut self, count: u32) {
+        self.count = count;
+        self.prescaled = 0;
+    }
+
+    fn expire(&mut self, machine: &mut Machine) {
+        if self.status & TIMER_EXPIRED != 0 {
+            self.status |= TIMER_MISSED;
+        }
+
+        self.status |= TIMER_EXPIRED;
+
+        if self.ctrl & TIMER_NMI != 0 {
+            machine.raise_nmi();
+        }
+
+        if self.ctrl & TIMER_ONE_SHOT != 0 {
+            self.ctrl &= !TIMER_ENABLE;
+        }
+
+        self.count = self.period();
+    }
+}
+
+impl Device for Timer {
+    fn read(&mut self, offset: u16) -> u16 {
+        match offset {
+            TIMER_CTRL => self.ctrl,
+            TIMER_STATUS => self.status,
+            TIMER_RELOAD => self.reload,
+            TIMER_PRESCALER => self.prescaler,
+            TIMER_COUNT => self.count as u16,
+            TIMER_CYCLES..=TIMER_CYCLES_LOW => self.cycles.read(offset - TIMER_CYCLES),
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset: u16, val: u16) {
+        match offset {
+            TIMER_CTRL => {
+                if self.ctrl & TIMER_ENABLE == 0 && val & TIMER_ENABLE != 0 {
+                    self.restart(self.period());
+                }
+
+                self.ctrl = val & (TIMER_ENABLE | TIMER_NMI | TIMER_ONE_SHOT);
+            }
+            TIMER_STATUS => self.status &= !val,
+            TIMER_RELOAD => self.reload = val,
+            TIMER_PRESCALER => self.prescaler = val,
+            TIMER_COUNT => self.restart(if val == 0 { 0x10000 } else { val as u32 }),
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self, cycles: u64, machine: &mut Machine) {
+        self.cycles.tick(cycles);
+
+        if self.ctrl & TIMER_ENABLE == 0 {
+            return;
+        }
+
+        let cycles_per_tick = self.prescaler as u64 + 1;
+        self.prescaled += cycles;
+        let mut ticks = self.prescaled / cycles_per_tick;
+        self.prescaled %= cycles_per_tick;
+
+        while ticks > 0 && self.ctrl & TIMER_ENABLE != 0 {
+            if ticks < self.count as u64 {
+                self.count -= ticks as u32;
+                break;
+            }
+
+            ticks -= self.count as u64;
+            self.expire(machine);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::tick;
+
+    #[test]
+    fn test_periodic() {
+        let mut timer = Timer::new();
+        timer.write(TIMER_RELOAD, 3);
+        timer.write(TIMER_PRESCALER, 1);
+        timer.write(TIMER_CTRL, TIMER_ENABLE | TIMER_NMI);
+
+        assert!(!tick(&mut timer, 5).nmi);
+        assert_eq!(timer.read(TIMER_COUNT), 1);
+        assert!(tick(&mut timer, 1).nmi);
+        assert_eq!(timer.read(TIMER_STATUS), TIMER_EXPIRED);
+        assert_eq!(timer.read(TIMER_COUNT), 3);
+
+        // two expiries in a single tick
+        assert!(tick(&mut timer, 12).nmi);
+        assert_eq!(timer.read(TIMER_STATUS), TIMER_EXPIRED | TIMER_MISSED);
+
+        timer.write(TIMER_STATUS, TIMER_EXPIRED | TIMER_MISSED);
+        assert_eq!(timer.read(TIMER_STATUS), 0);
+        assert_ne!(timer.read(TIMER_CTRL) & TIMER_ENABLE, 0);
+    }
+
+    #[test]
+    fn test_one_shot_without_nmi() {
+        let mut timer = Timer::new();
+        timer.write(TIMER_RELOAD, 4);
+        timer.write(TIMER_CTRL, TIMER_ENABLE | TIMER_ONE_SHOT);
+
+        assert!(!tick(&mut timer, 10).nmi);
+        assert_eq!(timer.read(TIMER_STATUS), TIMER_EXPIRED);
+        assert_eq!(timer.read(TIMER_CTRL), TIMER_ONE_SHOT);
+
+        timer.write(TIMER_STATUS, TIMER_EXPIRED);
+        assert!(!tick(&mut timer, 10).nmi);
+        assert_eq!(timer.read(TIMER_STATUS), 0);
+    }
+
+    #[test]
+    fn test_disabled() {
+        let mut timer = Timer::new();
+        timer.write(TIMER_RELOAD, 1);
+        timer.write(TIMER_CTRL, TIMER_NMI);
+
+        assert!(!tick(&mut timer, 100).nmi);
+        assert_eq!(timer.read(TIMER_STATUS), 0);
+
+        // the cycle counter runs anyway
+        assert_eq!(timer.read(TIMER_CYCLES), 0);
+        assert_eq!(timer.read(TIMER_CYCLES_LOW), 100);
+    }
+
+    #[test]
+    fn test_write_count() {
+        let mut timer = Timer::new();
+        timer.write(TIMER_RELOAD, 100);
+        timer.write(TIMER_CTRL, TIMER_ENABLE);
+        timer.write(TIMER_COUNT, 2);
+
+        assert!(!tick(&mut timer, 2).nmi);
+        assert_eq!(timer.read(TIMER_STATUS), TIMER_EXPIRED);
+        assert_eq!(timer.read(TIMER_COUNT), 100);
+    }
+}
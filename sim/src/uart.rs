@@ -0,0 +1,377 @@
+use crate::bus::{Device, Machine};
+use crate::isa::{CLOCK_HZ, IO_PAGE};
+use std::collections::VecDeque;
+use std::ffi::CStr;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::FromRawFd;
+use std::sync::mpsc;
+
+pub const UART_BASE: u16 = IO_PAGE;
+pub const UART_END: u16 = UART_BASE + 3;
+
+// registers, relative to UART_BASE
+pub const UART_STATUS: u16 = 0;
+// reading pops a received byte (0 if there is none), writing queues a byte to send
+pub const UART_DATA: u16 = 1;
+pub const UART_RX_COUNT: u16 = 2;
+pub const UART_TX_FREE: u16 = 3;
+
+// status bits
+pub const UART_RX_READY: u16 = 1 << 0;
+pub const UART_TX_READY: u16 = 1 << 1;
+// the transmit FIFO is empty and the last byte has been sent
+pub const UART_TX_IDLE: u16 = 1 << 2;
+// a byte was received while the receive FIFO was full, cleared by reading the status
+pub const UART_OVERRUN: u16 = 1 << 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UartConfig {
+    pub fifo_depth: usize,
+    // time to send or receive a byte
+    pub cycles_per_byte: u64,
+}
+
+impl UartConfig {
+    // 8N1: a start bit, 8 data bits and a stop bit per byte
+    pub fn baud(fifo_depth: usize, baud_rate: u64) -> Self {
+        UartConfig {
+            fifo_depth,
+            cycles_per_byte: (10 * CLOCK_HZ).div_ceil(baud_rate),
+        }
+    }
+}
+
+impl Default for UartConfig {
+    // the baud rate of the programmer
+    fn default() -> Self {
+        UartConfig::baud(16, 921_600)
+    }
+}
+
+// A serial port of the simulator. Bytes written by the host (see `send`)
+// are received one every `cycles_per_byte` cycles, and bytes written by the
+// program are sent to the host at the same rate.
+pub struct Uart {
+    config: UartConfig,
+    rx: VecDeque<u8>,
+    tx: VecDeque<u8>,
+    rx_cycles: u64,
+    tx_cycles: u64,
+    overrun: bool,
+    // bytes sent by the host which have not reached the receiver yet
+    host_tx: VecDeque<u8>,
+    input: Option<mpsc::Receiver<u8>>,
+    output: Option<Box<dyn Write>>,
+    // everything the program sent
+    sent: Vec<u8>,
+    // keeps the slave side of a pseudo-terminal open
+    _pty_slave: Option<File>,
+}
+
+impl Uart {
+    // a UART without a host, see `send` and `sent`
+    pub fn new(config: UartConfig) -> Self {
+        Uart {
+            config,
+            rx: VecDeque::new(),
+            tx: VecDeque::new(),
+            rx_cycles: 0,
+            tx_cycles: 0,
+            overrun: false,
+            host_tx: VecDeque::new(),
+            input: None,
+            output: None,
+            sent: Vec::new(),
+            _pty_slave: None,
+        }
+    }
+
+    // bridges the UART to a host (files, a child process, ...),
+    // `input` is read from a separate thread
+    pub fn connect(
+        mut self,
+        input: impl Read + Send + 'static,
+        output: impl Write + 'static,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel();
+
+        std::thread::spawn(move || {
+            let mut input = input;
+            let mut buffer = [0; 256];
+
+            while let Ok(len @ 1..) = input.read(&mut buffer) {
+                if buffer[..len].iter().any(|&byte| sender.send(byte).is_err()) {
+                    break;
+                }
+            }
+        });
+
+        self.input = Some(receiver);
+        self.output = Some(Box::new(output));
+        self
+    }
+
+    pub fn stdio(config: UartConfig) -> Self {
+        Uart::new(config).connect(io::stdin(), io::stdout())
+    }
+
+    // a pseudo-terminal, returns the path of the terminal to connect to
+    pub fn pty(config: UartConfig) -> io::Result<(Self, String)> {
+        let (master, slave, path) = open_pty()?;
+        let mut uart = Uart::new(config).connect(master.try_clone()?, master);
+        uart._pty_slave = Some(slave);
+
+        Ok((uart, path))
+    }
+
+    // bytes sent by the host
+    pub fn send(&mut self, bytes: &[u8]) {
+        self.host_tx.extend(bytes);
+    }
+
+    // bytes sent by the program so far
+    pub fn sent(&self) -> &[u8] {
+        &self.sent
+    }
+
+    // sends the bytes left in the transmit FIFO right away,
+    // for programs which halt before they are out
+    pub fn flush(&mut self) {
+        while let Some(byte) = self.tx.pop_front() {
+            self.transmit(byte);
+        }
+    }
+
+    fn transmit(&mut self, byte: u8) {
+        self.sent.push(byte);
+
+        if let Some(output) = &mut self.output {
+            // the host going away does not concern the program
+            let _ = output.write_all(&[byte]).and_then(|_| output.flush());
+        }
+    }
+
+    fn status(&mut self) -> u16 {
+        let mut status = 0;
+
+        if !self.rx.is_empty() {
+            status |= UART_RX_READY;
+        }
+
+        if self.tx.len() < self.config.fifo_depth {
+            status |= UART_TX_READY;
+        }
+
+        if self.tx.is_empty() {
+            status |= UART_TX_IDLE;
+        }
+
+        if std::mem::take(&mut self.overrun) {
+            status |= UART_OVERRUN;
+        }
+
+        status
+    }
+}
+
+impl Device for Uart {
+    fn read(&mut self, offset: u16) -> u16 {
+        match offset {
+            UART_STATUS => self.status(),
+            UART_DATA => self.rx.pop_front().unwrap_or(0) as u16,
+            UART_RX_COUNT => self.rx.len() as u16,
+            UART_TX_FREE => (self.config.fifo_depth - self.tx.len()) as u16,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset: u16, val: u16) {
+        // like most UARTs, writing to a full FIFO drops the byte
+        if offset == UART_DATA && self.tx.len() < self.config.fifo_depth {
+            self.tx.push_back(val as u8);
+        }
+    }
+
+    fn tick(&mut self, cycles: u64, _machine: &mut Machine) {
+        if let Some(input) = &self.input {
+            self.host_tx.extend(input.try_iter());
+        }
+
+        let per_byte = self.config.cycles_per_byte;
+
+        // an idle line starts shifting the next byte as soon as it comes
+        if self.host_tx.is_empty() {
+            self.rx_cycles = 0;
+        } else {
+            self.rx_cycles += cycles;
+
+            while self.rx_cycles >= per_byte {
+                let Some(byte) = self.host_tx.pop_front() else {
+                    break;
+                };
+
+                self.rx_cycles -= per_byte;
+
+                if self.rx.len() < self.config.fifo_depth {
+                    self.rx.push_back(byte);
+                } else {
+                    self.overrun = true;
+                }
+            }
+        }
+
+        if self.tx.is_empty() {
+            self.tx_cycles = 0;
+        } else {
+            self.tx_cycles += cycles;
+
+            while self.tx_cycles >= per_byte {
+                let Some(byte) = self.tx.pop_front() else {
+                    break;
+                };
+
+                self.tx_cycles -= per_byte;
+                self.transmit(byte);
+            }
+        }
+    }
+}
+
+// a pseudo-terminal pair: (master, slave, path of the slave)
+pub fn open_pty() -> io::Result<(File, File, String)> {
+    // SAFETY: openpty initializes both file descriptors on success,
+    // which are then owned by the returned files
+    unsafe {
+        let (mut master, mut slave) = (0, 0);
+        let res = libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            std::ptr::null(),
+        );
+
+        if res != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let (master, slave) = (File::from_raw_fd(master), File::from_raw_fd(slave));
+        let mut path = [0; 128];
+
+        if libc::ttyname_r(
+            std::os::fd::AsRawFd::as_raw_fd(&slave),
+            path.as_mut_ptr(),
+            path.len(),
+        ) != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+
+        let path = CStr::from_ptr(path.as_ptr()).to_string_lossy().into_owned();
+        Ok((master, slave, path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::tick;
+
+    fn uart(fifo_depth: usize) -> Uart {
+        Uart::new(UartConfig {
+            fifo_depth,
+            cycles_per_byte: 10,
+        })
+    }
+
+    #[test]
+    fn test_baud_rate() {
+        assert_eq!(UartConfig::default().cycles_per_byte, 1086);
+        assert_eq!(UartConfig::baud(1, 9600).cycles_per_byte, 104_167);
+    }
+
+    #[test]
+    fn test_receive() {
+        let mut uart = uart(2);
+        uart.send(b"abc");
+        assert_eq!(uart.read(UART_STATUS) & UART_RX_READY, 0);
+
+        tick(&mut uart, 9);
+        assert_eq!(uart.read(UART_RX_COUNT), 0);
+        tick(&mut uart, 1);
+        assert_eq!(uart.read(UART_RX_COUNT), 1);
+
+        // the third byte does not fit in the FIFO
+        tick(&mut uart, 25);
+        assert_eq!(
+            uart.read(UART_STATUS),
+            UART_RX_READY | UART_TX_READY | UART_TX_IDLE | UART_OVERRUN
+        );
+        assert_eq!(uart.read(UART_STATUS) & UART_OVERRUN, 0);
+
+        assert_eq!(uart.read(UART_DATA), b'a' as u16);
+        assert_eq!(uart.read(UART_DATA), b'b' as u16);
+        assert_eq!(uart.read(UART_DATA), 0);
+        assert_eq!(uart.read(UART_STATUS) & UART_RX_READY, 0);
+    }
+
+    #[test]
+    fn test_transmit() {
+        let mut uart = uart(2);
+
+        for byte in b"xyz" {
+            uart.write(UART_DATA, *byte as u16);
+        }
+
+        assert_eq!(uart.read(UART_TX_FREE), 0);
+        assert_eq!(uart.read(UART_STATUS) & (UART_TX_READY | UART_TX_IDLE), 0);
+
+        tick(&mut uart, 15);
+        assert_eq!(uart.sent(), b"x");
+        assert_eq!(uart.read(UART_TX_FREE), 1);
+
+        tick(&mut uart, 5);
+        assert_eq!(uart.sent(), b"xy", "z was dropped");
+        assert_ne!(uart.read(UART_STATUS) & UART_TX_IDLE, 0);
+
+        uart.write(UART_DATA, b'!' as u16);
+        uart.flush();
+        assert_eq!(uart.sent(), b"xy!");
+    }
+
+    #[test]
+    fn test_pty() {
+        let (mut uart, path) = Uart::pty(UartConfig {
+            fifo_depth: 16,
+            cycles_per_byte: 1,
+        })
+        .unwrap();
+
+        let mut terminal = crate::upload::open_serial(&path, 9600).unwrap();
+        terminal.write_all(b"ping").unwrap();
+        uart.write(UART_DATA, b'!' as u16);
+
+        let mut received = Vec::new();
+
+        for _ in 0..1000 {
+            tick(&mut uart, 1);
+
+            while uart.read(UART_STATUS) & UART_RX_READY != 0 {
+                received.push(uart.read(UART_DATA) as u8);
+            }
+
+            if received.len() == 4 {
+                break;
+            }
+
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+
+        assert_eq!(received, b"ping");
+
+        let mut reply = [0];
+        terminal.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"!");
+    }
+}
@@ -0,0 +1,533 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+use crate::procedures::hash::{crc16, word_bytes};
+
+pub const BAUD_RATE: u32 = 921_600;
+pub const DEFAULT_PORT: &str = "/dev/ttyUSB2";
+
+// prog.py writes the file in chunks of this size
+const CHUNK_SIZE: usize = 256;
+
+// The byte stream of the program mode of Top.veryl: every word of the image,
+// starting at address 0, high byte first.
+// Binaries written by `dump_bin` store words low byte first, so they
+// must not be sent as is.
+pub fn upload_stream(image: &[u16]) -> Vec<u8> {
+    image.iter().flat_map(|word| word.to_be_bytes()).collect()
+}
+
+// opens a serial port in raw mode, 8N1 without flow control,
+// reads time out instead of blocking forever
+pub fn open_serial(path: impl AsRef<Path>, baud_rate: u32) -> io::Result<File> {
+    let port = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(path)?;
+
+    let speed = match baud_rate {
+        9600 => libc::B9600,
+        115_200 => libc::B115200,
+        921_600 => libc::B921600,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unsupported baud rate {baud_rate}"),
+            ))
+        }
+    };
+
+    // SAFETY: the termios struct is initialized by tcgetattr before being used
+    unsafe {
+        let mut termios = std::mem::zeroed::<libc::termios>();
+
+        if libc::tcgetattr(port.as_raw_fd(), &mut termios) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        libc::cfmakeraw(&mut termios);
+        // reads return 0 bytes after 0.5s without data, see `upload_framed`
+        termios.c_cc[libc::VMIN] = 0;
+        termios.c_cc[libc::VTIME] = 5;
+        termios.c_cflag |= libc::CLOCAL | libc::CREAD;
+        termios.c_cflag &= !(libc::CSTOPB | libc::CRTSCTS);
+        libc::cfsetispeed(&mut termios, speed);
+        libc::cfsetospeed(&mut termios, speed);
+
+        if libc::tcsetattr(port.as_raw_fd(), libc::TCSANOW, &termios) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(port)
+}
+
+// sends a whole image, `progress` is called with the number of bytes sent so far
+pub fn upload(
+    port: &mut impl Write,
+    image: &[u16],
+    mut progress: impl FnMut(usize),
+) -> io::Result<()> {
+    let stream = upload_stream(image);
+
+    for (i, chunk) in stream.chunks(CHUNK_SIZE).enumerate() {
+        port.write_all(chunk)?;
+        progress(i * CHUNK_SIZE + chunk.len());
+    }
+
+    port.flush()
+}
+
+// The framed protocol, for uploads that survive dropped or corrupted bytes.
+// Every frame is
+//     SYNC, command, address, length, payload, CRC
+// where the address and the length (in words) are 16 bits, the payload holds
+// `length` words for a write and nothing for a verify, and the CRC is
+// `crc16` of everything after the sync byte. Every field is big-endian.
+// The receiver replies with NAK to a corrupt frame, otherwise with
+//     ACK, command, address
+// followed by the CRC of the words now in RAM for a verify.
+pub const SYNC: u8 = 0xa5;
+pub const ACK: u8 = 0x06;
+pub const NAK: u8 = 0x15;
+pub const WRITE: u8 = b'W';
+pub const VERIFY: u8 = b'V';
+pub const MAX_FRAME_WORDS: usize = 256;
+
+const HEADER_LEN: usize = 6;
+const MAX_FRAME_LEN: usize = HEADER_LEN + 2 * MAX_FRAME_WORDS + 2;
+const MAX_ATTEMPTS: usize = 8;
+
+pub fn frame(command: u8, addr: u16, len: usize, payload: &[u16]) -> Vec<u8> {
+    let mut frame = vec![SYNC, command];
+    frame.extend(addr.to_be_bytes());
+    frame.extend((len as u16).to_be_bytes());
+    frame.extend(word_bytes(payload));
+
+    let crc = crc16(&frame[1..]);
+    frame.extend(crc.to_be_bytes());
+    frame
+}
+
+enum Reply {
+    Ack(Vec<u8>),
+    Nak,
+    Timeout,
+}
+
+// None if the read timed out
+fn read_byte(port: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+
+    loop {
+        return match port.read(&mut byte) {
+            Ok(0) => Ok(None),
+            Ok(_) => Ok(Some(byte[0])),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        };
+    }
+}
+
+// waits for the reply to the frame at `addr`, skipping replies to earlier frames
+fn read_reply(port: &mut impl Read, command: u8, addr: u16, data_len: usize) -> io::Result<Reply> {
+    loop {
+        match read_byte(port)? {
+            None => return Ok(Reply::Timeout),
+            Some(NAK) => return Ok(Reply::Nak),
+            Some(ACK) => {
+                let mut reply = Vec::with_capacity(3 + data_len);
+
+                while reply.len() < 3 + data_len {
+                    match read_byte(port)? {
+                        Some(byte) => reply.push(byte),
+                        None => return Ok(Reply::Timeout),
+                    }
+                }
+
+                if reply[0] == command && reply[1..3] == addr.to_be_bytes() {
+                    return Ok(Reply::Ack(reply.split_off(3)));
+                }
+            }
+            Some(_) => {}
+        }
+    }
+}
+
+// sends a frame until it is acknowledged, returns the data of the reply
+fn transact<P: Read + Write>(
+    port: &mut P,
+    frame: &[u8],
+    addr: u16,
+    data_len: usize,
+) -> io::Result<Vec<u8>> {
+    let command = frame[1];
+
+    for _ in 0..MAX_ATTEMPTS {
+        port.write_all(frame)?;
+        port.flush()?;
+
+        match read_reply(port, command, addr, data_len)? {
+            Reply::Ack(data) => return Ok(data),
+            Reply::Nak => {}
+            Reply::Timeout => {
+                // a byte went missing and the receiver is waiting for the end
+                // of the frame: zeros complete it (it gets NAKed), and are
+                // then skipped while looking for the next sync byte
+                port.write_all(&[0; MAX_FRAME_LEN])?;
+                port.flush()?;
+                while read_byte(port)?.is_some() {}
+            }
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!("no acknowledgement for the frame at {addr:#06x} after {MAX_ATTEMPTS} attempts"),
+    ))
+}
+
+// writes `words` at `addr` with the framed protocol, then reads them back,
+// `progress` is called with the number of words written so far
+pub fn upload_framed<P: Read + Write>(
+    port: &mut P,
+    addr: u16,
+    words: &[u16],
+    mut progress: impl FnMut(usize),
+) -> io::Result<()> {
+    let mut sent = 0;
+
+    for chunk in words.chunks(MAX_FRAME_WORDS) {
+        let frame_addr = addr.wrapping_add(sent as u16);
+        transact(
+            port,
+            &frame(WRITE, frame_addr, chunk.len(), chunk),
+            frame_addr,
+            0,
+        )?;
+        sent += chunk.len();
+        progress(sent);
+    }
+
+    verify(port, addr, words)
+}
+
+// compares the CRC of every frame of `words` with the one of the RAM of the board
+pub fn verify<P: Read + Write>(port: &mut P, addr: u16, words: &[u16]) -> io::Result<()> {
+    for (i, chunk) in words.chunks(MAX_FRAME_WORDS).enumerate() {
+        let frame_addr = addr.wrapping_add((i * MAX_FRAME_WORDS) as u16);
+        let reply = transact(
+            port,
+            &frame(VERIFY, frame_addr, chunk.len(), &[]),
+            frame_addr,
+            2,
+        )?;
+
+        if u16::from_be_bytes([reply[0], reply[1]]) != crc16(&word_bytes(chunk)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("verification failed for the frame at {frame_addr:#06x}"),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+// The reference implementation of the receiving end of the framed protocol.
+#[derive(Default)]
+pub struct Receiver {
+    frame: Vec<u8>,
+}
+
+impl Receiver {
+    pub fn new() -> Self {
+        Receiver::default()
+    }
+
+    // returns the reply once a frame is complete
+    pub fn receive(&mut self, byte: u8, ram: &mut [u16]) -> Vec<u8> {
+        // look for the start of a frame
+        if self.frame.is_empty() && byte != SYNC {
+            return Vec::new();
+        }
+
+        self.frame.push(byte);
+
+        if self.frame.len() < HEADER_LEN {
+            return Vec::new();
+        }
+
+        let command = self.frame[1];
+        let addr = u16::from_be_bytes([self.frame[2], self.frame[3]]);
+        let len = u16::from_be_bytes([self.frame[4], self.frame[5]]) as usize;
+        let payload_len = match command {
+            WRITE => 2 * len,
+            VERIFY => 0,
+            _ => usize::MAX,
+        };
+
+        // a corrupt header, the sender times out and retransmits
+        if payload_len == usize::MAX || len > MAX_FRAME_WORDS {
+            self.frame.clear();
+            return Vec::new();
+        }
+
+        if self.frame.len() < HEADER_LEN + payload_len + 2 {
+            return Vec::new();
+        }
+
+        let frame = std::mem::take(&mut self.frame);
+        let (body, crc) = frame[1..].split_at(frame.len() - 3);
+
+        if crc16(body) != u16::from_be_bytes([crc[0], crc[1]]) {
+            return vec![NAK];
+        }
+
+        let addresses = (0..len).map(|i| addr.wrapping_add(i as u16) as usize);
+        let mut reply = vec![ACK, command, frame[2], frame[3]];
+
+        if command == WRITE {
+            for (i, addr) in addresses.enumerate() {
+                ram[addr] = u16::from_be_bytes([body[5 + 2 * i], body[6 + 2 * i]]);
+            }
+        } else {
+            let words: Vec<u16> = addresses.map(|addr| ram[addr]).collect();
+            reply.extend(crc16(&word_bytes(&words)).to_be_bytes());
+        }
+
+        reply
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::{Board, StatusLed};
+    use crate::sim::CPU;
+    use crate::testing::Rng;
+    use crate::START_PC;
+
+    fn program_mode() -> Board {
+        let mut board = Board::new(CPU::from(&[], START_PC));
+        board.set_switch(0, true);
+        board
+    }
+
+    #[test]
+    fn test_byte_order() {
+        assert_eq!(upload_stream(&[0x1234, 0xabcd]), [0x12, 0x34, 0xab, 0xcd]);
+    }
+
+    // a pseudo-terminal pair (master, slave path), the slave stands for the USB serial port
+    fn pty() -> (File, String) {
+        let (master, _, path) = crate::uart::open_pty().unwrap();
+        (master, path)
+    }
+
+    #[test]
+    fn test_upload_over_pty() {
+        let mut rng = Rng::new(41);
+        let image: Vec<u16> = (0..0x10000).map(|_| rng.next_u16()).collect();
+        let (mut master, slave) = pty();
+
+        let mut port = open_serial(&slave, BAUD_RATE).unwrap();
+        let uploader = std::thread::spawn({
+            let image = image.clone();
+            move || {
+                let mut sent = 0;
+                upload(&mut port, &image, |n| sent = n).unwrap();
+                sent
+            }
+        });
+
+        // the last word is never read, the upload ends once the program
+        // address reaches 0xffff
+        let mut board = program_mode();
+        let received = board.receive_from(&mut master).unwrap();
+        assert!(received >= 2 * 0xffff);
+
+        assert_eq!(uploader.join().unwrap(), 2 * image.len());
+
+        assert_eq!(&board.cpu.ram[..0xffff], &image[..0xffff]);
+        assert_eq!(board.leds(), 0xffff);
+        assert_eq!(
+            board.status_led(),
+            StatusLed {
+                green: true,
+                ..StatusLed::default()
+            }
+        );
+    }
+
+    // a serial link to a receiver, reads time out when no reply is pending.
+    // `fault` can drop (None) or alter the n-th byte sent
+    struct Link<F: FnMut(usize, u8) -> Option<u8>> {
+        receiver: Receiver,
+        ram: Vec<u16>,
+        replies: std::collections::VecDeque<u8>,
+        sent: usize,
+        fault: F,
+    }
+
+    impl<F: FnMut(usize, u8) -> Option<u8>> Link<F> {
+        fn new(fault: F) -> Self {
+            Link {
+                receiver: Receiver::new(),
+                ram: vec![0; 0x10000],
+                replies: Default::default(),
+                sent: 0,
+                fault,
+            }
+        }
+    }
+
+    impl<F: FnMut(usize, u8) -> Option<u8>> Write for Link<F> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            for &byte in buf {
+                if let Some(byte) = (self.fault)(self.sent, byte) {
+                    let reply = self.receiver.receive(byte, &mut self.ram);
+                    self.replies.extend(reply);
+                }
+
+                self.sent += 1;
+            }
+
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<F: FnMut(usize, u8) -> Option<u8>> Read for Link<F> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(self.replies.len());
+
+            for (dst, src) in buf.iter_mut().zip(self.replies.drain(..len)) {
+                *dst = src;
+            }
+
+            Ok(len)
+        }
+    }
+
+    fn random_words(seed: u64, len: usize) -> Vec<u16> {
+        let mut rng = Rng::new(seed);
+        (0..len).map(|_| rng.next_u16()).collect()
+    }
+
+    #[test]
+    fn test_framed_upload() {
+        let words = random_words(42, 1000);
+        let mut link = Link::new(|_, byte| Some(byte));
+        let mut progress = Vec::new();
+
+        upload_framed(&mut link, START_PC, &words, |n| progress.push(n)).unwrap();
+
+        assert_eq!(&link.ram[START_PC as usize..][..words.len()], &words);
+        assert_eq!(progress, [256, 512, 768, 1000]);
+        // 4 write and 4 verify frames, without retransmissions
+        assert_eq!(link.sent, 8 * 8 + 2 * words.len());
+    }
+
+    #[test]
+    fn test_framed_upload_with_faults() {
+        let words = random_words(42, 1000);
+        // a dropped byte in the first frame, then corrupted bytes further in
+        // the stream, which land in a header or a payload once it is shifted
+        // by the retransmissions
+        let mut link = Link::new(|n, byte| match n {
+            100 => None,
+            3000 | 4000 | 4001 => Some(byte ^ 0x10),
+            _ => Some(byte),
+        });
+
+        upload_framed(&mut link, 0, &words, |_| {}).unwrap();
+        assert_eq!(&link.ram[..words.len()], &words);
+        assert!(link.sent > 8 * 8 + 2 * words.len());
+    }
+
+    #[test]
+    fn test_receiver_resync() {
+        let mut receiver = Receiver::new();
+        let mut ram = vec![0; 0x10000];
+        let mut receive = |bytes: &[u8]| -> Vec<u8> {
+            bytes
+                .iter()
+                .flat_map(|&byte| receiver.receive(byte, &mut ram))
+                .collect()
+        };
+
+        // noise, an unknown command and an oversized frame are skipped silently
+        assert!(receive(&[0, 1, 2]).is_empty());
+        assert!(receive(&frame(b'X', 0, 1, &[7])).is_empty());
+        assert!(receive(&frame(WRITE, 0, MAX_FRAME_WORDS + 1, &[])).is_empty());
+
+        let mut corrupt = frame(WRITE, 0x10, 1, &[7]);
+        corrupt[6] ^= 1;
+        assert_eq!(receive(&corrupt), [NAK]);
+
+        assert_eq!(receive(&frame(WRITE, 0x10, 1, &[7])), [ACK, WRITE, 0, 0x10]);
+        let crc = crc16(&[0, 7]).to_be_bytes();
+        assert_eq!(
+            receive(&frame(VERIFY, 0x10, 1, &[])),
+            [ACK, VERIFY, 0, 0x10, crc[0], crc[1]]
+        );
+        assert_eq!(ram[0x10], 7);
+    }
+
+    #[test]
+    fn test_verify_mismatch() {
+        let words = random_words(42, 300);
+        let mut link = Link::new(|_, byte| Some(byte));
+
+        upload_framed(&mut link, 0, &words, |_| {}).unwrap();
+        link.ram[260] ^= 1;
+
+        let err = verify(&mut link, 0, &words).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("0x0100"));
+    }
+
+    #[test]
+    fn test_dead_link() {
+        let mut link = Link::new(|_, _| None);
+        let err = upload_framed(&mut link, 0, &[1, 2, 3], |_| {}).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn test_framed_upload_over_pty() {
+        let words = random_words(43, 2000);
+        let (mut master, slave) = pty();
+
+        // the board end of the link, until the uploader closes the port
+        let board = std::thread::spawn(move || {
+            let mut board = Board::framed(CPU::from(&[], START_PC));
+            board.set_switch(0, true);
+            board.receive_from(&mut master).unwrap();
+            board.cpu.ram
+        });
+
+        let mut port = open_serial(&slave, BAUD_RATE).unwrap();
+        upload_framed(&mut port, 0x8000, &words, |_| {}).unwrap();
+        drop(port);
+
+        assert_eq!(&board.join().unwrap()[0x8000..][..words.len()], &words);
+    }
+}